@@ -1,18 +1,33 @@
 #![warn(clippy::all)]
 
+//! Informazioni sui video di RaiPlay e download delle varianti della loro
+//! playlist HLS.
+
+use crate::auth;
+use crate::cancel::CancelToken;
+use crate::crypto::{self, UnsupportedEncryptionError};
+use crate::error::Error;
+use crate::http;
+use crate::journal::SegmentJournal;
 use crate::models::video;
-use console::style;
-use failure::{Error, Fail};
-use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use crate::progress::{ProgressEvent, ProgressReporter, Resource};
+use crate::remux::Metadata;
+use crate::retry::{self, RetryPolicy};
+use crate::subtitles;
+use futures::stream::{self, StreamExt};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 /// URL di esempio a video su RaiPlay.
-#[allow(dead_code)]
-pub const RAI_PLAY_EXAMPLE_URLS: [&'static str; 6] = [
+pub const RAI_PLAY_EXAMPLE_URLS: [&str; 6] = [
     "https://www.raiplay.it/video/2019/10/ulisse-il-piacere-della-scoperta-il-gattopardo---il-romanzo-della-sicilia-cbbcfc7a-c25c-476c-b396-a744aa1fe457.html",
     "https://www.raiplay.it/video/2020/02/sanremo-2020-vince-diodato-e2488135-54c5-4776-b846-af9d7c386e83.html",
     "https://www.raiplay.it/video/2020/02/sanremo-2020-serata-finale-francesco-gabbani-viceversa-37c8448b-824a-48e2-883f-dafaf972bc23.html",
@@ -21,19 +36,89 @@ pub const RAI_PLAY_EXAMPLE_URLS: [&'static str; 6] = [
     "https://www.raiplay.it/video/2018/03/Nati-per-sopravvivere-E1-ada6827d-0551-4d2b-969b-acc6eb9cbda8.html"
 ];
 
-#[derive(Fail, Debug)]
-#[fail(display = "URL `{}` is not valid", _0)]
-struct UrlNotValidError(String);
+/// Numero di segmenti scaricati in parallelo se non specificato.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// Timeout per la richiesta di un singolo segmento.
+pub const SEGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Il journal di un download viene aggiornato ogni tanti segmenti o ogni
+/// tanto tempo, invece che dopo ogni segmento.
+const JOURNAL_SEGMENTS: usize = 32;
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Opzioni per il download dei segmenti di una variante.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Segmenti scaricati in parallelo.
+    pub jobs: usize,
+    pub retry: RetryPolicy,
+    /// Se un segmento fallisce anche dopo tutti i tentativi viene saltato e
+    /// annotato nel report, invece di interrompere il download.
+    pub skip_broken_segments: bool,
+    /// Interrompe il download quando scatta, lasciando il journal per
+    /// riprenderlo.
+    pub cancel: CancelToken,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> DownloadOptions {
+        DownloadOptions {
+            jobs: DEFAULT_JOBS,
+            retry: RetryPolicy::default(),
+            skip_broken_segments: false,
+            cancel: CancelToken::never(),
+        }
+    }
+}
 
-#[derive(Fail, Debug)]
-#[fail(display = "M3U8 is not valid and it cannot be parsed")]
-struct M3u8NotValidError;
+/// Risoluzione di una variante, dall'attributo `RESOLUTION`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Resolution, Error> {
+        let mut parts = s.trim().splitn(2, ['x', 'X']);
+        let mut next = || -> Result<u32, Error> {
+            parts
+                .next()
+                .and_then(|part| part.trim().parse().ok())
+                .ok_or_else(|| Error::Playlist(format!("invalid resolution `{}`", s)))
+        };
+        Ok(Resolution {
+            width: next()?,
+            height: next()?,
+        })
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Codec audio che possono comparire in `CODECS` senza una traccia video.
+const AUDIO_CODECS: [&str; 5] = ["mp4a", "ac-3", "ec-3", "opus", "mp3"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct M3u8VideoVariant {
     pub uri: String,
-    pub bandwidth: String,
-    pub resolution: String,
+    /// Bitrate di picco in bit al secondo.
+    pub bandwidth: u64,
+    /// Bitrate medio in bit al secondo, se dichiarato.
+    pub average_bandwidth: Option<u64>,
+    /// `None` per le varianti solo audio.
+    pub resolution: Option<Resolution>,
+    pub codecs: Vec<String>,
+    pub frame_rate: Option<f64>,
+    /// `GROUP-ID` delle tracce audio alternative associate alla variante.
+    pub audio_group: Option<String>,
     segments: Option<Vec<M3u8VideoSegment>>,
     #[serde(skip)]
     pub m3u8_content: Vec<u8>,
@@ -43,110 +128,572 @@ pub struct M3u8VideoVariant {
 pub struct M3u8VideoSegment {
     pub duration: f32,
     pub uri: String,
+    pub media_sequence: u64,
+    /// Chiave con cui è cifrato il segmento, `None` se è in chiaro.
+    pub key: Option<M3u8SegmentKey>,
+}
+
+/// Attributi dell'`#EXT-X-KEY` che si applica a un segmento.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct M3u8SegmentKey {
+    pub method: String,
+    pub uri: Option<String>,
+    pub iv: Option<String>,
+    pub keyformat: Option<String>,
+}
+
+impl M3u8SegmentKey {
+    /// Controlla che la chiave sia AES-128 standard e ne ritorna l'URI.
+    fn aes128_uri(&self) -> Result<&str, Error> {
+        let identity = self
+            .keyformat
+            .as_ref()
+            .is_none_or(|format| format == "identity");
+        match &self.uri {
+            Some(uri) if self.method == crypto::METHOD_AES_128 && identity => Ok(uri),
+            _ => Err(Error::from(UnsupportedEncryptionError {
+                method: self.method.clone(),
+                keyformat: self.keyformat.clone(),
+            })),
+        }
+    }
+}
+
+/// Segmento saltato durante il download, con la posizione nel video.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SegmentGap {
+    pub index: usize,
+    pub uri: String,
+    pub start: f32,
+    pub duration: f32,
+    pub cause: String,
 }
 
 impl M3u8VideoVariant {
     pub fn new(
         uri: String,
-        bandwidth: String,
-        resolution: String,
+        bandwidth: u64,
+        resolution: Option<Resolution>,
         m3u8_content: Vec<u8>,
     ) -> M3u8VideoVariant {
         M3u8VideoVariant {
             uri,
             bandwidth,
+            average_bandwidth: None,
             resolution,
+            codecs: Vec::new(),
+            frame_rate: None,
+            audio_group: None,
             segments: None,
             m3u8_content,
         }
     }
 
+    /// Crea la variante da un `EXT-X-STREAM-INF` della master playlist.
+    fn from_stream(
+        uri: String,
+        stream: &m3u8_rs::playlist::VariantStream,
+        m3u8_content: Vec<u8>,
+    ) -> Result<M3u8VideoVariant, Error> {
+        let parse_number = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| Error::Playlist(format!("invalid bandwidth `{}`", value)))
+        };
+        let mut variant = M3u8VideoVariant::new(
+            uri,
+            parse_number(&stream.bandwidth)?,
+            stream.resolution.as_deref().map(str::parse).transpose()?,
+            m3u8_content,
+        );
+        variant.average_bandwidth = stream
+            .average_bandwidth
+            .as_deref()
+            .map(parse_number)
+            .transpose()?;
+        variant.codecs = stream
+            .codecs
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(|codec| codec.trim().to_string())
+            .filter(|codec| !codec.is_empty())
+            .collect();
+        variant.frame_rate = stream
+            .frame_rate
+            .as_deref()
+            .and_then(|rate| rate.trim().parse().ok());
+        variant.audio_group = stream.audio.clone();
+        Ok(variant)
+    }
+
+    /// Vero se la variante non ha video: senza `RESOLUTION` e con soli codec
+    /// audio in `CODECS`.
+    pub fn is_audio_only(&self) -> bool {
+        self.resolution.is_none()
+            && !self.codecs.is_empty()
+            && self
+                .codecs
+                .iter()
+                .all(|codec| AUDIO_CODECS.iter().any(|audio| codec.starts_with(audio)))
+    }
+
+    /// Confronta due varianti per qualità: prima il video rispetto al solo
+    /// audio, poi la risoluzione, il frame rate e infine il bitrate.
+    pub fn cmp_quality(&self, other: &M3u8VideoVariant) -> Ordering {
+        (!self.is_audio_only(), self.resolution)
+            .cmp(&(!other.is_audio_only(), other.resolution))
+            .then_with(|| {
+                let self_rate = self.frame_rate.unwrap_or(0.0);
+                let other_rate = other.frame_rate.unwrap_or(0.0);
+                self_rate
+                    .partial_cmp(&other_rate)
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| {
+                (
+                    self.average_bandwidth.unwrap_or(self.bandwidth),
+                    self.bandwidth,
+                )
+                    .cmp(&(
+                        other.average_bandwidth.unwrap_or(other.bandwidth),
+                        other.bandwidth,
+                    ))
+            })
+    }
+
+    /// Descrizione della variante da mostrare all'utente, ad esempio
+    /// `1280x720 25fps, 1.80 Mbit/s` o `solo audio, 128 kbit/s`.
+    pub fn label(&self) -> String {
+        let bitrate = self.average_bandwidth.unwrap_or(self.bandwidth);
+        let bitrate = if bitrate >= 1_000_000 {
+            format!("{:.2} Mbit/s", bitrate as f64 / 1_000_000.0)
+        } else {
+            format!("{} kbit/s", bitrate / 1000)
+        };
+        let kind = match (self.resolution, self.frame_rate) {
+            (Some(resolution), Some(rate)) => format!("{} {}fps", resolution, rate),
+            (Some(resolution), None) => resolution.to_string(),
+            (None, _) if self.is_audio_only() => String::from("solo audio"),
+            (None, _) => String::from("risoluzione sconosciuta"),
+        };
+        format!("{}, {}", kind, bitrate)
+    }
+
     /// Scarica il vettore di segmenti e lo cachea nello struct, se tutto va bene
-    /// ritorna una reference wrappata in un Some().
+    /// ritorna una reference wrappata in un Some(). La playlist viene
+    /// scaricata ritentando gli errori transitori secondo `retry`.
     pub async fn fetch_segments(
         &mut self,
-        verbose: Option<bool>,
+        retry: &RetryPolicy,
+        progress: &dyn ProgressReporter,
     ) -> Result<&Vec<M3u8VideoSegment>, Error> {
-        let verbose = verbose.unwrap_or(false);
-
         if self.segments.is_none() {
-            if verbose {
-                print!("Ottenendo i segmenti M3U8 per la variante...");
-            }
-            let text = reqwest::get(&self.uri).await?.text().await?;
-            let parsed = m3u8_rs::parse_media_playlist_res(text.as_bytes())
-                .map_err(|_| M3u8NotValidError)?;
-
-            self.segments = Some(
-                parsed
-                    .segments
-                    .into_iter()
-                    .map(|seg| M3u8VideoSegment {
-                        duration: seg.duration,
-                        uri: seg.uri,
-                    })
-                    .collect(),
-            );
-
-            if verbose {
-                println!("{}", style(" fatto").green());
-            }
+            progress.report(ProgressEvent::FetchingMetadata(Resource::Segments));
+            let (base_url, data) =
+                retry::fetch_url_with_retry(&http::client()?, &self.uri, retry, |_, _| {}).await?;
+            let text = String::from_utf8_lossy(&data);
+            let segments = parse_media_playlist(&text, &base_url)?.segments;
+            self.segments = Some(segments);
+            progress.report(ProgressEvent::MetadataFetched(Resource::Segments));
         }
 
         Ok(self.segments.as_ref().unwrap())
     }
 
     /// Salva i dati M3U8 in un file.
-    pub fn save_m3u8(&self, path: &Path, verbose: Option<bool>) -> Result<(), Error> {
-        let verbose = verbose.unwrap_or(false);
+    pub fn save_m3u8(&self, path: &Path, progress: &dyn ProgressReporter) -> Result<(), Error> {
         let mut file = File::create(path)?;
         file.write_all(&self.m3u8_content)?;
-        if verbose {
-            println!("M3U8 salvato in {:#?}", style(path).green());
-        }
+        progress.report(ProgressEvent::Finished { path });
         Ok(())
     }
 
     /// Scarica tutti i segmenti e li concatena in un unico file .ts.
-    pub async fn download_ts(&mut self, path: &Path, verbose: Option<bool>) -> Result<(), Error> {
-        let segs = self.fetch_segments(verbose).await?;
-        let verbose = verbose.unwrap_or(false);
+    ///
+    /// Fino a `options.jobs` segmenti vengono scaricati in parallelo, ma sono
+    /// scritti nel file sempre nell'ordine della playlist. I segmenti
+    /// completati vengono registrati in un journal accanto al file, così che
+    /// rilanciando lo stesso download si riprenda dal primo segmento mancante.
+    ///
+    /// Se `options.skip_broken_segments` è attivo, ritorna i segmenti saltati
+    /// e li salva anche in `<nome>.ts.gaps.json`.
+    pub async fn download_ts(
+        &mut self,
+        path: &Path,
+        options: &DownloadOptions,
+        progress: &dyn ProgressReporter,
+    ) -> Result<Vec<SegmentGap>, Error> {
+        let variant_uri = self.uri.clone();
+        let segs = self.fetch_segments(&options.retry, progress).await?;
+        let jobs = options.jobs.max(1);
         let segs_len = segs.len() as u64;
-        let mut file = File::create(path)?;
-
-        let progress_bar = {
-            let pbar = ProgressBar::new(segs_len)
-                .with_style(ProgressStyle::default_bar().template(
-                "[{prefix} / {msg}] [{wide_bar:.cyan/blue}] segmento {pos} / {len} [{elapsed} .. {eta}]",
-            ));
+        let seg_uris: Vec<String> = segs.iter().map(|seg| seg.uri.clone()).collect();
 
-            pbar.set_prefix(&style("0").green().to_string());
-            pbar.set_message("0");
-            pbar.enable_steady_tick(1000);
+        // Le chiavi vengono scaricate una volta sola prima dei segmenti, così
+        // uno stream con cifratura non supportata fallisce prima di creare
+        // il file.
+        let client = http::segment_client()?;
+        let mut keys: HashMap<String, [u8; 16]> = HashMap::new();
+        fetch_keys(&client, segs, &options.retry, &mut keys).await?;
 
-            pbar
+        let journal_path = SegmentJournal::path_for(path);
+        // Se il .ts è più corto di quanto registrato nel journal, i dati
+        // mancanti non sono mai arrivati sul disco e si ricomincia da capo.
+        let ts_len = fs::metadata(path).map(|metadata| metadata.len()).ok();
+        let (mut file, mut journal) = match SegmentJournal::load(&journal_path)? {
+            Some(journal)
+                if ts_len.is_some_and(|len| len >= journal.committed_len())
+                    && journal.matches(&variant_uri, &seg_uris) =>
+            {
+                // Un eventuale segmento scritto solo in parte viene scartato.
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(journal.committed_len())?;
+                file.seek(SeekFrom::End(0))?;
+                (file, journal)
+            }
+            _ => (
+                File::create(path)?,
+                SegmentJournal::new(variant_uri, seg_uris),
+            ),
         };
+        journal.save(&journal_path)?;
+        let already_committed = journal.committed.len();
+        progress.report(ProgressEvent::DownloadStarted {
+            path,
+            segments: Some(segs_len),
+            bytes: None,
+            resumed: already_committed as u64,
+        });
 
-        let mut total_content_len = 0;
+        let mut total_content_len = journal.committed_len();
+        let mut journal_saved_at = Instant::now();
 
-        for seg in segs {
-            let seg_data = reqwest::get(&seg.uri).await?;
-            total_content_len += seg_data.content_length().unwrap_or(0);
-            file.write(&mut seg_data.bytes().await?)?;
-            // std::io::copy(&mut seg_data, &mut file)?;
+        // `buffered` tiene al massimo `jobs` download in volo e restituisce i
+        // segmenti in ordine: quelli completati in anticipo restano in memoria
+        // solo finché non arriva il loro turno, quindi la finestra di
+        // riordinamento non supera mai `jobs` segmenti.
+        let keys = &keys;
+        let mut seg_stream = stream::iter(segs.iter().enumerate().skip(already_committed))
+            .map(|(i, seg)| {
+                let fut = retry::fetch_with_retry(
+                    &client,
+                    &seg.uri,
+                    &options.retry,
+                    move |attempt, cause| {
+                        progress.report(ProgressEvent::SegmentRetried {
+                            index: i,
+                            attempt,
+                            retries: options.retry.retries,
+                            cause,
+                        })
+                    },
+                );
+                async move {
+                    progress.report(ProgressEvent::SegmentStarted { index: i });
+                    let data = match fut.await {
+                        Ok(data) => decrypt_segment(seg, keys, data),
+                        Err(err) => Err(err),
+                    };
+                    (i, data)
+                }
+            })
+            .buffered(jobs);
+
+        // Interrotto il download, il journal resta accanto al file, così
+        // che rilanciando il comando riprenda dall'ultimo segmento scritto.
+        let interrupt = options.cancel.cancelled();
+        tokio::pin!(interrupt);
 
-            progress_bar.inc(1);
-            progress_bar.set_prefix(&style(HumanBytes(total_content_len)).green().to_string());
-            let predicted_total_size = total_content_len / progress_bar.position() * segs_len;
-            progress_bar.set_message(&HumanBytes(predicted_total_size).to_string());
+        loop {
+            let (i, seg_data) = tokio::select! {
+                next = seg_stream.next() => match next {
+                    Some(next) => next,
+                    None => break,
+                },
+                _ = &mut interrupt => {
+                    file.sync_data()?;
+                    journal.append(&journal_path)?;
+                    progress.report(ProgressEvent::Aborted);
+                    return Err(Error::Cancelled);
+                }
+            };
+            match seg_data {
+                Ok(seg_data) => {
+                    total_content_len += seg_data.len() as u64;
+                    file.write_all(&seg_data)?;
+                    journal.commit(i, seg_data.len() as u64);
+                    progress.report(ProgressEvent::SegmentCompleted {
+                        index: i,
+                        bytes: seg_data.len() as u64,
+                    });
+                }
+                // Un token scaduto riguarda anche tutti i segmenti successivi,
+                // saltarli produrrebbe un file vuoto.
+                Err(err)
+                    if options.skip_broken_segments && !matches!(err, Error::TokenExpired(_)) =>
+                {
+                    let cause = err.to_string();
+                    progress.report(ProgressEvent::SegmentSkipped {
+                        index: i,
+                        cause: &cause,
+                    });
+                    journal.commit_skipped(i, cause);
+                }
+                Err(err) => {
+                    if let Error::TokenExpired(_) = err {
+                        progress.report(ProgressEvent::Warning(
+                            "Il token del CDN è scaduto, rilancia il comando per riprendere il download",
+                        ));
+                    }
+                    file.sync_data()?;
+                    journal.append(&journal_path)?;
+                    progress.report(ProgressEvent::Aborted);
+                    return Err(err);
+                }
+            }
+            // I segmenti vengono registrati nel journal a gruppi, dopo aver
+            // portato sul disco i loro dati.
+            if journal.pending() >= JOURNAL_SEGMENTS
+                || journal_saved_at.elapsed() >= JOURNAL_INTERVAL
+            {
+                file.sync_data()?;
+                journal.append(&journal_path)?;
+                journal_saved_at = Instant::now();
+            }
+            progress.report(ProgressEvent::BytesWritten {
+                written: total_content_len,
+                total: None,
+            });
         }
 
-        progress_bar.finish();
-        if verbose {
-            println!("TS salvato in {:#?}", style(path).green());
+        let gaps: Vec<SegmentGap> = journal
+            .skipped()
+            .map(|gap| SegmentGap {
+                index: gap.index,
+                uri: segs[gap.index].uri.clone(),
+                start: segs[..gap.index].iter().map(|seg| seg.duration).sum(),
+                duration: segs[gap.index].duration,
+                cause: gap.skipped.clone().unwrap_or_default(),
+            })
+            .collect();
+        if !gaps.is_empty() {
+            let mut report_path = path.as_os_str().to_owned();
+            report_path.push(".gaps.json");
+            let mut report = File::create(&report_path)?;
+            report.write_all(serde_json::to_string_pretty(&gaps)?.as_bytes())?;
+            progress.report(ProgressEvent::GapsSaved {
+                count: gaps.len() as u64,
+                report: Path::new(&report_path),
+            });
+        }
+
+        fs::remove_file(&journal_path)?;
+        progress.report(ProgressEvent::Finished { path });
+        Ok(gaps)
+    }
+}
+
+/// Media playlist letta da [`parse_media_playlist`].
+#[derive(Debug)]
+pub struct M3u8MediaPlaylist {
+    pub target_duration: f32,
+    /// Vero se c'è `#EXT-X-ENDLIST`, cioè se la playlist non crescerà più.
+    pub end_list: bool,
+    pub segments: Vec<M3u8VideoSegment>,
+}
+
+/// Legge una media playlist scaricata da `base_url`, risolvendo gli URI dei
+/// segmenti e delle chiavi.
+pub fn parse_media_playlist(
+    text: &str,
+    base_url: &reqwest::Url,
+) -> Result<M3u8MediaPlaylist, Error> {
+    let parsed = m3u8_rs::parse_media_playlist_res(text.as_bytes())
+        .map_err(|_| Error::Playlist(String::from("media playlist cannot be parsed")))?;
+
+    // m3u8-rs associa l'`#EXT-X-KEY` solo al segmento successivo al tag,
+    // mentre la chiave vale fino al prossimo `#EXT-X-KEY`.
+    let mut current_key = None;
+    let mut segments = Vec::with_capacity(parsed.segments.len());
+    for (i, seg) in parsed.segments.into_iter().enumerate() {
+        if let Some(key) = seg.key {
+            current_key = if key.method == crypto::METHOD_NONE {
+                None
+            } else {
+                Some(M3u8SegmentKey {
+                    method: key.method,
+                    uri: match key.uri {
+                        Some(uri) => Some(resolve_uri(base_url, &uri)?),
+                        None => None,
+                    },
+                    iv: key.iv,
+                    keyformat: key.keyformat,
+                })
+            };
+        }
+        segments.push(M3u8VideoSegment {
+            duration: seg.duration,
+            uri: resolve_uri(base_url, &seg.uri)?,
+            media_sequence: parsed.media_sequence as u64 + i as u64,
+            key: current_key.clone(),
+        });
+    }
+
+    Ok(M3u8MediaPlaylist {
+        target_duration: parsed.target_duration,
+        end_list: parsed.end_list,
+        segments,
+    })
+}
+
+/// Scarica in `keys` le chiavi AES-128 dei segmenti che mancano.
+pub async fn fetch_keys(
+    client: &reqwest::Client,
+    segments: &[M3u8VideoSegment],
+    policy: &RetryPolicy,
+    keys: &mut HashMap<String, [u8; 16]>,
+) -> Result<(), Error> {
+    for key in segments.iter().filter_map(|seg| seg.key.as_ref()) {
+        let key_uri = key.aes128_uri()?;
+        if !keys.contains_key(key_uri) {
+            let data = retry::fetch_with_retry(client, key_uri, policy, |_, _| {}).await?;
+            keys.insert(key_uri.to_string(), crypto::parse_key(&data)?);
         }
-        Ok(())
     }
+    Ok(())
+}
+
+/// Decifra un segmento se ha una chiave, altrimenti lo ritorna invariato.
+pub fn decrypt_segment(
+    seg: &M3u8VideoSegment,
+    keys: &HashMap<String, [u8; 16]>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    match &seg.key {
+        Some(key) => {
+            let aes_key = &keys[key.aes128_uri()?];
+            let iv = crypto::segment_iv(key.iv.as_deref(), seg.media_sequence)?;
+            crypto::decrypt_aes128(&data, aes_key, &iv)
+        }
+        None => Ok(data),
+    }
+}
+
+/// Traccia audio alternativa, dichiarata con `#EXT-X-MEDIA:TYPE=AUDIO`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct M3u8AudioRendition {
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    /// Ad esempio `public.accessibility.describes-video` per
+    /// l'audiodescrizione.
+    pub characteristics: Option<String>,
+    pub default: bool,
+    /// Playlist della traccia, `None` se l'audio è già nelle varianti del
+    /// gruppo.
+    pub playlist: Option<M3u8VideoVariant>,
+}
+
+impl M3u8AudioRendition {
+    /// Codice ISO 639-2 della lingua, se riconosciuto.
+    pub fn language_code(&self) -> Option<String> {
+        let language = self.language.as_deref()?.split('-').next()?;
+        match subtitles::language_code(language).as_str() {
+            "und" if language.len() == 3 => Some(language.to_lowercase()),
+            "und" => None,
+            code => Some(code.to_string()),
+        }
+    }
+
+    /// Vero se la traccia corrisponde a `requested`, che può essere un
+    /// codice di lingua (`it`, `ita`), un nome di lingua o il `NAME` della
+    /// traccia.
+    pub fn matches_language(&self, requested: &str) -> bool {
+        let requested = requested.trim().to_lowercase();
+        if self.name.to_lowercase() == requested
+            || self.language.as_deref().map(str::to_lowercase) == Some(requested.clone())
+        {
+            return true;
+        }
+        let code = subtitles::language_code(&requested);
+        code != "und" && self.language_code() == Some(code)
+    }
+
+    pub fn label(&self) -> String {
+        match &self.language {
+            Some(language) => format!("{} ({})", self.name, language),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Legge una attribute-list HLS (`CHIAVE=valore,CHIAVE="valore, con
+/// virgole"`), togliendo le virgolette dai valori.
+fn parse_attribute_list(list: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = list.trim();
+
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or("");
+            &quoted[..end]
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        attributes.insert(key, value.to_string());
+        rest = rest.trim_start_matches(',').trim_start();
+    }
+
+    attributes
+}
+
+/// Legge le tracce audio alternative della master playlist. Le tag
+/// `EXT-X-MEDIA` vengono lette a mano perché m3u8-rs le associa in modo
+/// inaffidabile alle varianti.
+fn parse_audio_renditions(
+    master: &str,
+    base_url: &reqwest::Url,
+) -> Result<Vec<M3u8AudioRendition>, Error> {
+    let mut renditions = Vec::new();
+
+    for line in master.lines() {
+        let attributes = match line.trim().strip_prefix("#EXT-X-MEDIA:") {
+            Some(list) => parse_attribute_list(list),
+            None => continue,
+        };
+        if attributes.get("TYPE").map(String::as_str) != Some("AUDIO") {
+            continue;
+        }
+        let attribute = |key: &str| attributes.get(key).cloned();
+
+        let playlist = match attributes.get("URI") {
+            Some(uri) => Some(M3u8VideoVariant::new(
+                resolve_uri(base_url, uri)?,
+                0,
+                None,
+                master.as_bytes().to_vec(),
+            )),
+            None => None,
+        };
+        renditions.push(M3u8AudioRendition {
+            group_id: attribute("GROUP-ID").unwrap_or_default(),
+            name: attribute("NAME").unwrap_or_default(),
+            language: attribute("LANGUAGE"),
+            characteristics: attribute("CHARACTERISTICS"),
+            default: attribute("DEFAULT").as_deref() == Some("YES"),
+            playlist,
+        });
+    }
+
+    Ok(renditions)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -154,26 +701,263 @@ pub struct RaiPlayVideoInfos {
     pub mp4_url: String,
     pub infos: video::RaiPlayVideo,
     pub m3u8_variants: Vec<M3u8VideoVariant>,
+    pub audio_renditions: Vec<M3u8AudioRendition>,
 }
 
 impl RaiPlayVideoInfos {
+    /// Indici delle tracce audio alternative, con una playlist propria, da
+    /// scaricare insieme alla variante `variant`. Se `languages` è vuoto
+    /// viene scelta la traccia predefinita del gruppo; `all` le sceglie
+    /// tutte.
+    pub fn select_audio(&self, variant: usize, languages: &[String]) -> Vec<usize> {
+        let group = match &self.m3u8_variants[variant].audio_group {
+            Some(group) => group,
+            None => return Vec::new(),
+        };
+        let candidates: Vec<usize> = self
+            .audio_renditions
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| &r.group_id == group && r.playlist.is_some())
+            .map(|(i, _)| i)
+            .collect();
+
+        if languages.iter().any(|lang| lang == "all") {
+            return candidates;
+        }
+        if languages.is_empty() {
+            let default = candidates
+                .iter()
+                .copied()
+                .find(|&i| self.audio_renditions[i].default);
+            return default
+                .or_else(|| candidates.first().copied())
+                .into_iter()
+                .collect();
+        }
+        // Le tracce seguono l'ordine delle lingue richieste.
+        let mut selected = Vec::new();
+        for language in languages {
+            for &i in &candidates {
+                if self.audio_renditions[i].matches_language(language) && !selected.contains(&i) {
+                    selected.push(i);
+                }
+            }
+        }
+        selected
+    }
+
+    /// Variante da usare quando serve solo l'audio: la migliore variante solo
+    /// audio, altrimenti la migliore con tracce audio alternative, altrimenti
+    /// la più leggera, da cui verrà scartato il video.
+    pub fn audio_only_variant(&self) -> Option<usize> {
+        let best = |filter: &dyn Fn(&M3u8VideoVariant) -> bool| {
+            self.m3u8_variants
+                .iter()
+                .enumerate()
+                .filter(|(_, variant)| filter(variant))
+                .max_by(|a, b| a.1.cmp_quality(b.1))
+                .map(|(i, _)| i)
+        };
+        best(&|variant| variant.is_audio_only())
+            .or_else(|| {
+                best(&|variant| {
+                    self.audio_renditions.iter().any(|r| {
+                        Some(&r.group_id) == variant.audio_group.as_ref() && r.playlist.is_some()
+                    })
+                })
+            })
+            .or_else(|| {
+                self.m3u8_variants
+                    .iter()
+                    .enumerate()
+                    .min_by(|a, b| a.1.cmp_quality(b.1))
+                    .map(|(i, _)| i)
+            })
+    }
+
+    /// Metadati del video da salvare nel contenitore.
+    pub fn metadata(&self) -> Metadata {
+        let infos = &self.infos;
+        let non_empty = |s: &str| {
+            let s = s.trim();
+            if s.is_empty() {
+                None
+            } else {
+                Some(s.to_string())
+            }
+        };
+
+        Metadata {
+            title: non_empty(&infos.episode_title).or_else(|| non_empty(&infos.name)),
+            show: non_empty(&infos.program_info.name),
+            season: infos.season.trim().parse().ok(),
+            episode: infos.episode.trim().parse().ok(),
+            description: non_empty(&infos.description),
+            channel: non_empty(&infos.channel),
+            date: iso_date(&infos.date_published),
+        }
+    }
+
+    /// Scarica le tracce audio alternative `renditions` in file `.ts`
+    /// accanto al video `filename` e ritorna ogni file con la lingua della
+    /// sua traccia.
+    pub async fn download_renditions(
+        &mut self,
+        renditions: &[usize],
+        filename: &str,
+        options: &DownloadOptions,
+        progress: &dyn ProgressReporter,
+    ) -> Result<Vec<(PathBuf, Option<String>)>, Error> {
+        let mut files: Vec<(PathBuf, Option<String>)> = Vec::new();
+
+        for &r in renditions {
+            let rendition = &mut self.audio_renditions[r];
+            let language = rendition.language_code();
+            let tag = language.clone().unwrap_or_else(|| String::from("audio"));
+            let mut path = PathBuf::from(format!("{}.{}.ts", filename, tag));
+            let mut n = 1;
+            while files.iter().any(|(other, _)| other == &path) {
+                n += 1;
+                path = PathBuf::from(format!("{}.{}-{}.ts", filename, tag, n));
+            }
+
+            progress.report(ProgressEvent::VariantSelected {
+                label: &rendition.label(),
+                audio: true,
+            });
+            let playlist = rendition
+                .playlist
+                .as_mut()
+                .ok_or_else(|| Error::Playlist(String::from("audio rendition has no playlist")))?;
+            playlist.download_ts(&path, options, progress).await?;
+            files.push((path, language));
+        }
+
+        Ok(files)
+    }
+
     /// Scarica i segmenti di tutte le varianti M3U8.
-    pub async fn fetch_all_segments(&mut self, verbose: Option<bool>) -> Result<(), Error> {
+    pub async fn fetch_all_segments(
+        &mut self,
+        retry: &RetryPolicy,
+        progress: &dyn ProgressReporter,
+    ) -> Result<(), Error> {
         for seg in self.m3u8_variants.iter_mut() {
-            seg.fetch_segments(verbose).await?;
+            seg.fetch_segments(retry, progress).await?;
+        }
+        for rendition in self.audio_renditions.iter_mut() {
+            if let Some(playlist) = rendition.playlist.as_mut() {
+                playlist.fetch_segments(retry, progress).await?;
+            }
         }
         Ok(())
     }
 }
 
+/// Converte una data di RaiPlay, nel formato `GG-MM-AAAA` o `GG/MM/AAAA`,
+/// in `AAAA-MM-GG`. Le date già in quel formato vengono ritornate invariate.
+pub fn iso_date(date: &str) -> Option<String> {
+    // Un eventuale orario dopo la data viene ignorato.
+    let date = date.split_whitespace().next()?;
+    match date.split(['-', '/']).collect::<Vec<_>>()[..] {
+        [year, month, day] if year.len() == 4 => Some(format!("{}-{}-{}", year, month, day)),
+        [day, month, year] if year.len() == 4 => Some(format!("{}-{}-{}", year, month, day)),
+        _ => None,
+    }
+}
+
+/// Risolve un URI di una playlist rispetto all'URL da cui è stata scaricata,
+/// secondo l'RFC 3986. Gli URI già assoluti vengono ritornati invariati.
+fn resolve_uri(base_url: &reqwest::Url, uri: &str) -> Result<String, Error> {
+    Ok(base_url.join(uri)?.to_string())
+}
+
+/// Il relinker non rifiuta chi si collega dall'estero a un contenuto
+/// visibile solo dall'Italia, ma lo redirige a un video segnaposto ("video
+/// non disponibile").
+pub fn is_geo_blocked_placeholder(url: &reqwest::Url) -> bool {
+    url.path()
+        .rsplit('/')
+        .next()
+        .is_some_and(|name| name.starts_with("video_no_available"))
+}
+
+/// Legge le varianti della master playlist `m3u8_text`, ordinate per
+/// qualità crescente. Una variante con attributi non validi viene segnalata
+/// e ignorata; l'errore arriva solo se non ne resta nessuna.
+fn parse_variants(
+    m3u8_text: &str,
+    base_url: &reqwest::Url,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<M3u8VideoVariant>, Error> {
+    let parsed = m3u8_rs::parse_master_playlist_res(m3u8_text.as_bytes())
+        .map_err(|_| Error::Playlist(String::from("master playlist cannot be parsed")))?;
+
+    let mut variants = Vec::new();
+    let mut last_error = None;
+    // Le varianti I-frame servono solo per le anteprime durante il seek.
+    for var in parsed.variants.iter().filter(|var| !var.is_i_frame) {
+        let variant = resolve_uri(base_url, &var.uri)
+            .and_then(|uri| M3u8VideoVariant::from_stream(uri, var, m3u8_text.as_bytes().to_vec()));
+        match variant {
+            Ok(variant) => variants.push(variant),
+            Err(err) => {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "Ignoro la variante {}: {}",
+                    var.uri, err
+                )));
+                last_error = Some(err);
+            }
+        }
+    }
+    if let (true, Some(err)) = (variants.is_empty(), last_error) {
+        return Err(err);
+    }
+    variants.sort_by(|a, b| a.cmp_quality(b));
+    Ok(variants)
+}
+
+/// Scarica la master playlist dal relinker `content_url` e ne legge le
+/// varianti, ordinate per qualità crescente, e le tracce audio alternative.
+pub async fn fetch_master_playlist(
+    content_url: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<(Vec<M3u8VideoVariant>, Vec<M3u8AudioRendition>), Error> {
+    progress.report(ProgressEvent::FetchingMetadata(Resource::Variants));
+    let client = http::client()?;
+    let resp = client.get(content_url).send().await?;
+    if is_geo_blocked_placeholder(resp.url()) {
+        return Err(Error::GeoBlocked(content_url.to_string()));
+    }
+    let resp = resp.error_for_status()?;
+    // Gli URI relativi vanno risolti rispetto all'URL finale, dopo i
+    // redirect del relinker verso il server del CDN.
+    let base_url = resp.url().clone();
+    let m3u8_text = resp.text().await?;
+    let variants = parse_variants(&m3u8_text, &base_url, progress)?;
+    let renditions = parse_audio_renditions(&m3u8_text, &base_url)?;
+
+    progress.report(ProgressEvent::MetadataFetched(Resource::Variants));
+    Ok((variants, renditions))
+}
+
 /// Estrae l'URL all'M3U8 del video o direttamente al suo MP4 (di qualità
 /// sconosciuta).
 pub async fn extract_video_url(
     url: &str,
-    verbose: Option<bool>,
+    progress: &dyn ProgressReporter,
 ) -> Result<RaiPlayVideoInfos, Error> {
-    let verbose = verbose.unwrap_or(false);
+    let video = fetch_video(url, progress).await?;
+    resolve_video(url, video, progress).await
+}
 
+/// Scarica solo il JSON della pagina del video `url`, senza passare dal
+/// relinker.
+pub async fn fetch_video(
+    url: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<video::RaiPlayVideo, Error> {
     let captures = {
         let re = Regex::new(r#"^http(s)?://(?:www\.)?raiplay\.it/video/\d{4}/\d{2}/[^\.]+\.html$"#)
             .unwrap();
@@ -182,11 +966,7 @@ pub async fn extract_video_url(
 
     let is_https = match captures {
         Some(caps) => caps.get(1).is_some(),
-        None => {
-            return Err(Error::from_boxed_compat(Box::new(
-                UrlNotValidError(url.to_string()).compat(),
-            )))
-        }
+        None => return Err(Error::InvalidUrl(url.to_string())),
     };
 
     let req_url = if is_https {
@@ -202,60 +982,117 @@ pub async fn extract_video_url(
         json_url.truncate(json_url.len() - 1);
         json_url.join(".") + ".json"
     };
-    if verbose {
-        print!("Ottenendo il JSON contenente le informazioni sul video...");
-    }
-    let rai_json_resp: video::RaiPlayVideo = reqwest::get(&json_url).await?.json().await?;
-    if verbose {
-        println!("{}", style(" fatto").green());
-    }
+    progress.report(ProgressEvent::FetchingMetadata(Resource::VideoInfo));
+    let rai_json_resp: video::RaiPlayVideo = http::client()?
+        .get(&json_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    progress.report(ProgressEvent::MetadataFetched(Resource::VideoInfo));
+    Ok(rai_json_resp)
+}
+
+/// Completa le informazioni del video `url`, già scaricate con
+/// [`fetch_video`], con le varianti della master playlist e l'URL dell'MP4
+/// ottenuti dal relinker.
+pub async fn resolve_video(
+    url: &str,
+    rai_json_resp: video::RaiPlayVideo,
+    progress: &dyn ProgressReporter,
+) -> Result<RaiPlayVideoInfos, Error> {
     let m3u8_url = &rai_json_resp.video.content_url;
 
-    let m3u8_variants = {
-        if verbose {
-            print!("Ottenendo le varianti M3U8...");
-        }
-        let client = reqwest::Client::builder().user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/80.0.3987.106 Safari/537.36").build()?;
-        let resp = client.get(m3u8_url).send().await?;
-        let m3u8_text = resp.text().await?;
-        let parsed = m3u8_rs::parse_master_playlist_res(m3u8_text.as_bytes())
-            .map_err(|_| M3u8NotValidError)?;
-
-        let variants = parsed
-            .variants
-            .into_iter()
-            .filter_map(|var| match var.resolution {
-                Some(res) => Some(M3u8VideoVariant::new(
-                    var.uri,
-                    var.bandwidth,
-                    res,
-                    m3u8_text.as_bytes().to_vec(),
-                )),
-                None => None,
-            })
-            .collect();
+    let login_required = rai_json_resp.login_required;
+    // Senza credenziali il relinker fallirebbe in modi poco chiari.
+    if login_required && !auth::session().is_logged_in() {
+        return Err(Error::LoginRequired(url.to_string()));
+    }
+    let geoprotected = rai_json_resp
+        .rights_management
+        .rights
+        .geoprotection
+        .is_set();
 
-        if verbose {
-            println!("{}", style(" fatto").green());
+    let (m3u8_variants, audio_renditions) = match fetch_master_playlist(m3u8_url, progress).await {
+        Ok(playlist) => playlist,
+        Err(Error::GeoBlocked(_)) => return Err(Error::GeoBlocked(url.to_string())),
+        // Per i contenuti geoprotetti un 403 o una risposta che non è una
+        // playlist sono il modo in cui il CDN rifiuta chi è all'estero.
+        Err(Error::Http { status: 403, .. }) | Err(Error::Playlist(_)) if geoprotected => {
+            return Err(Error::GeoBlocked(url.to_string()))
+        }
+        // Le credenziali ci sono ma il relinker non le ha accettate.
+        Err(Error::Http {
+            status: 401..=403, ..
+        })
+        | Err(Error::Playlist(_))
+            if login_required =>
+        {
+            return Err(Error::LoginRequired(url.to_string()))
         }
-        variants
+        Err(err) => return Err(err),
     };
 
     let mp4_url = {
-        if verbose {
-            print!("Ottenendo l'URL MP4 del video...");
+        progress.report(ProgressEvent::FetchingMetadata(Resource::Mp4Url));
+        let client = http::client()?;
+        let resp = client.head(m3u8_url).send().await?;
+        let mp4_url = resp.url().clone();
+        if is_geo_blocked_placeholder(&mp4_url) {
+            return Err(Error::GeoBlocked(url.to_string()));
         }
-        let client = reqwest::Client::new();
-        let mp4_url = client.head(m3u8_url).send().await?.url().to_string();
-        if verbose {
-            println!("{}", style(" fatto").green());
-        }
-        mp4_url
+        resp.error_for_status()?;
+        progress.report(ProgressEvent::MetadataFetched(Resource::Mp4Url));
+        mp4_url.to_string()
     };
 
     Ok(RaiPlayVideoInfos {
         m3u8_variants,
+        audio_renditions,
         infos: rai_json_resp,
         mp4_url,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+
+    #[test]
+    fn test() {
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+        assert!(is_geo_blocked_placeholder(&url(
+            "https://download.rai.it/video_no_available.mp4"
+        )));
+        assert!(!is_geo_blocked_placeholder(&url(
+            "https://creativemedia3-rai-it.akamaized.net/podcastcdn/raiuno/video.mp4"
+        )));
+
+        let rights: video::Rights =
+            serde_json::from_str(r#"{"offline": {}, "geoprotection": {"value": "Y"}}"#).unwrap();
+        assert!(rights.geoprotection.is_set());
+        assert!(!rights.offline.is_set());
+
+        let base_url = url("https://cdn.rai.it/master.m3u8");
+        let master = |first: &str| {
+            format!(
+                "#EXTM3U\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION=640x360\n\
+                 low.m3u8\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1280x720\n\
+                 high.m3u8\n",
+                first
+            )
+        };
+        let variants = parse_variants(&master("abc"), &base_url, &NoProgress).unwrap();
+        assert_eq!(variants.len(), 1);
+        assert_eq!(variants[0].uri, "https://cdn.rai.it/high.m3u8");
+        let variants = parse_variants(&master("800000"), &base_url, &NoProgress).unwrap();
+        assert_eq!(variants.len(), 2);
+        let broken = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=abc\nlow.m3u8\n";
+        assert!(parse_variants(broken, &base_url, &NoProgress).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+#![warn(clippy::all)]
+
+//! Archivio dei video già scaricati, per saltarli quando si riscarica una
+//! stagione. Il file ha una riga per video, con il suo `id` e il suo
+//! `path_id` separati da uno spazio.
+
+use crate::error::Error;
+use crate::remux;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub id: String,
+    pub path_id: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DownloadArchive {
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl DownloadArchive {
+    pub fn parse(content: &str) -> DownloadArchive {
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(ArchiveEntry {
+                    id: fields.next()?.to_string(),
+                    path_id: fields.next().unwrap_or_default().to_string(),
+                })
+            })
+            .collect();
+        DownloadArchive { entries }
+    }
+
+    /// Legge l'archivio da `path`; se il file non esiste è vuoto.
+    pub fn load(path: &Path) -> Result<DownloadArchive, Error> {
+        if !path.exists() {
+            return Ok(DownloadArchive::default());
+        }
+        Ok(DownloadArchive::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Salva l'archivio in `path`, passando da un file temporaneo come il
+    /// journal dei download.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)?;
+        for entry in &self.entries {
+            if entry.path_id.is_empty() {
+                writeln!(file, "{}", entry.id)?;
+            } else {
+                writeln!(file, "{} {}", entry.id, entry.path_id)?;
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Se l'archivio contiene un video con questo `id` o `path_id`.
+    pub fn contains(&self, key: &str) -> bool {
+        !key.is_empty()
+            && self
+                .entries
+                .iter()
+                .any(|entry| entry.id == key || entry.path_id == key)
+    }
+
+    /// Aggiunge un video, a meno che non ci sia già. Ritorna vero se è stato
+    /// aggiunto.
+    pub fn add(&mut self, id: &str, path_id: &str) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.entries.push(ArchiveEntry {
+            id: id.to_string(),
+            path_id: path_id.to_string(),
+        });
+        true
+    }
+}
+
+/// Controlla che `output` sia un file scritto per intero: se un download o
+/// una conversione si fossero interrotti sarebbe vuoto, avrebbe un
+/// pacchetto TS o un frame MP3 troncato, o sarebbe più corto di quanto
+/// dichiarano i box del MP4 o il segmento del Matroska. Un file in un altro
+/// formato, per esempio una pagina di errore, non viene accettato.
+pub fn verify_output(output: &Path) -> Result<(), Error> {
+    let metadata = fs::metadata(output)?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Err(Error::Media(format!("`{}` is empty", output.display())));
+    }
+    remux::verify(output).map_err(|err| {
+        Error::Media(format!(
+            "`{}` is truncated or not valid: {}",
+            output.display(),
+            err
+        ))
+    })
+}
+
+/// Aggiunge il video `id` all'archivio in `path` dopo aver verificato il suo
+/// file `output`. L'archivio viene riletto subito prima, così che un altro
+/// download che lo usa nel frattempo non perda le sue righe.
+pub fn record(path: &Path, id: &str, path_id: &str, output: &Path) -> Result<(), Error> {
+    verify_output(output)?;
+    let mut archive = DownloadArchive::load(path)?;
+    if archive.add(id, path_id) {
+        archive.save(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let mut archive = DownloadArchive::parse(
+            "ContentItem-1 /video/2019/10/Il-Collegio-1.json\n\nContentItem-2\n",
+        );
+        assert_eq!(archive.entries.len(), 2);
+        assert!(archive.contains("ContentItem-1"));
+        assert!(archive.contains("/video/2019/10/Il-Collegio-1.json"));
+        assert!(archive.contains("ContentItem-2"));
+        assert!(!archive.contains(""));
+
+        assert!(!archive.add("ContentItem-2", "/video/2019/10/Il-Collegio-2.json"));
+        assert!(archive.add("ContentItem-3", "/video/2019/10/Il-Collegio-3.json"));
+        assert!(archive.contains("/video/2019/10/Il-Collegio-3.json"));
+
+        let path =
+            std::env::temp_dir().join(format!("raiplay-dl-archive-{}.mp4", std::process::id()));
+        let mut mp4 = Vec::new();
+        for (kind, size) in [(b"ftyp", 16u32), (b"mdat", 24), (b"moov", 12)].iter() {
+            mp4.extend_from_slice(&size.to_be_bytes());
+            mp4.extend_from_slice(*kind);
+            mp4.resize(mp4.len() + *size as usize - 8, 0);
+        }
+        fs::write(&path, &mp4).unwrap();
+        assert!(verify_output(&path).is_ok());
+        fs::write(&path, &mp4[..40]).unwrap();
+        assert!(verify_output(&path).is_err());
+
+        // EBML header vuoto e segmento di 3 byte.
+        let mkv = [
+            0x1a, 0x45, 0xdf, 0xa3, 0x80, 0x18, 0x53, 0x80, 0x67, 0x83, 0, 0, 0,
+        ];
+        fs::write(&path, mkv).unwrap();
+        assert!(verify_output(&path).is_ok());
+        fs::write(&path, &mkv[..12]).unwrap();
+        assert!(verify_output(&path).is_err());
+
+        let mut ts = vec![0; 188 * 3];
+        for packet in ts.chunks_mut(188) {
+            packet[0] = 0x47;
+        }
+        fs::write(&path, &ts).unwrap();
+        assert!(verify_output(&path).is_ok());
+        fs::write(&path, &ts[..300]).unwrap();
+        assert!(verify_output(&path).is_err());
+        ts[188] = 0;
+        fs::write(&path, &ts).unwrap();
+        assert!(verify_output(&path).is_err());
+
+        fs::write(&path, "<html><body>Forbidden</body></html>").unwrap();
+        let html = verify_output(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(html.is_err());
+    }
+}
@@ -0,0 +1,344 @@
+#![warn(clippy::all)]
+
+//! Accesso con un account RaiPlay, necessario per i contenuti con
+//! `login_required`.
+//!
+//! Le credenziali possono essere un token già ottenuto, i cookie esportati
+//! dal browser o email e password, con cui si ottiene un token che viene
+//! salvato su disco e rinnovato quando sta per scadere. La sessione scelta
+//! con [`set_session`] viene allegata a tutte le richieste fatte con i
+//! client di `crate::http`: JSON di RaiPlay, relinker e CDN.
+
+use crate::error::Error;
+use crate::http;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use lazy_static::lazy_static;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, COOKIE};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Nome del file in cui salvare il token se non specificato, nella
+/// directory di cache dell'utente.
+pub const DEFAULT_TOKEN_FILE: &str = "raiplay-dl-token.json";
+
+/// Sottodirectory della cache dell'utente usata da raiplay-dl.
+const CACHE_DIR: &str = "raiplay-dl";
+
+const LOGIN_URL: &str = "https://www.raiplay.it/raisso/login/domain/app/social";
+
+/// Un token che scade entro questo margine viene rinnovato prima di usarlo,
+/// per non farlo scadere a metà download.
+const REFRESH_MARGIN_MINUTES: i64 = 10;
+
+/// Domini a cui appartengono i cookie di RaiPlay.
+const COOKIE_DOMAINS: [&str; 2] = ["raiplay.it", "rai.it"];
+
+lazy_static! {
+    static ref SESSION: RwLock<Session> = RwLock::new(Session::default());
+    static ref ACCOUNT: RwLock<Option<Account>> = RwLock::new(None);
+}
+
+/// Credenziali allegate alle richieste.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Token mandato come `Authorization: Bearer`.
+    pub token: Option<String>,
+    /// Valore dell'header `Cookie`.
+    pub cookies: Option<String>,
+}
+
+impl Session {
+    pub fn is_logged_in(&self) -> bool {
+        self.token.is_some() || self.cookies.is_some()
+    }
+
+    pub fn headers(&self) -> Result<HeaderMap, Error> {
+        let invalid =
+            |what: &str| Error::LoginFailed(format!("{} contains invalid characters", what));
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &self.token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| invalid("token"))?;
+            headers.insert(AUTHORIZATION, value);
+        }
+        if let Some(cookies) = &self.cookies {
+            let value = HeaderValue::from_str(cookies).map_err(|_| invalid("cookie jar"))?;
+            headers.insert(COOKIE, value);
+        }
+        Ok(headers)
+    }
+}
+
+/// Usa `session` per tutte le richieste successive.
+pub fn set_session(session: Session) -> Result<(), Error> {
+    // Gli header vengono controllati qui, così che `client` non possa
+    // fallire per colpa delle credenziali.
+    session.headers()?;
+    *SESSION.write().unwrap() = session;
+    http::reset();
+    Ok(())
+}
+
+/// Sessione in uso.
+pub fn session() -> Session {
+    SESSION.read().unwrap().clone()
+}
+
+/// Email e password con cui rinnovare il token della sessione.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub username: String,
+    pub password: String,
+    /// File in cui salvare il token, come in [`login`].
+    pub cache: PathBuf,
+}
+
+/// Accede con `account` come [`login`], usa il token per la sessione e
+/// ricorda l'account per rinnovarlo con [`refresh`].
+pub async fn log_in(account: Account, mut session: Session) -> Result<(), Error> {
+    session.token = Some(login(&account.username, &account.password, &account.cache).await?);
+    set_session(session)?;
+    *ACCOUNT.write().unwrap() = Some(account);
+    Ok(())
+}
+
+/// Rinnova il token della sessione se scade entro il margine di rinnovo,
+/// accedendo di nuovo con l'account dato a [`log_in`]. Va chiamata prima di
+/// ogni download, perché con `--run-scheduled` tra l'avvio e una
+/// registrazione possono passare giorni. Senza account non fa niente.
+pub async fn refresh() -> Result<(), Error> {
+    let account = match ACCOUNT.read().unwrap().clone() {
+        Some(account) => account,
+        None => return Ok(()),
+    };
+    let session = session();
+    let expires = session.token.as_deref().and_then(token_expiry);
+    if !expires_soon(expires, Utc::now()) {
+        return Ok(());
+    }
+    log_in(account, session).await
+}
+
+/// Se un token con scadenza `expires` va rinnovato prima di usarlo a `now`.
+fn expires_soon(expires: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    expires.is_some_and(|expires| expires <= now + Duration::minutes(REFRESH_MARGIN_MINUTES))
+}
+
+/// Legge un cookie jar in formato Netscape, come quelli esportati dalle
+/// estensioni dei browser, e ne ricava l'header `Cookie` con i cookie di
+/// RaiPlay non ancora scaduti.
+pub fn parse_cookie_jar(content: &str, now: DateTime<Utc>) -> String {
+    content
+        .lines()
+        .filter_map(|line| {
+            // curl segna così i cookie HttpOnly, che vanno tenuti.
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                return None;
+            }
+            let domain = fields[0].trim_start_matches('.');
+            let is_rai = COOKIE_DOMAINS
+                .iter()
+                .any(|rai| domain == *rai || domain.ends_with(&format!(".{}", rai)));
+            // Scadenza 0 vuol dire cookie di sessione.
+            let expires: i64 = fields[4].parse().unwrap_or(0);
+            let expired = expires != 0 && expires < now.timestamp();
+            if !is_rai || expired {
+                return None;
+            }
+            Some(format!("{}={}", fields[5], fields[6]))
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Legge il cookie jar `path` con [`parse_cookie_jar`].
+pub fn load_cookie_jar(path: &Path) -> Result<String, Error> {
+    let cookies = parse_cookie_jar(&fs::read_to_string(path)?, Utc::now());
+    if cookies.is_empty() {
+        return Err(Error::LoginFailed(format!(
+            "no RaiPlay cookies in `{}`",
+            path.display()
+        )));
+    }
+    Ok(cookies)
+}
+
+/// File in cui salvare il token se non specificato: `$XDG_CACHE_HOME`,
+/// `~/.cache` o `%LOCALAPPDATA%`, in una sottodirectory `raiplay-dl`. Se
+/// nessuna è definita si usa la directory corrente.
+pub fn default_token_file() -> PathBuf {
+    let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    let cache = non_empty("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| non_empty("LOCALAPPDATA").map(PathBuf::from));
+    match cache {
+        Some(cache) => cache.join(CACHE_DIR).join(DEFAULT_TOKEN_FILE),
+        None => PathBuf::from(DEFAULT_TOKEN_FILE),
+    }
+}
+
+/// Scadenza di un token JWT, dal campo `exp` del suo payload.
+pub fn token_expiry(token: &str) -> Option<DateTime<Utc>> {
+    #[derive(Deserialize)]
+    struct Claims {
+        exp: i64,
+    }
+
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+    Utc.timestamp_opt(claims.exp, 0).single()
+}
+
+/// Token ottenuto con email e password, salvato su disco per non ripetere
+/// l'accesso a ogni avvio.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedToken {
+    pub username: String,
+    pub token: String,
+    /// Scadenza, se il token la dichiara.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl CachedToken {
+    /// Legge il token da `path`; se il file non esiste non c'è un token.
+    pub fn load(path: &Path) -> Result<Option<CachedToken>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read(path)?;
+        Ok(Some(serde_json::from_slice(&content)?))
+    }
+
+    /// Salva il token in `path`, passando da un file temporaneo come il
+    /// journal dei download. Su Unix il file è leggibile solo dall'utente,
+    /// visto che il token dà accesso all'account.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Se il token è di `username` e si può usare fino a `now` più il
+    /// margine di rinnovo.
+    pub fn is_valid_for(&self, username: &str, now: DateTime<Utc>) -> bool {
+        self.username == username && !expires_soon(self.expires, now)
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    response: String,
+    #[serde(default)]
+    authorization: Option<String>,
+}
+
+/// Accede con `username` e `password` e ritorna il token. Se in `cache`
+/// c'è già un token valido per lo stesso account viene usato quello,
+/// altrimenti il nuovo token viene salvato lì.
+pub async fn login(username: &str, password: &str, cache: &Path) -> Result<String, Error> {
+    if let Some(cached) = CachedToken::load(cache)? {
+        if cached.is_valid_for(username, Utc::now()) {
+            return Ok(cached.token);
+        }
+    }
+
+    let rejected = || {
+        Error::LoginFailed(format!(
+            "RaiPlay rejected the credentials for `{}`",
+            username
+        ))
+    };
+    let resp = http::client()?
+        .post(LOGIN_URL)
+        .form(&[("email", username), ("password", password)])
+        .send()
+        .await?;
+    if resp.status().is_client_error() {
+        return Err(rejected());
+    }
+    let resp: LoginResponse = resp.error_for_status()?.json().await?;
+    let token = match resp.authorization {
+        Some(authorization) if resp.response == "OK" => {
+            authorization.trim_start_matches("Bearer ").to_string()
+        }
+        _ => return Err(rejected()),
+    };
+
+    CachedToken {
+        username: username.to_string(),
+        expires: token_expiry(&token),
+        token: token.clone(),
+    }
+    .save(cache)?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let jar = "# Netscape HTTP Cookie File\n\
+                   .raiplay.it\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+                   #HttpOnly_www.raiplay.it\tFALSE\t/\tTRUE\t1700000000\tauth\tdef\n\
+                   .raiplay.it\tTRUE\t/\tTRUE\t1500000000\told\tghi\n\
+                   .example.com\tTRUE\t/\tFALSE\t0\tother\tjkl\n";
+        assert_eq!(parse_cookie_jar(jar, now), "session=abc; auth=def");
+
+        // {"alg":"HS256"}.{"sub":"raiplay","exp":1600000000}.firma
+        let token = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJyYWlwbGF5IiwiZXhwIjoxNjAwMDAwMDAwfQ.c2ln";
+        assert_eq!(token_expiry(token), Some(now));
+        assert_eq!(token_expiry("not-a-jwt"), None);
+
+        let cached = CachedToken {
+            username: String::from("mario@example.com"),
+            token: token.to_string(),
+            expires: token_expiry(token),
+        };
+        assert!(cached.is_valid_for("mario@example.com", now - Duration::hours(1)));
+        assert!(!cached.is_valid_for("mario@example.com", now - Duration::minutes(5)));
+        assert!(!cached.is_valid_for("luigi@example.com", now - Duration::hours(1)));
+        assert!(expires_soon(Some(now), now - Duration::minutes(5)));
+        assert!(!expires_soon(None, now));
+
+        let dir = std::env::temp_dir().join(format!("raiplay-dl-auth-{}", std::process::id()));
+        let path = dir.join(DEFAULT_TOKEN_FILE);
+        cached.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(CachedToken::load(&path).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
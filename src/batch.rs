@@ -0,0 +1,128 @@
+#![warn(clippy::all)]
+
+//! Download di molti URL di fila, letti dalla riga di comando o da un file
+//! batch, con il riepilogo finale degli esiti.
+
+use crate::error::{Error, EXIT_FAILURE};
+use std::fs;
+use std::io::{self, Read};
+
+/// Legge gli URL di un file batch: uno per riga, ignorando le righe vuote e
+/// i commenti che iniziano con `#` o `;`.
+pub fn parse_urls(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+        .map(String::from)
+        .collect()
+}
+
+/// Legge gli URL dal file batch `path`, o dallo standard input se è `-`.
+pub fn read_urls(path: &str) -> Result<Vec<String>, Error> {
+    let content = if path == "-" {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        content
+    } else {
+        fs::read_to_string(path)?
+    };
+    Ok(parse_urls(&content))
+}
+
+/// Esito del download di un URL.
+#[derive(Debug)]
+pub enum Outcome {
+    Downloaded,
+    /// Non scaricato, per il motivo indicato.
+    Skipped(String),
+    Failed(Error),
+}
+
+/// Esiti degli URL di un batch, nell'ordine in cui sono stati scaricati.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub entries: Vec<(String, Outcome)>,
+}
+
+impl Summary {
+    pub fn add(&mut self, url: &str, outcome: Outcome) {
+        self.entries.push((url.to_string(), outcome));
+    }
+
+    /// Numero di esiti per cui `filter` è vera.
+    pub fn count(&self, filter: impl Fn(&Outcome) -> bool) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, outcome)| filter(outcome))
+            .count()
+    }
+
+    /// Codice di uscita del batch: 0 se nessun URL è fallito, il codice
+    /// dell'errore se sono falliti tutti per lo stesso motivo, altrimenti
+    /// [`EXIT_FAILURE`].
+    pub fn exit_code(&self) -> i32 {
+        let mut codes = self
+            .entries
+            .iter()
+            .filter_map(|(_, outcome)| match outcome {
+                Outcome::Failed(err) => Some(err.exit_code()),
+                _ => None,
+            });
+        match codes.next() {
+            None => 0,
+            Some(code) if codes.all(|other| other == code) => code,
+            Some(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// Esito del batch: [`Error::BatchFailed`] con il codice di
+    /// [`Summary::exit_code`] se qualche URL è fallito.
+    pub fn result(&self) -> Result<(), Error> {
+        match self.exit_code() {
+            0 => Ok(()),
+            code => Err(Error::BatchFailed {
+                failed: self.count(|outcome| matches!(outcome, Outcome::Failed(_))),
+                code,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let urls = parse_urls(
+            "# episodi della settimana\n\
+             https://www.raiplay.it/video/a.html\n\
+             \n\
+             \t https://www.raiplay.it/video/b.html \n\
+             ; vecchio\n",
+        );
+        assert_eq!(
+            urls,
+            [
+                "https://www.raiplay.it/video/a.html",
+                "https://www.raiplay.it/video/b.html"
+            ]
+        );
+
+        let mut summary = Summary::default();
+        summary.add(&urls[0], Outcome::Downloaded);
+        summary.add(&urls[1], Outcome::Skipped(String::from("duplicato")));
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!(
+            summary.count(|outcome| matches!(outcome, Outcome::Skipped(_))),
+            1
+        );
+
+        summary.add("a", Outcome::Failed(Error::InvalidUrl(String::from("a"))));
+        assert_eq!(summary.exit_code(), 2);
+        assert_eq!(summary.result().unwrap_err().exit_code(), 2);
+        summary.add("b", Outcome::Failed(Error::Cancelled));
+        assert_eq!(summary.exit_code(), EXIT_FAILURE);
+    }
+}
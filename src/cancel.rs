@@ -0,0 +1,110 @@
+#![warn(clippy::all)]
+
+//! Interruzione dei download con Ctrl-C.
+//!
+//! In tokio 0.2 la prima chiamata a `tokio::signal::ctrl_c` installa un
+//! gestore di SIGINT per tutto il processo, che non viene più rimosso: da lì
+//! in poi Ctrl-C non termina il programma. Per questo il segnale va
+//! ascoltato una volta sola, con [`ctrl_c`], e il [`CancelToken`] ottenuto
+//! passato a tutto ciò che deve potersi interrompere.
+
+use crate::error::Error;
+use std::fmt;
+use std::future::Future;
+use tokio::sync::watch;
+
+/// Segnala che l'utente ha chiesto di interrompere. Si può clonare e
+/// passare a più download; quello di default non scatta mai.
+#[derive(Clone)]
+pub struct CancelToken(watch::Receiver<bool>);
+
+impl CancelToken {
+    /// Token che non scatta mai, per chi non vuole interrompere i download.
+    pub fn never() -> CancelToken {
+        let (_, receiver) = watch::channel(false);
+        CancelToken(receiver)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// [`Error::Cancelled`] se è già stato chiesto di interrompere.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Si completa quando viene chiesto di interrompere.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.0.clone();
+        while !*receiver.borrow() {
+            // Senza più nessuno che possa interrompere si aspetta per sempre.
+            if receiver.recv().await.is_none() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Esegue `future`, ma ritorna [`Error::Cancelled`] non appena viene
+    /// chiesto di interrompere.
+    pub async fn run<T>(&self, future: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        tokio::select! {
+            result = future => result,
+            _ = self.cancelled() => Err(Error::Cancelled),
+        }
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> CancelToken {
+        CancelToken::never()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CancelToken")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Inizia ad ascoltare Ctrl-C e ritorna il token che scatta alla prima
+/// pressione. Va chiamata una volta sola, all'avvio, dentro il runtime di
+/// tokio.
+pub fn ctrl_c() -> CancelToken {
+    let (sender, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = sender.broadcast(true);
+        }
+        // Il sender resta vivo, così che i token non lo credano sparito.
+        futures::future::pending::<()>().await;
+    });
+    CancelToken(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        futures::executor::block_on(async {
+            let never = CancelToken::never();
+            assert!(never.check().is_ok());
+            assert_eq!(never.run(async { Ok(1) }).await.unwrap(), 1);
+
+            let (sender, receiver) = watch::channel(false);
+            let token = CancelToken(receiver);
+            sender.broadcast(true).unwrap();
+            assert!(matches!(token.check(), Err(Error::Cancelled)));
+            let pending = futures::future::pending::<Result<(), Error>>();
+            assert!(matches!(token.run(pending).await, Err(Error::Cancelled)));
+        });
+    }
+}
@@ -0,0 +1,131 @@
+#![warn(clippy::all)]
+
+use crate::error::Error;
+use aes::Aes128;
+use block_modes::block_padding::Pkcs7;
+use block_modes::{BlockMode, Cbc};
+use std::fmt;
+
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+
+/// Unico metodo di cifratura HLS supportato.
+pub const METHOD_AES_128: &str = "AES-128";
+
+/// Metodo usato quando lo stream non è cifrato.
+pub const METHOD_NONE: &str = "NONE";
+
+/// Lo stream usa un metodo di cifratura (SAMPLE-AES, DRM, ...) che non
+/// possiamo decifrare.
+#[derive(Debug)]
+pub struct UnsupportedEncryptionError {
+    pub method: String,
+    pub keyformat: Option<String>,
+}
+
+impl fmt::Display for UnsupportedEncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.keyformat {
+            Some(keyformat) => write!(
+                f,
+                "encryption method `{}` with key format `{}` is not supported (DRM protected stream)",
+                self.method, keyformat
+            ),
+            None => write!(f, "encryption method `{}` is not supported", self.method),
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedEncryptionError {}
+
+/// La chiave o l'IV di un segmento non sono validi.
+#[derive(Debug)]
+pub struct InvalidKeyError(pub String);
+
+impl fmt::Display for InvalidKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid AES-128 key: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidKeyError {}
+
+/// Converte i byte scaricati da un URI di chiave in una chiave AES-128.
+pub fn parse_key(data: &[u8]) -> Result<[u8; 16], Error> {
+    if data.len() != 16 {
+        return Err(Error::from(InvalidKeyError(format!(
+            "expected 16 bytes, got {}",
+            data.len()
+        ))));
+    }
+    let mut key = [0; 16];
+    key.copy_from_slice(data);
+    Ok(key)
+}
+
+/// Ritorna l'IV di un segmento: quello esplicito dell'attributo IV (in
+/// esadecimale, `0x...`) oppure, come da specifica, il media sequence number
+/// del segmento in big-endian.
+pub fn segment_iv(iv: Option<&str>, media_sequence: u64) -> Result<[u8; 16], Error> {
+    let mut out = [0; 16];
+
+    match iv {
+        Some(iv) => {
+            let hex = iv.trim_start_matches("0x").trim_start_matches("0X");
+            if hex.len() != 32 {
+                return Err(Error::from(InvalidKeyError(format!(
+                    "IV `{}` is not 128 bits long",
+                    iv
+                ))));
+            }
+            let not_hex = || InvalidKeyError(format!("IV `{}` is not hexadecimal", iv));
+            for (i, byte) in out.iter_mut().enumerate() {
+                let digits = hex
+                    .get(i * 2..i * 2 + 2)
+                    .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .ok_or_else(not_hex)?;
+                *byte = u8::from_str_radix(digits, 16).map_err(|_| not_hex())?;
+            }
+        }
+        None => out[8..].copy_from_slice(&media_sequence.to_be_bytes()),
+    }
+
+    Ok(out)
+}
+
+/// Decifra un segmento cifrato con AES-128-CBC e padding PKCS7.
+pub fn decrypt_aes128(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, Error> {
+    let cipher = Aes128Cbc::new_from_slices(key, iv)
+        .map_err(|_| InvalidKeyError(String::from("wrong key or IV length")))?;
+    cipher
+        .decrypt_vec(data)
+        .map_err(|_| Error::from(InvalidKeyError(String::from("segment decryption failed"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(
+            segment_iv(None, 258).unwrap(),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2]
+        );
+        assert_eq!(
+            segment_iv(Some("0x000102030405060708090A0B0C0D0E0F"), 0).unwrap(),
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        );
+        assert!(segment_iv(Some("0x0001"), 0).is_err());
+        assert!(segment_iv(Some(&format!("0xa{}a", "é".repeat(15))), 0).is_err());
+        assert!(segment_iv(Some("0x+0010203040506070809000102030405"), 0).is_err());
+        assert!(parse_key(&[0; 15]).is_err());
+
+        let key = [7; 16];
+        let iv = segment_iv(None, 42).unwrap();
+        let plain = b"segmento mpeg-ts di prova".to_vec();
+        let encrypted = Aes128Cbc::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_vec(&plain);
+        assert_eq!(decrypt_aes128(&encrypted, &key, &iv).unwrap(), plain);
+    }
+}
@@ -1,79 +1,88 @@
 #![warn(clippy::all)]
 
-use console::style;
-use failure::Error;
-use indicatif::{ProgressBar, ProgressStyle};
+//! Download di un singolo file, come l'MP4 di un video.
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+use crate::http;
+use crate::progress::{ProgressEvent, ProgressReporter};
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-/// Scarica un file usando `url`.
-pub async fn download(url: &str, verbose: Option<bool>) -> Result<(), Error> {
-    let verbose = verbose.unwrap_or(false);
-    let url = reqwest::Url::parse(url)?;
+/// Scarica un file usando `url`, salvandolo con il nome che ha nell'URL.
+/// Ritorna il percorso del file salvato.
+pub async fn download(
+    url: &str,
+    cancel: &CancelToken,
+    progress: &dyn ProgressReporter,
+) -> Result<PathBuf, Error> {
+    let parsed_url = reqwest::Url::parse(url)?;
 
-    let file_name = match url
+    let file_name = match parsed_url
         .path_segments()
-        .and_then(|segs| segs.last().map(String::from))
+        .and_then(|mut segs| segs.next_back().map(String::from))
     {
         Some(fname) => fname,
         None => Uuid::new_v4().to_string(),
     };
-    if verbose {
-        println!("\nSalvo in: {}", style(&file_name).green());
-    }
+    let path = PathBuf::from(file_name);
+    download_to(url, &path, cancel, progress).await?;
+    Ok(path)
+}
+
+/// Scarica un file usando `url` e lo salva in `path`, fermandosi con
+/// [`Error::Cancelled`] se scatta `cancel`.
+pub async fn download_to(
+    url: &str,
+    path: &Path,
+    cancel: &CancelToken,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    let url = reqwest::Url::parse(url)?;
 
     let mut resp = {
-        let client = reqwest::Client::new();
-        client.get(url).send().await?
+        let client = http::client()?;
+        client.get(url).send().await?.error_for_status()?
     };
-    let headers = resp.headers();
-
     let ct_len = {
-        let ct_len = headers.get(reqwest::header::CONTENT_LENGTH);
+        let ct_len = resp.headers().get(reqwest::header::CONTENT_LENGTH);
         let ct_len_str = ct_len.and_then(|len| len.to_str().ok());
         ct_len_str.and_then(|x| x.parse::<u64>().ok())
     };
-    if verbose {
-        match ct_len {
-            Some(len) => println!(
-                "Content-Length: {} ({})",
-                style(len).green(),
-                style(indicatif::HumanBytes(len)).cyan()
-            ),
-            None => println!("Content-Length: {}", style("unknown").red()),
-        }
-    }
-
-    let ct_type = headers
-        .get(reqwest::header::CONTENT_TYPE)
-        .and_then(|t| t.to_str().ok());
-    if verbose {
-        match ct_type {
-            Some(t) => println!("Content-Type: {}", style(t).green(),),
-            None => println!("Content-Type: {}", style("unknown").red()),
-        }
-    }
+    progress.report(ProgressEvent::DownloadStarted {
+        path,
+        segments: None,
+        bytes: ct_len,
+        resumed: 0,
+    });
 
-    let progress_bar = match ct_len {
-        Some(len) => ProgressBar::new(len).with_style(ProgressStyle::default_bar().template(
-            "{wide_bar:.cyan/blue} {bytes:.green} / {total_bytes} [{elapsed} .. {eta:.cyan}]",
-        )),
-        None => ProgressBar::new(0).with_style(
-            ProgressStyle::default_spinner()
-                .template("[{elapsed_precise}] {spinner:.green} {bytes:.cyan}"),
-        ),
-    };
-    let mut file = File::create(&file_name)?;
-
-    while let Some(ref chunk) = resp.chunk().await? {
-        file.write(chunk)?;
-        progress_bar.inc(chunk.len() as u64);
+    let mut file = File::create(path)?;
+    let mut written = 0;
+    let interrupt = cancel.cancelled();
+    tokio::pin!(interrupt);
+    loop {
+        let chunk = tokio::select! {
+            chunk = resp.chunk() => chunk.map_err(Error::from),
+            _ = &mut interrupt => Err(Error::Cancelled),
+        };
+        let chunk = match chunk {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => {
+                progress.report(ProgressEvent::Aborted);
+                return Err(err);
+            }
+        };
+        file.write_all(&chunk)?;
+        written += chunk.len() as u64;
+        progress.report(ProgressEvent::BytesWritten {
+            written,
+            total: ct_len,
+        });
     }
 
-    progress_bar.finish();
-    if verbose {
-        println!("File salvato in {}", style(file_name).green());
-    }
+    progress.report(ProgressEvent::Finished { path });
     Ok(())
 }
@@ -0,0 +1,236 @@
+#![warn(clippy::all)]
+
+//! Errore unico della libreria. Ogni caso ha un codice di uscita diverso,
+//! così che gli script che usano la CLI possano distinguerli.
+
+use crate::crypto::{InvalidKeyError, UnsupportedEncryptionError};
+use crate::remux::{InvalidStreamError, UnsupportedContainerError};
+use crate::retry::{RetriesExhaustedError, TokenExpiredError};
+use crate::sound::UnsupportedAudioError;
+use crate::subtitles::InvalidSubtitlesError;
+use std::fmt;
+use std::io;
+
+/// Codice di uscita quando non c'è niente da scaricare, e per più errori
+/// diversi in un batch.
+pub const EXIT_FAILURE: i32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// L'URL non è di una pagina supportata.
+    InvalidUrl(String),
+    /// Il server ha risposto `status` per `url`.
+    Http {
+        url: String,
+        status: u16,
+    },
+    /// Connessione fallita o interrotta, anche dopo tutti i tentativi.
+    Network(String),
+    /// Il CDN rifiuta i segmenti perché il token nel loro URL è scaduto.
+    TokenExpired(TokenExpiredError),
+    /// Il contenuto è visibile solo dall'Italia.
+    GeoBlocked(String),
+    /// Lo stream è protetto da un DRM che non possiamo decifrare.
+    DrmProtected(UnsupportedEncryptionError),
+    /// Il contenuto richiede l'accesso con un account RaiPlay.
+    LoginRequired(String),
+    /// Accesso a RaiPlay fallito o credenziali non valide.
+    LoginFailed(String),
+    /// Playlist M3U8 o chiave di cifratura non valide.
+    Playlist(String),
+    /// JSON di RaiPlay non valido.
+    Json(String),
+    /// File multimediale o sottotitoli non validi o in un formato non
+    /// supportato.
+    Media(String),
+    Io(io::Error),
+    /// Niente corrisponde ai filtri o al selettore richiesti.
+    NothingToDownload(String),
+    /// Opzioni della riga di comando che non si possono usare insieme o con
+    /// questo URL.
+    Usage(String),
+    /// Alcuni download di un batch sono falliti; `code` è il codice di
+    /// uscita calcolato dal riepilogo.
+    BatchFailed {
+        failed: usize,
+        code: i32,
+    },
+    /// Download interrotto con Ctrl-C.
+    Cancelled,
+}
+
+impl Error {
+    /// Codice di uscita della CLI per questo errore.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidUrl(_) => 2,
+            Error::Http { .. } => 3,
+            Error::Network(_) => 4,
+            Error::TokenExpired(_) => 5,
+            Error::GeoBlocked(_) => 6,
+            Error::DrmProtected(_) => 7,
+            Error::LoginRequired(_) => 8,
+            Error::Playlist(_) | Error::Json(_) => 9,
+            Error::Media(_) => 10,
+            Error::Io(_) => 11,
+            Error::LoginFailed(_) => 12,
+            Error::NothingToDownload(_) | Error::Usage(_) => EXIT_FAILURE,
+            Error::BatchFailed { code, .. } => *code,
+            // Come le shell per un processo interrotto da SIGINT.
+            Error::Cancelled => 130,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "URL `{}` is not valid", url),
+            Error::Http { url, status } => write!(f, "HTTP status {} for `{}`", status, url),
+            Error::Network(cause) => write!(f, "network error: {}", cause),
+            Error::TokenExpired(err) => err.fmt(f),
+            Error::GeoBlocked(url) => {
+                write!(f, "`{}` is geo-restricted and only available in Italy", url)
+            }
+            Error::DrmProtected(err) => err.fmt(f),
+            Error::LoginRequired(url) => write!(f, "`{}` requires a RaiPlay login", url),
+            Error::LoginFailed(cause) => write!(f, "RaiPlay login failed: {}", cause),
+            Error::Playlist(cause) => write!(f, "M3U8 is not valid: {}", cause),
+            Error::Json(cause) => write!(f, "JSON is not valid: {}", cause),
+            Error::Media(cause) => cause.fmt(f),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::NothingToDownload(cause) => write!(f, "nothing to download: {}", cause),
+            Error::Usage(cause) => cause.fmt(f),
+            Error::BatchFailed { failed, .. } => write!(f, "{} downloads failed", failed),
+            Error::Cancelled => write!(f, "download cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        match err.status() {
+            Some(status) => Error::Http {
+                url: err.url().map(|url| url.to_string()).unwrap_or_default(),
+                status: status.as_u16(),
+            },
+            None if err.is_decode() => Error::Json(err.to_string()),
+            None => Error::Network(err.to_string()),
+        }
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Error {
+        Error::InvalidUrl(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err.to_string())
+    }
+}
+
+impl From<TokenExpiredError> for Error {
+    fn from(err: TokenExpiredError) -> Error {
+        Error::TokenExpired(err)
+    }
+}
+
+impl From<RetriesExhaustedError> for Error {
+    fn from(err: RetriesExhaustedError) -> Error {
+        Error::Network(err.to_string())
+    }
+}
+
+impl From<UnsupportedEncryptionError> for Error {
+    fn from(err: UnsupportedEncryptionError) -> Error {
+        Error::DrmProtected(err)
+    }
+}
+
+impl From<InvalidKeyError> for Error {
+    fn from(err: InvalidKeyError) -> Error {
+        Error::Playlist(err.to_string())
+    }
+}
+
+impl From<InvalidStreamError> for Error {
+    fn from(err: InvalidStreamError) -> Error {
+        Error::Media(err.to_string())
+    }
+}
+
+impl From<UnsupportedContainerError> for Error {
+    fn from(err: UnsupportedContainerError) -> Error {
+        Error::Media(err.to_string())
+    }
+}
+
+impl From<UnsupportedAudioError> for Error {
+    fn from(err: UnsupportedAudioError) -> Error {
+        Error::Media(err.to_string())
+    }
+}
+
+impl From<InvalidSubtitlesError> for Error {
+    fn from(err: InvalidSubtitlesError) -> Error {
+        Error::Media(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let codes: Vec<i32> = [
+            Error::InvalidUrl(String::new()),
+            Error::Http {
+                url: String::new(),
+                status: 500,
+            },
+            Error::Network(String::new()),
+            Error::GeoBlocked(String::new()),
+            Error::LoginRequired(String::new()),
+            Error::LoginFailed(String::new()),
+            Error::Playlist(String::new()),
+            Error::Media(String::new()),
+            Error::Io(io::Error::from(io::ErrorKind::NotFound)),
+            Error::NothingToDownload(String::new()),
+            Error::Cancelled,
+        ]
+        .iter()
+        .map(Error::exit_code)
+        .collect();
+        let mut unique = codes.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+        assert!(!codes.contains(&0));
+
+        let err = Error::from(InvalidStreamError("truncated PSI section"));
+        assert_eq!(err.exit_code(), 10);
+        assert_eq!(
+            err.to_string(),
+            "MPEG-TS stream is not valid: truncated PSI section"
+        );
+    }
+}
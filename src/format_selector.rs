@@ -0,0 +1,300 @@
+#![warn(clippy::all)]
+
+//! Selettore della variante da scaricare, ad esempio `best`, `720p`,
+//! `height<=720`, `bandwidth>2000000` o `worst[height>=480]`. Più
+//! alternative separate da `/` vengono provate in ordine, come in
+//! `720p/best`. Le varianti solo audio si scelgono solo con `bestaudio` e
+//! `worstaudio`, o se la playlist non ha altro.
+
+use crate::api::M3u8VideoVariant;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct InvalidSelectorError(pub String);
+
+impl fmt::Display for InvalidSelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Format selector is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSelectorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Width,
+    Height,
+    Bandwidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl Op {
+    fn matches(self, a: u64, b: u64) -> bool {
+        match self {
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Ge => a >= b,
+            Op::Gt => a > b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    field: Field,
+    op: Op,
+    value: u64,
+}
+
+impl Filter {
+    fn matches(&self, variant: &M3u8VideoVariant) -> bool {
+        let value = match self.field {
+            Field::Width => dimensions(variant).map(|(width, _)| width),
+            Field::Height => dimensions(variant).map(|(_, height)| height),
+            Field::Bandwidth => Some(variant.bandwidth),
+        };
+        value.is_some_and(|value| self.op.matches(value, self.value))
+    }
+}
+
+/// Una delle alternative del selettore: la variante migliore (o peggiore)
+/// tra quelle che soddisfano tutti i filtri.
+#[derive(Debug, Clone, PartialEq)]
+struct Alternative {
+    worst: bool,
+    /// Se scegliere tra le varianti solo audio invece che tra i video.
+    audio: bool,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSelector {
+    alternatives: Vec<Alternative>,
+}
+
+fn dimensions(variant: &M3u8VideoVariant) -> Option<(u64, u64)> {
+    variant
+        .resolution
+        .map(|res| (u64::from(res.width), u64::from(res.height)))
+}
+
+/// Legge un numero con un eventuale suffisso `k` o `M`.
+fn parse_value(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1_000),
+        'm' | 'M' => (&value[..value.len() - 1], 1_000_000),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn parse_filter(filter: &str) -> Result<Filter, InvalidSelectorError> {
+    let invalid = || InvalidSelectorError(format!("invalid filter `{}`", filter));
+    let filter = filter.trim();
+
+    // `720p` equivale a `height=720`.
+    if let Some(height) = filter.strip_suffix('p') {
+        if let Ok(value) = height.parse() {
+            return Ok(Filter {
+                field: Field::Height,
+                op: Op::Eq,
+                value,
+            });
+        }
+    }
+
+    let start = filter.find(['<', '>', '=', '!']).ok_or_else(invalid)?;
+    let (field, rest) = filter.split_at(start);
+    let (op, value) = [
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("!=", Op::Ne),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+        ("=", Op::Eq),
+    ]
+    .iter()
+    .find_map(|(symbol, op)| rest.strip_prefix(symbol).map(|value| (*op, value)))
+    .ok_or_else(invalid)?;
+
+    let field = match field.trim() {
+        "width" | "w" => Field::Width,
+        "height" | "h" | "res" => Field::Height,
+        "bandwidth" | "tbr" | "br" => Field::Bandwidth,
+        _ => return Err(invalid()),
+    };
+    Ok(Filter {
+        field,
+        op,
+        value: parse_value(value).ok_or_else(invalid)?,
+    })
+}
+
+fn parse_alternative(alternative: &str) -> Result<Alternative, InvalidSelectorError> {
+    let alternative = alternative.trim();
+    let (head, mut rest) = match alternative.find('[') {
+        Some(i) => alternative.split_at(i),
+        None => (alternative, ""),
+    };
+
+    let mut parsed = Alternative {
+        worst: false,
+        audio: false,
+        filters: Vec::new(),
+    };
+    match head.trim() {
+        "best" => {}
+        "worst" => parsed.worst = true,
+        "bestaudio" => parsed.audio = true,
+        "worstaudio" => {
+            parsed.worst = true;
+            parsed.audio = true;
+        }
+        "" if !rest.is_empty() => {}
+        filter => parsed.filters.push(parse_filter(filter)?),
+    }
+
+    // Filtri aggiuntivi tra parentesi quadre, come in `best[height<=720]`.
+    while !rest.is_empty() {
+        let end = match (rest.starts_with('['), rest.find(']')) {
+            (true, Some(end)) => end,
+            _ => {
+                return Err(InvalidSelectorError(format!(
+                    "unbalanced brackets in `{}`",
+                    alternative
+                )))
+            }
+        };
+        parsed.filters.push(parse_filter(&rest[1..end])?);
+        rest = rest[end + 1..].trim_start();
+    }
+
+    Ok(parsed)
+}
+
+impl FromStr for FormatSelector {
+    type Err = InvalidSelectorError;
+
+    fn from_str(selector: &str) -> Result<FormatSelector, InvalidSelectorError> {
+        if selector.trim().is_empty() {
+            return Err(InvalidSelectorError(String::from("empty selector")));
+        }
+        Ok(FormatSelector {
+            alternatives: selector
+                .split('/')
+                .map(parse_alternative)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Default for FormatSelector {
+    /// Selettore `best`.
+    fn default() -> FormatSelector {
+        FormatSelector {
+            alternatives: vec![Alternative {
+                worst: false,
+                audio: false,
+                filters: Vec::new(),
+            }],
+        }
+    }
+}
+
+impl FormatSelector {
+    /// Ritorna l'indice della variante scelta, `None` se nessuna
+    /// alternativa è soddisfatta.
+    pub fn select(&self, variants: &[M3u8VideoVariant]) -> Option<usize> {
+        let has_video = variants.iter().any(|variant| !variant.is_audio_only());
+        self.alternatives.iter().find_map(|alternative| {
+            let candidates = variants.iter().enumerate().filter(|(_, variant)| {
+                let audio = variant.is_audio_only();
+                let kind = if alternative.audio {
+                    audio
+                } else {
+                    !audio || !has_video
+                };
+                kind && alternative.filters.iter().all(|f| f.matches(variant))
+            });
+            let cmp = |a: &(usize, &M3u8VideoVariant), b: &(usize, &M3u8VideoVariant)| {
+                a.1.cmp_quality(b.1)
+            };
+            if alternative.worst {
+                candidates.min_by(cmp)
+            } else {
+                candidates.max_by(cmp)
+            }
+            .map(|(i, _)| i)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Resolution;
+
+    #[test]
+    fn test() {
+        let variants: Vec<M3u8VideoVariant> = [
+            (Some((1280, 720)), 1_800_000),
+            (Some((640, 360)), 600_000),
+            (Some((1920, 1080)), 3_200_000),
+            (Some((1280, 720)), 2_400_000),
+            (None, 128_000),
+        ]
+        .iter()
+        .map(|&(resolution, bandwidth)| {
+            let mut variant = M3u8VideoVariant::new(
+                String::new(),
+                bandwidth,
+                resolution.map(|(width, height)| Resolution { width, height }),
+                Vec::new(),
+            );
+            if resolution.is_none() {
+                variant.codecs = vec![String::from("mp4a.40.2")];
+            }
+            variant
+        })
+        .collect();
+        let select = |selector: &str| {
+            selector
+                .parse::<FormatSelector>()
+                .unwrap()
+                .select(&variants)
+        };
+
+        assert_eq!(select("best"), Some(2));
+        assert_eq!(select("worst"), Some(1));
+        assert_eq!(select("bestaudio"), Some(4));
+        assert_eq!(select("worstaudio[bandwidth>200k]/best"), Some(2));
+        assert_eq!(select("worst[height>0]"), Some(1));
+        assert_eq!(select("720p"), Some(3));
+        assert_eq!(select("worst[height=720]"), Some(0));
+        assert_eq!(select("height<=720"), Some(3));
+        assert_eq!(select("bandwidth>2M[height<1080]"), Some(3));
+        assert_eq!(select("bandwidth<100k"), None);
+        assert_eq!(select("480p/360p/best"), Some(1));
+        let audio = FormatSelector::default().select(&variants[4..]);
+        assert_eq!(audio, Some(0));
+        assert!("height~720".parse::<FormatSelector>().is_err());
+        assert!("best[height<720".parse::<FormatSelector>().is_err());
+        assert!("".parse::<FormatSelector>().is_err());
+        assert!("bandwidth>99999999999999999M"
+            .parse::<FormatSelector>()
+            .is_err());
+    }
+}
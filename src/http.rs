@@ -0,0 +1,54 @@
+#![warn(clippy::all)]
+
+//! Client HTTP condivisi da tutte le richieste, così che scaricando molti
+//! URL di fila le connessioni verso RaiPlay e il CDN vengano riusate.
+
+use crate::api::SEGMENT_TIMEOUT;
+use crate::auth;
+use crate::error::Error;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/80.0.3987.106 Safari/537.36";
+
+struct Clients {
+    client: reqwest::Client,
+    segments: reqwest::Client,
+}
+
+lazy_static! {
+    static ref CLIENTS: Mutex<Option<Clients>> = Mutex::new(None);
+}
+
+fn with_clients<T>(f: impl FnOnce(&Clients) -> T) -> Result<T, Error> {
+    let mut clients = CLIENTS.lock().unwrap();
+    if clients.is_none() {
+        let builder = || {
+            reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .default_headers(auth::session().headers().unwrap_or_default())
+        };
+        *clients = Some(Clients {
+            client: builder().build()?,
+            segments: builder().timeout(SEGMENT_TIMEOUT).build()?,
+        });
+    }
+    Ok(f(clients.as_ref().unwrap()))
+}
+
+/// Client per le richieste a RaiPlay e al relinker, con le credenziali
+/// della sessione in uso.
+pub fn client() -> Result<reqwest::Client, Error> {
+    with_clients(|clients| clients.client.clone())
+}
+
+/// Come [`client`], ma ogni richiesta fallisce dopo [`SEGMENT_TIMEOUT`].
+pub fn segment_client() -> Result<reqwest::Client, Error> {
+    with_clients(|clients| clients.segments.clone())
+}
+
+/// Scarta i client, che verranno ricreati con la nuova sessione alla
+/// prossima richiesta.
+pub fn reset() {
+    *CLIENTS.lock().unwrap() = None;
+}
@@ -0,0 +1,265 @@
+#![warn(clippy::all)]
+
+//! Immagini dei video e dei programmi: locandine, sfondi e loghi di
+//! [`Images`]. RaiPlay le indica con percorsi relativi al sito che
+//! contengono il segnaposto `[RESOLUTION]`, da sostituire con la dimensione
+//! voluta, come `1920x1080`.
+
+use crate::error::Error;
+use crate::http;
+use crate::models::video::{Images, RaiPlayVideo};
+use crate::progress::{ProgressEvent, ProgressReporter};
+use crate::remux::Artwork;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Dominio rispetto a cui risolvere le immagini di RaiPlay.
+pub const RAI_PLAY_BASE_URL: &str = "https://www.raiplay.it/";
+
+const RESOLUTION_PLACEHOLDER: &str = "[RESOLUTION]";
+
+lazy_static! {
+    static ref RESOLUTION_RE: Regex = Regex::new(r"^\d+x\d+$").unwrap();
+}
+
+/// Se `resolution` è una dimensione come `1920x1080`.
+pub fn is_valid_resolution(resolution: &str) -> bool {
+    RESOLUTION_RE.is_match(resolution)
+}
+
+#[derive(Debug)]
+pub struct InvalidImageKindError(pub String);
+
+impl fmt::Display for InvalidImageKindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Image kind is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidImageKindError {}
+
+/// Una delle immagini di [`Images`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageKind {
+    Landscape,
+    Portrait,
+    Square,
+    Landscape43,
+    Portrait43,
+    PortraitLogo,
+    LandscapeLogo,
+}
+
+impl ImageKind {
+    pub const ALL: [ImageKind; 7] = [
+        ImageKind::Landscape,
+        ImageKind::Portrait,
+        ImageKind::Square,
+        ImageKind::Landscape43,
+        ImageKind::Portrait43,
+        ImageKind::PortraitLogo,
+        ImageKind::LandscapeLogo,
+    ];
+
+    /// Nome del campo in [`Images`], usato anche nella riga di comando e nei
+    /// nomi dei file.
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageKind::Landscape => "landscape",
+            ImageKind::Portrait => "portrait",
+            ImageKind::Square => "square",
+            ImageKind::Landscape43 => "landscape43",
+            ImageKind::Portrait43 => "portrait43",
+            ImageKind::PortraitLogo => "portrait_logo",
+            ImageKind::LandscapeLogo => "landscape_logo",
+        }
+    }
+
+    /// Dimensione richiesta se non se ne sceglie un'altra, con le
+    /// proporzioni dell'immagine.
+    pub fn default_resolution(self) -> &'static str {
+        match self {
+            ImageKind::Landscape | ImageKind::LandscapeLogo => "1920x1080",
+            ImageKind::Portrait | ImageKind::PortraitLogo => "1080x1920",
+            ImageKind::Square => "1200x1200",
+            ImageKind::Landscape43 => "1600x1200",
+            ImageKind::Portrait43 => "1200x1600",
+        }
+    }
+
+    pub fn path(self, images: &Images) -> &str {
+        match self {
+            ImageKind::Landscape => &images.landscape,
+            ImageKind::Portrait => &images.portrait,
+            ImageKind::Square => &images.square,
+            ImageKind::Landscape43 => &images.landscape43,
+            ImageKind::Portrait43 => &images.portrait43,
+            ImageKind::PortraitLogo => &images.portrait_logo,
+            ImageKind::LandscapeLogo => &images.landscape_logo,
+        }
+    }
+}
+
+impl FromStr for ImageKind {
+    type Err = InvalidImageKindError;
+
+    fn from_str(s: &str) -> Result<ImageKind, InvalidImageKindError> {
+        ImageKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| InvalidImageKindError(s.to_string()))
+    }
+}
+
+/// URL assoluto dell'immagine `path`, relativo a `base_url`, con
+/// `resolution` al posto del segnaposto `[RESOLUTION]`. `None` se il
+/// percorso è vuoto.
+pub fn resolve(base_url: &str, path: &str, resolution: &str) -> Option<reqwest::Url> {
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+    let path = path.replace(RESOLUTION_PLACEHOLDER, resolution);
+    reqwest::Url::parse(base_url).ok()?.join(&path).ok()
+}
+
+/// URL dell'immagine `kind` di un video di RaiPlay, o del suo programma se
+/// il video non la ha. Senza `resolution` si usa quella predefinita del
+/// tipo di immagine.
+pub fn video_image_url(
+    video: &RaiPlayVideo,
+    kind: ImageKind,
+    resolution: Option<&str>,
+) -> Option<reqwest::Url> {
+    let resolution = resolution.unwrap_or_else(|| kind.default_resolution());
+    resolve(RAI_PLAY_BASE_URL, kind.path(&video.images), resolution).or_else(|| {
+        resolve(
+            RAI_PLAY_BASE_URL,
+            kind.path(&video.program_info.images),
+            resolution,
+        )
+    })
+}
+
+pub async fn fetch(url: &reqwest::Url) -> Result<Vec<u8>, Error> {
+    let resp = http::client()?
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// Scarica l'immagine `url` da incorporare come copertina. Un errore non
+/// interrompe il download, e viene solo segnalato.
+pub async fn fetch_artwork(url: &reqwest::Url, progress: &dyn ProgressReporter) -> Option<Artwork> {
+    match fetch(url).await {
+        Ok(data) => {
+            let artwork = Artwork::from_bytes(data);
+            if artwork.is_none() {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "La copertina {} non è un JPEG o un PNG",
+                    url
+                )));
+            }
+            artwork
+        }
+        Err(err) => {
+            progress.report(ProgressEvent::Warning(&format!(
+                "Non sono riuscito a scaricare la copertina: {}",
+                err
+            )));
+            None
+        }
+    }
+}
+
+/// Estensione del file per l'immagine `url`, presa dal suo percorso.
+pub fn extension(url: &reqwest::Url) -> &str {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+        .filter(|ext| !ext.is_empty() && ext.len() <= 4)
+        .unwrap_or("jpg")
+}
+
+/// Salva accanto al video tutte le immagini che ha, come
+/// `<filename>.landscape.jpg`, e ritorna i percorsi dei file salvati. Le
+/// immagini che non si riescono a scaricare vengono segnalate senza
+/// interrompere il download.
+pub async fn save_thumbnails(
+    video: &RaiPlayVideo,
+    filename: &str,
+    resolution: Option<&str>,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut saved = Vec::new();
+    for &kind in ImageKind::ALL.iter() {
+        let url = match video_image_url(video, kind, resolution) {
+            Some(url) => url,
+            None => continue,
+        };
+        let path = PathBuf::from(format!("{}.{}.{}", filename, kind.name(), extension(&url)));
+        match fetch(&url).await {
+            Ok(data) => {
+                fs::write(&path, data)?;
+                saved.push(path);
+            }
+            Err(err) => progress.report(ProgressEvent::Warning(&format!(
+                "Non sono riuscito a scaricare l'immagine {}: {}",
+                kind.name(),
+                err
+            ))),
+        }
+    }
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(
+            resolve(
+                RAI_PLAY_BASE_URL,
+                "/resizegd/[RESOLUTION]/dl/img/2019/10/collegio.jpg",
+                "1920x1080"
+            )
+            .map(String::from)
+            .as_deref(),
+            Some("https://www.raiplay.it/resizegd/1920x1080/dl/img/2019/10/collegio.jpg")
+        );
+        assert_eq!(
+            resolve(
+                RAI_PLAY_BASE_URL,
+                "https://www.rai.it/dl/img/collegio.png",
+                "1920x1080"
+            )
+            .map(String::from)
+            .as_deref(),
+            Some("https://www.rai.it/dl/img/collegio.png")
+        );
+        assert_eq!(resolve(RAI_PLAY_BASE_URL, " ", "1920x1080"), None);
+
+        let url = reqwest::Url::parse("https://www.raiplay.it/dl/img/collegio.png?v=2").unwrap();
+        assert_eq!(extension(&url), "png");
+        let url = reqwest::Url::parse("https://www.raiplay.it/resizegd/600x400/").unwrap();
+        assert_eq!(extension(&url), "jpg");
+
+        assert_eq!(
+            "portrait_logo".parse::<ImageKind>().unwrap(),
+            ImageKind::PortraitLogo
+        );
+        assert!("logo".parse::<ImageKind>().is_err());
+        assert!(is_valid_resolution("600x400"));
+        assert!(!is_valid_resolution("600"));
+    }
+}
@@ -0,0 +1,241 @@
+#![warn(clippy::all)]
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Estensione aggiunta al percorso del .ts per ottenere quello del journal.
+const JOURNAL_EXTENSION: &str = "part.json";
+
+/// Segmento già scritto nel file .ts. Se `skipped` è presente il segmento
+/// non è stato scaricato e contiene la causa dell'errore.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommittedSegment {
+    pub index: usize,
+    pub offset: u64,
+    pub len: u64,
+    #[serde(default)]
+    pub skipped: Option<String>,
+}
+
+/// Prima riga del journal, scritta una volta sola all'inizio del download.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalHeader {
+    variant_uri: String,
+    segments: Vec<String>,
+}
+
+/// Journal di un download .ts, salvato accanto al file di output.
+///
+/// Tiene traccia della variante, della lista dei segmenti e di quelli già
+/// scritti con il loro offset, così che un download interrotto possa essere
+/// ripreso dal primo segmento mancante. Il file ha una riga JSON con la
+/// variante e i segmenti, seguita da una riga per ogni segmento scritto.
+#[derive(Debug)]
+pub struct SegmentJournal {
+    pub variant_uri: String,
+    pub segments: Vec<String>,
+    pub committed: Vec<CommittedSegment>,
+    /// Numero di segmenti di `committed` già scritti nel file.
+    saved: usize,
+}
+
+/// Rimuove la query string da un URI. I token del CDN cambiano a ogni
+/// richiesta al relinker, quindi non vanno considerati nel confronto.
+fn strip_query(uri: &str) -> &str {
+    uri.split('?').next().unwrap_or(uri)
+}
+
+impl SegmentJournal {
+    pub fn new(variant_uri: String, segments: Vec<String>) -> SegmentJournal {
+        SegmentJournal {
+            variant_uri,
+            segments,
+            committed: Vec::new(),
+            saved: 0,
+        }
+    }
+
+    /// Ritorna il percorso del journal associato a `ts_path`, cioè
+    /// `<nome>.ts.part.json`.
+    pub fn path_for(ts_path: &Path) -> PathBuf {
+        let mut path = ts_path.as_os_str().to_owned();
+        path.push(".");
+        path.push(JOURNAL_EXTENSION);
+        PathBuf::from(path)
+    }
+
+    /// Legge il journal da `path`, se esiste. Un'ultima riga scritta solo
+    /// in parte viene ignorata, insieme a quello che la segue.
+    pub fn load(path: &Path) -> Result<Option<SegmentJournal>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines();
+        let header: JournalHeader = serde_json::from_str(lines.next().unwrap_or_default())?;
+        let committed: Vec<CommittedSegment> = lines
+            .map_while(|line| serde_json::from_str(line).ok())
+            .collect();
+        Ok(Some(SegmentJournal {
+            variant_uri: header.variant_uri,
+            segments: header.segments,
+            saved: committed.len(),
+            committed,
+        }))
+    }
+
+    /// Controlla che il journal si riferisca alla stessa variante e agli
+    /// stessi segmenti.
+    pub fn matches(&self, variant_uri: &str, segments: &[String]) -> bool {
+        strip_query(&self.variant_uri) == strip_query(variant_uri)
+            && self.segments.len() == segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(segments)
+                .all(|(a, b)| strip_query(a) == strip_query(b))
+    }
+
+    /// Numero di byte del .ts coperti da segmenti completi.
+    pub fn committed_len(&self) -> u64 {
+        self.committed
+            .last()
+            .map(|seg| seg.offset + seg.len)
+            .unwrap_or(0)
+    }
+
+    /// Registra il segmento `index`, appena scritto con `len` byte.
+    pub fn commit(&mut self, index: usize, len: u64) {
+        let offset = self.committed_len();
+        self.committed.push(CommittedSegment {
+            index,
+            offset,
+            len,
+            skipped: None,
+        });
+    }
+
+    /// Registra il segmento `index` come saltato a causa di `cause`.
+    pub fn commit_skipped(&mut self, index: usize, cause: String) {
+        let offset = self.committed_len();
+        self.committed.push(CommittedSegment {
+            index,
+            offset,
+            len: 0,
+            skipped: Some(cause),
+        });
+    }
+
+    /// Ritorna i segmenti saltati finora.
+    pub fn skipped(&self) -> impl Iterator<Item = &CommittedSegment> {
+        self.committed.iter().filter(|seg| seg.skipped.is_some())
+    }
+
+    /// Numero di segmenti registrati ma non ancora scritti nel file.
+    pub fn pending(&self) -> usize {
+        self.committed.len() - self.saved
+    }
+
+    fn write_commits(&self, file: &mut File) -> Result<(), Error> {
+        let mut lines = Vec::new();
+        for seg in &self.committed[self.saved..] {
+            serde_json::to_writer(&mut lines, seg)?;
+            lines.push(b'\n');
+        }
+        file.write_all(&lines)?;
+        Ok(())
+    }
+
+    /// Salva da capo il journal in `path`, con la lista dei segmenti. Il
+    /// file viene prima scritto accanto e poi rinominato, così che
+    /// un'interruzione non lasci mai un journal troncato.
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let header = JournalHeader {
+            variant_uri: self.variant_uri.clone(),
+            segments: self.segments.clone(),
+        };
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        self.saved = 0;
+        self.write_commits(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        self.saved = self.committed.len();
+        Ok(())
+    }
+
+    /// Aggiunge in fondo al journal in `path` i segmenti registrati dopo
+    /// l'ultimo salvataggio. I loro dati devono essere già sul disco.
+    pub fn append(&mut self, path: &Path) -> Result<(), Error> {
+        if self.pending() == 0 {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        self.write_commits(&mut file)?;
+        file.sync_data()?;
+        self.saved = self.committed.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(
+            SegmentJournal::path_for(Path::new("video.ts")),
+            PathBuf::from("video.ts.part.json")
+        );
+
+        let segments = vec![
+            String::from("https://cdn.rai.it/seg1.ts?token=a"),
+            String::from("https://cdn.rai.it/seg2.ts?token=a"),
+            String::from("https://cdn.rai.it/seg3.ts?token=a"),
+        ];
+        let mut journal = SegmentJournal::new(
+            String::from("https://cdn.rai.it/index.m3u8?token=a"),
+            segments,
+        );
+        journal.commit(0, 100);
+        journal.commit_skipped(1, String::from("timeout"));
+        journal.commit(2, 50);
+        assert_eq!(journal.committed[2].offset, 100);
+        assert_eq!(journal.committed_len(), 150);
+        assert_eq!(journal.skipped().count(), 1);
+
+        let new_segments = vec![
+            String::from("https://cdn.rai.it/seg1.ts?token=b"),
+            String::from("https://cdn.rai.it/seg2.ts?token=b"),
+            String::from("https://cdn.rai.it/seg3.ts?token=b"),
+        ];
+        assert!(journal.matches("https://cdn.rai.it/index.m3u8?token=b", &new_segments));
+        assert!(!journal.matches("https://cdn.rai.it/other.m3u8", &new_segments));
+        assert!(!journal.matches("https://cdn.rai.it/index.m3u8", &new_segments[..1]));
+
+        let path = std::env::temp_dir().join(format!("raiplay-dl-journal-{}", std::process::id()));
+        journal.save(&path).unwrap();
+        journal.commit(0, 10);
+        assert_eq!(journal.pending(), 1);
+        journal.append(&path).unwrap();
+        assert_eq!(journal.pending(), 0);
+        // Una riga scritta a metà da un'interruzione.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"index\":1,\"off").unwrap();
+        let loaded = SegmentJournal::load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.committed.len(), 4);
+        assert_eq!(loaded.committed_len(), 160);
+        assert_eq!(loaded.pending(), 0);
+        assert!(loaded.matches("https://cdn.rai.it/index.m3u8", &new_segments));
+    }
+}
@@ -0,0 +1,66 @@
+#![warn(clippy::all)]
+
+//! Libreria per scaricare video da RaiPlay e audio da RaiPlay Sound, usata
+//! dalla CLI `raiplay-dl`.
+//!
+//! Il punto di partenza è [`extract_video_url`], che dalla pagina di un
+//! video ottiene le sue informazioni ([`RaiPlayVideoInfos`]) con le varianti
+//! della playlist HLS ([`M3u8VideoVariant`]). Ogni variante può poi essere
+//! scaricata in un file MPEG-TS e convertita con [`remux`]; il file MP4 di
+//! qualità sconosciuta si scarica invece con [`download`].
+//!
+//! ```no_run
+//! use raiplay_dl::progress::ConsoleReporter;
+//! use raiplay_dl::{extract_video_url, DownloadOptions};
+//! use std::path::Path;
+//!
+//! # async fn example() -> Result<(), raiplay_dl::Error> {
+//! let url = "https://www.raiplay.it/video/2019/10/Il-Collegio-4-6f9681db-62ff-4094-8272-7f5babaebc29.html";
+//! let progress = ConsoleReporter::new(true);
+//! let mut infos = extract_video_url(url, &progress).await?;
+//! // Le varianti sono ordinate per qualità crescente.
+//! let best = infos.m3u8_variants.last_mut().unwrap();
+//! best.download_ts(Path::new("video.ts"), &DownloadOptions::default(), &progress)
+//!     .await?;
+//! raiplay_dl::remux::ts_to_mp4(&[Path::new("video.ts").into()], Path::new("video.mp4"))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Per scaricare un URL qualsiasi come fa la CLI, scegliendo da sola cosa
+//! salvare, c'è [`save::download_url`].
+//!
+//! Le funzioni che scaricano qualcosa segnalano il loro avanzamento a un
+//! [`progress::ProgressReporter`]: [`progress::ConsoleReporter`] lo mostra
+//! nel terminale come la CLI, [`progress::NoProgress`] lo ignora e una
+//! closure può riceverlo come eventi strutturati.
+
+pub mod api;
+pub mod archive;
+pub mod auth;
+pub mod batch;
+pub mod cancel;
+mod crypto;
+pub mod downloader;
+pub mod error;
+pub mod format_selector;
+pub(crate) mod http;
+pub mod images;
+mod journal;
+pub mod live;
+pub mod models;
+pub mod nfo;
+pub mod program;
+pub mod progress;
+pub mod remux;
+pub mod retry;
+pub mod sanitize_path;
+pub mod save;
+pub mod scheduler;
+pub mod sound;
+pub mod subtitles;
+pub mod template;
+
+pub use api::{extract_video_url, DownloadOptions, M3u8VideoVariant, RaiPlayVideoInfos};
+pub use downloader::{download, download_to};
+pub use error::Error;
@@ -0,0 +1,411 @@
+#![warn(clippy::all)]
+
+//! Registrazione delle dirette di RaiPlay (`raiplay.it/dirette/<canale>`):
+//! la media playlist viene riletta a ogni target duration e i segmenti nuovi
+//! vengono accodati al file.
+
+use crate::api::{self, DownloadOptions};
+use crate::error::Error;
+use crate::http;
+use crate::models::live;
+use crate::progress::{ProgressEvent, ProgressReporter, Resource};
+use crate::retry;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Dominio rispetto a cui risolvere gli URL dei canali.
+const RAI_PLAY_BASE_URL: &str = "https://www.raiplay.it/";
+
+/// Segmenti già presenti nella playlist da cui parte la registrazione: più
+/// vicino alla diretta si rischia di dover aspettare il primo segmento, più
+/// lontano si registra qualcosa che è già andato in onda.
+const LIVE_EDGE_SEGMENTS: usize = 3;
+
+lazy_static! {
+    static ref LIVE_URL_RE: Regex =
+        Regex::new(r"^https?://(?:www\.)?raiplay\.it/dirette/([^/?#.]+)").unwrap();
+    static ref DURATION_RE: Regex = Regex::new(r"^(?:(\d+)h)?(?:(\d+)m)?(?:(\d+)s)?$").unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidTimeError(pub String);
+
+impl fmt::Display for InvalidTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Time is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTimeError {}
+
+/// Quando fermare la registrazione. Senza limiti si registra finché la
+/// diretta non finisce o finché non viene premuto Ctrl-C.
+#[derive(Debug, Default, Clone)]
+pub struct LiveOptions {
+    /// Durata massima del contenuto registrato.
+    pub duration: Option<Duration>,
+    /// Ora in cui smettere di registrare.
+    pub until: Option<DateTime<Local>>,
+}
+
+/// Segmenti persi durante la registrazione, perché usciti dalla playlist
+/// prima di essere scaricati o perché continuavano a fallire.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiveGap {
+    pub first_sequence: u64,
+    pub count: u64,
+    /// Posizione nella registrazione, in secondi.
+    pub start: f32,
+    pub cause: String,
+}
+
+/// Risultato di una registrazione.
+#[derive(Debug)]
+pub struct LiveRecording {
+    pub segments: usize,
+    /// Durata registrata in secondi.
+    pub duration: f32,
+    pub gaps: Vec<LiveGap>,
+}
+
+/// Canale in diretta, con il relinker della sua master playlist.
+#[derive(Debug)]
+pub struct LiveChannel {
+    pub name: String,
+    pub content_url: String,
+}
+
+/// Vero se `url` è la pagina di una diretta.
+pub fn is_live_url(url: &str) -> bool {
+    LIVE_URL_RE.is_match(url)
+}
+
+/// Legge una durata come `90m`, `1h30m`, `45s`, `01:30:00` o un numero di
+/// secondi.
+pub fn parse_duration(s: &str) -> Result<Duration, InvalidTimeError> {
+    let invalid = || InvalidTimeError(s.to_string());
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid());
+    }
+
+    let seconds = if s.contains(':') {
+        s.split(':').try_fold(0_u64, |acc, part| {
+            let n = part.parse::<u64>().ok();
+            n.and_then(|n| acc.checked_mul(60)?.checked_add(n))
+                .ok_or_else(invalid)
+        })?
+    } else if let Ok(seconds) = s.parse::<u64>() {
+        seconds
+    } else {
+        let cap = DURATION_RE.captures(s).ok_or_else(invalid)?;
+        // Le cifre del regex possono comunque essere troppe per un u64.
+        let part = |i: usize, unit: u64| match cap.get(i) {
+            Some(m) => m.as_str().parse::<u64>().ok()?.checked_mul(unit),
+            None => Some(0),
+        };
+        part(1, 3600)
+            .and_then(|hours| hours.checked_add(part(2, 60)?))
+            .and_then(|seconds| seconds.checked_add(part(3, 1)?))
+            .ok_or_else(invalid)?
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Legge un'ora come `21:30`, `21:30:15` o `2020-03-01 21:30`. Un'ora senza
+/// data si riferisce alla prossima volta che arriverà dopo `now`.
+pub fn parse_until(s: &str, now: NaiveDateTime) -> Result<NaiveDateTime, InvalidTimeError> {
+    let invalid = || InvalidTimeError(s.to_string());
+    let s = s.trim();
+    let parse_time = |time: &str| {
+        NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .map_err(|_| invalid())
+    };
+
+    match s.find([' ', 'T']) {
+        Some(i) => {
+            let date = NaiveDate::parse_from_str(&s[..i], "%Y-%m-%d").map_err(|_| invalid())?;
+            Ok(date.and_time(parse_time(s[i + 1..].trim())?))
+        }
+        None => {
+            let until = now.date().and_time(parse_time(s)?);
+            if until > now {
+                Ok(until)
+            } else {
+                Ok(until + chrono::Duration::days(1))
+            }
+        }
+    }
+}
+
+/// Converte un'ora locale letta da [`parse_until`].
+pub fn local_time(time: NaiveDateTime) -> Result<DateTime<Local>, InvalidTimeError> {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .ok_or_else(|| InvalidTimeError(time.to_string()))
+}
+
+/// Scarica le informazioni sul canale della pagina `url`.
+pub async fn fetch_channel(
+    url: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<LiveChannel, Error> {
+    let name = match LIVE_URL_RE.captures(url) {
+        Some(caps) => caps[1].to_string(),
+        None => return Err(Error::InvalidUrl(url.to_string())),
+    };
+
+    progress.report(ProgressEvent::FetchingMetadata(Resource::ChannelInfo));
+    let json_url =
+        reqwest::Url::parse(RAI_PLAY_BASE_URL)?.join(&format!("dirette/{}.json", name))?;
+    let channel: live::RaiPlayLiveChannel = http::client()?
+        .get(json_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    progress.report(ProgressEvent::MetadataFetched(Resource::ChannelInfo));
+
+    let name = [&channel.channel, &channel.name]
+        .iter()
+        .map(|name| name.trim())
+        .find(|name| !name.is_empty())
+        .unwrap_or(&name)
+        .to_string();
+    Ok(LiveChannel {
+        name,
+        content_url: channel.video.content_url,
+    })
+}
+
+/// Registra in `path` la media playlist live `uri`, finché non scatta uno dei
+/// limiti di `options`, la diretta finisce o viene premuto Ctrl-C.
+///
+/// La registrazione parte dagli ultimi segmenti della playlist. Se la
+/// playlist avanza più in fretta dei download, o se un segmento fallisce con
+/// `skip_broken_segments`, i segmenti persi vengono annotati e salvati in
+/// `<nome>.ts.gaps.json`.
+pub async fn record(
+    uri: &str,
+    path: &Path,
+    options: &LiveOptions,
+    download_options: &DownloadOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<LiveRecording, Error> {
+    let client = http::segment_client()?;
+    let max_duration = options.duration.map(|duration| duration.as_secs_f32());
+
+    let mut file = File::create(path)?;
+    let mut keys: HashMap<String, [u8; 16]> = HashMap::new();
+    let mut recording = LiveRecording {
+        segments: 0,
+        duration: 0.0,
+        gaps: Vec::new(),
+    };
+    let mut next_sequence: Option<u64> = None;
+    let mut total_content_len = 0;
+
+    // Con Ctrl-C la registrazione si ferma subito, anche mentre aspetta la
+    // playlist o un segmento, e quanto registrato finora viene tenuto.
+    let interrupt = download_options.cancel.cancelled();
+    tokio::pin!(interrupt);
+
+    progress.report(ProgressEvent::RecordingStarted { path });
+    let warn = |message: String| progress.report(ProgressEvent::Warning(&message));
+
+    'polling: loop {
+        // Gli URI relativi dei segmenti vanno risolti rispetto all'URL
+        // finale, dopo i redirect del relinker verso il server del CDN.
+        let refresh = retry::fetch_url_with_retry(&client, uri, &download_options.retry, |_, _| {});
+        let (base_url, data) = tokio::select! {
+            result = refresh => result?,
+            _ = &mut interrupt => break 'polling,
+        };
+        let playlist = api::parse_media_playlist(&String::from_utf8_lossy(&data), &base_url)?;
+        let first = playlist.segments.first().map(|seg| seg.media_sequence);
+        let last = playlist.segments.last().map(|seg| seg.media_sequence);
+
+        let next = match (next_sequence, first, last) {
+            (None, Some(_), _) => {
+                let start = playlist.segments.len().saturating_sub(LIVE_EDGE_SEGMENTS);
+                playlist.segments[start].media_sequence
+            }
+            // Dopo un riavvio dell'encoder la numerazione può ripartire da
+            // capo.
+            (Some(next), Some(first), Some(last)) if last + 1 < next => {
+                warn(String::from("la numerazione dei segmenti è ripartita"));
+                first
+            }
+            (Some(next), Some(first), _) if first > next => {
+                warn(format!(
+                    "persi {} segmenti usciti dalla playlist",
+                    first - next
+                ));
+                recording.gaps.push(LiveGap {
+                    first_sequence: next,
+                    count: first - next,
+                    start: recording.duration,
+                    cause: String::from("segments left the playlist before being downloaded"),
+                });
+                first
+            }
+            (next, _, _) => next.unwrap_or(0),
+        };
+        next_sequence = Some(next);
+
+        let start = playlist
+            .segments
+            .iter()
+            .position(|seg| seg.media_sequence >= next)
+            .unwrap_or(playlist.segments.len());
+        let new_segments = &playlist.segments[start..];
+        let fetch_keys = api::fetch_keys(&client, new_segments, &download_options.retry, &mut keys);
+        tokio::select! {
+            result = fetch_keys => result?,
+            _ = &mut interrupt => break 'polling,
+        }
+
+        for seg in new_segments {
+            let fetch =
+                retry::fetch_with_retry(&client, &seg.uri, &download_options.retry, |_, _| {});
+            let data = tokio::select! {
+                result = fetch => result,
+                _ = &mut interrupt => break 'polling,
+            };
+            let data = data.and_then(|data| api::decrypt_segment(seg, &keys, data));
+            match data {
+                Ok(data) => {
+                    file.write_all(&data)?;
+                    total_content_len += data.len() as u64;
+                    recording.segments += 1;
+                    recording.duration += seg.duration;
+                }
+                // Con un token scaduto falliranno anche tutti i segmenti
+                // successivi.
+                Err(err)
+                    if download_options.skip_broken_segments
+                        && !matches!(err, Error::TokenExpired(_)) =>
+                {
+                    let cause = err.to_string();
+                    progress.report(ProgressEvent::SegmentSkipped {
+                        index: seg.media_sequence as usize,
+                        cause: &cause,
+                    });
+                    recording.gaps.push(LiveGap {
+                        first_sequence: seg.media_sequence,
+                        count: 1,
+                        start: recording.duration,
+                        cause,
+                    });
+                }
+                Err(err) => {
+                    progress.report(ProgressEvent::Aborted);
+                    return Err(err);
+                }
+            }
+            next_sequence = Some(seg.media_sequence + 1);
+
+            progress.report(ProgressEvent::Recorded {
+                duration: recording.duration,
+                segments: recording.segments as u64,
+                bytes: total_content_len,
+            });
+
+            if max_duration.is_some_and(|max| recording.duration >= max)
+                || options.until.is_some_and(|until| Local::now() >= until)
+                || download_options.cancel.is_cancelled()
+            {
+                break 'polling;
+            }
+        }
+
+        if playlist.end_list {
+            progress.report(ProgressEvent::LiveEnded);
+            break;
+        }
+
+        // Se la playlist non è cambiata viene riletta prima, come suggerito
+        // dalla specifica HLS.
+        let target = Duration::from_secs_f32(playlist.target_duration.max(1.0));
+        let mut wait = if new_segments.is_empty() {
+            target / 2
+        } else {
+            target
+        };
+        if let Some(until) = options.until {
+            let left = (until - Local::now()).to_std().unwrap_or_default();
+            if left.is_zero() {
+                break;
+            }
+            wait = wait.min(left);
+        }
+        tokio::select! {
+            _ = tokio::time::delay_for(wait) => {}
+            _ = &mut interrupt => break,
+        }
+    }
+
+    if !recording.gaps.is_empty() {
+        let mut report_path = path.as_os_str().to_owned();
+        report_path.push(".gaps.json");
+        let mut report = File::create(&report_path)?;
+        report.write_all(serde_json::to_string_pretty(&recording.gaps)?.as_bytes())?;
+        progress.report(ProgressEvent::GapsSaved {
+            count: recording.gaps.iter().map(|gap| gap.count).sum(),
+            report: Path::new(&report_path),
+        });
+    }
+    progress.report(ProgressEvent::Finished { path });
+    Ok(recording)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert!(is_live_url("https://www.raiplay.it/dirette/rai1"));
+        assert!(is_live_url("https://raiplay.it/dirette/rainews24?x=1"));
+        assert!(!is_live_url("https://www.raiplay.it/programmi/ilcollegio"));
+
+        let minutes = |m: u64| Ok(Duration::from_secs(m * 60));
+        assert_eq!(parse_duration("90m"), minutes(90));
+        assert_eq!(parse_duration("1h30m"), minutes(90));
+        assert_eq!(parse_duration("01:30:00"), minutes(90));
+        assert_eq!(parse_duration("5400"), minutes(90));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1x").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+        assert!(parse_duration("9999999999999999h").is_err());
+        assert!(parse_duration("99999999999999999:00:00").is_err());
+
+        let now = NaiveDate::from_ymd_opt(2020, 3, 1)
+            .unwrap()
+            .and_hms_opt(20, 0, 0)
+            .unwrap();
+        let at = |day, h, m| {
+            NaiveDate::from_ymd_opt(2020, 3, day)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        assert_eq!(parse_until("21:30", now), Ok(at(1, 21, 30)));
+        assert_eq!(parse_until("06:00", now), Ok(at(2, 6, 0)));
+        assert_eq!(parse_until("2020-03-05 21:30", now), Ok(at(5, 21, 30)));
+        assert_eq!(parse_until("2020-03-05T21:30", now), Ok(at(5, 21, 30)));
+        assert!(parse_until("25:00", now).is_err());
+    }
+}
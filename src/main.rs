@@ -1,15 +1,68 @@
 #![warn(clippy::all)]
 
-use clap::{App, Arg};
+use chrono::{Local, NaiveDate};
+use clap::{App, Arg, ArgMatches};
 use console::style;
-use std::fs::File;
+use futures::future::BoxFuture;
+use raiplay_dl::batch::Outcome;
+use raiplay_dl::format_selector::FormatSelector;
+use raiplay_dl::progress::ConsoleReporter;
+use raiplay_dl::save::{self, Prompt, SaveOptions};
+use raiplay_dl::template::OutputTemplate;
+use raiplay_dl::{api, auth, batch, cancel, images, live, program, scheduler, sound, Error};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
-mod api;
-mod downloader;
-mod models;
-mod sanitize_path;
+fn validate_number(n: String) -> Result<(), String> {
+    n.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("deve essere un numero intero non negativo"))
+}
+
+/// Opzioni della riga di comando che valgono per ogni URL.
+#[derive(Clone)]
+struct Settings {
+    verbose: bool,
+    options: SaveOptions,
+    /// A chi chiedere la qualità quando non c'è un selettore, se c'è
+    /// qualcuno a rispondere.
+    prompt: Option<ConsolePrompt>,
+    progress: Arc<ConsoleReporter>,
+}
+
+impl Settings {
+    fn prompt(&self) -> Option<&dyn Prompt> {
+        self.prompt.as_ref().map(|prompt| prompt as &dyn Prompt)
+    }
+}
+
+/// Chiede le scelte nel terminale.
+#[derive(Clone)]
+struct ConsolePrompt {
+    verbose: bool,
+    cancel: cancel::CancelToken,
+}
+
+impl Prompt for ConsolePrompt {
+    fn choose<'a>(
+        &'a self,
+        title: &'a str,
+        choices: &'a [String],
+    ) -> BoxFuture<'a, Result<usize, Error>> {
+        Box::pin(async move {
+            println!("{}{}", if self.verbose { "\n" } else { "" }, title);
+            for (i, choice) in choices.iter().enumerate() {
+                println!("  [{}] {}", style(i).cyan(), choice);
+            }
+            let i = prompt_index(choices.len(), &self.cancel).await?;
+            println!();
+            Ok(i)
+        })
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -20,8 +73,16 @@ async fn main() {
         .arg(
             Arg::with_name("url")
                 .value_name("URL")
-                .help("URL al video da scaricare")
-                .required(true),
+                .multiple(true)
+                .help("URL ai video, ai programmi o alle dirette da scaricare")
+                .required_unless_one(&["run-scheduled", "batch-file"]),
+        )
+        .arg(
+            Arg::with_name("batch-file")
+                .short("a")
+                .long("batch-file")
+                .value_name("FILE")
+                .help("File con gli URL da scaricare, uno per riga (- per lo standard input); le righe vuote e quelle che iniziano con # vengono ignorate"),
         )
         .arg(
             Arg::with_name("quiet")
@@ -35,12 +96,273 @@ async fn main() {
                 .long("mp4")
                 .help("Al posto di scaricare il video come .ts, lo scarica come mp4"),
         )
+        .arg(
+            Arg::with_name("container")
+                .short("c")
+                .long("container")
+                .value_name("FORMATO")
+                .possible_values(&["ts", "mp4", "mkv"])
+                .default_value("ts")
+                .help("Contenitore in cui salvare la variante scelta, senza bisogno di ffmpeg"),
+        )
+        .arg(
+            Arg::with_name("quality")
+                .short("f")
+                .long("quality")
+                .value_name("SELETTORE")
+                .validator(|selector| {
+                    selector
+                        .parse::<FormatSelector>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Sceglie la variante senza chiederla, es. best, worst, bestaudio, 720p, height<=720, bandwidth>2000000, 720p/best"),
+        )
+        .arg(
+            Arg::with_name("audio-lang")
+                .long("audio-lang")
+                .value_name("LINGUE")
+                .use_delimiter(true)
+                .help("Tracce audio alternative da scaricare, es. ita,eng o all (di default quella predefinita)"),
+        )
+        .arg(
+            Arg::with_name("audio-only")
+                .long("audio-only")
+                .help("Scarica solo l'audio e lo salva come M4A"),
+        )
         .arg(
             Arg::with_name("m3u8")
                 .short("M")
                 .long("m3u8")
                 .help("Scarica solo il file .m3u8 master del video"),
         )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("Numero di segmenti da scaricare in parallelo (default: 4)")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("deve essere un numero maggiore di 0")),
+                }),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("N")
+                .help("Tentativi aggiuntivi per ogni segmento fallito (default: 5)")
+                .validator(validate_number),
+        )
+        .arg(
+            Arg::with_name("retry-backoff")
+                .long("retry-backoff")
+                .value_name("MS")
+                .help("Attesa in millisecondi prima del primo nuovo tentativo, raddoppiata a ogni fallimento (default: 500)")
+                .validator(validate_number),
+        )
+        .arg(
+            Arg::with_name("retry-jitter")
+                .long("retry-jitter")
+                .value_name("MS")
+                .help("Massimo ritardo casuale in millisecondi aggiunto a ogni attesa (default: 250)")
+                .validator(validate_number),
+        )
+        .arg(
+            Arg::with_name("skip-broken-segments")
+                .long("skip-broken-segments")
+                .help("Salta i segmenti che continuano a fallire e li annota in un report invece di interrompere il download"),
+        )
+        .arg(
+            Arg::with_name("subs")
+                .long("subs")
+                .help("Scarica i sottotitoli e li salva come .srt e .vtt accanto al video"),
+        )
+        .arg(
+            Arg::with_name("season")
+                .long("season")
+                .value_name("N")
+                .help("Con l'URL di un programma, scarica solo gli episodi della stagione N")
+                .validator(validate_number),
+        )
+        .arg(
+            Arg::with_name("episodes")
+                .long("episodes")
+                .value_name("EPISODI")
+                .help("Con l'URL di un programma, scarica solo questi episodi, es. 3-7 o 1,4,9-")
+                .validator(|episodes| {
+                    episodes
+                        .parse::<program::EpisodeRanges>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .value_name("DURATA")
+                .help("Con una diretta, registra per questa durata, es. 90m, 1h30m o 01:30:00")
+                .validator(|duration| {
+                    live::parse_duration(&duration)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .value_name("ORA")
+                .help("Con una diretta, registra fino a quest'ora, es. 23:15 o 2020-03-01 23:15")
+                .validator(|until| {
+                    live::parse_until(&until, Local::now().naive_local())
+                        .and_then(live::local_time)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("schedule")
+                .long("schedule")
+                .value_name("PROGRAMMA")
+                .help("Con una diretta, programma la registrazione della trasmissione del palinsesto con questo titolo o in onda a quest'ora, es. 'Il Collegio' o 21:25"),
+        )
+        .arg(
+            Arg::with_name("date")
+                .long("date")
+                .value_name("DATA")
+                .requires("schedule")
+                .help("Giorno del palinsesto in cui cercare la trasmissione, es. 2020-03-01 (default: oggi)")
+                .validator(|date| {
+                    NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                        .map(|_| ())
+                        .map_err(|_| String::from("deve essere una data come 2020-03-01"))
+                }),
+        )
+        .arg(
+            Arg::with_name("pad-before")
+                .long("pad-before")
+                .value_name("DURATA")
+                .help("Anticipo con cui iniziare una registrazione programmata (default: 2m)")
+                .validator(|duration| {
+                    live::parse_duration(&duration)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("pad-after")
+                .long("pad-after")
+                .value_name("DURATA")
+                .help("Margine dopo la fine prevista di una registrazione programmata (default: 5m)")
+                .validator(|duration| {
+                    live::parse_duration(&duration)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("jobs-file")
+                .long("jobs-file")
+                .value_name("FILE")
+                .help("File in cui salvare le registrazioni programmate (default: raiplay-dl-jobs.json)"),
+        )
+        .arg(
+            Arg::with_name("run-scheduled")
+                .long("run-scheduled")
+                .help("Esegue le registrazioni programmate in attesa, ad esempio dopo un riavvio"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("MODELLO")
+                .validator(|output| {
+                    output
+                        .parse::<OutputTemplate>()
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .help("Modello per il nome dei file, es. '{program_info.name}/S{season:02}E{episode:02} - {episode_title}.{ext}'; accetta i campi di RaiPlayVideo e della variante scelta (variant.*), o per RaiPlay Sound quelli dell'episodio (name, metadata.*)"),
+        )
+        .arg(
+            Arg::with_name("media-server")
+                .long("media-server")
+                .conflicts_with_all(&["output", "infos", "m3u8"])
+                .help("Salva i file come 'Programma/Season NN/Programma - SNNEMM - Titolo' con le schede NFO, la locandina e lo sfondo per Jellyfin, Kodi e Plex"),
+        )
+        .arg(
+            Arg::with_name("thumbnails")
+                .long("thumbnails")
+                .help("Salva accanto al video le sue immagini (locandine, sfondi e loghi)"),
+        )
+        .arg(
+            Arg::with_name("embed-thumbnail")
+                .long("embed-thumbnail")
+                .value_name("IMMAGINE")
+                .possible_values(&[
+                    "landscape",
+                    "portrait",
+                    "square",
+                    "landscape43",
+                    "portrait43",
+                    "portrait_logo",
+                    "landscape_logo",
+                ])
+                .help("Incorpora l'immagine come copertina del MP4, del MKV o del M4A"),
+        )
+        .arg(
+            Arg::with_name("thumbnail-size")
+                .long("thumbnail-size")
+                .value_name("LxA")
+                .validator(|size| {
+                    if images::is_valid_resolution(&size) {
+                        Ok(())
+                    } else {
+                        Err(format!("Image size is not valid: {}", size))
+                    }
+                })
+                .help("Dimensione delle immagini, es. 1920x1080; di default dipende dall'immagine"),
+        )
+        .arg(
+            Arg::with_name("download-archive")
+                .long("download-archive")
+                .value_name("FILE")
+                .help("Salta i video e gli audio elencati nel file e ci aggiunge quelli scaricati"),
+        )
+        .arg(
+            Arg::with_name("username")
+                .long("username")
+                .value_name("EMAIL")
+                .requires("password")
+                .help("Email dell'account RaiPlay, per i contenuti che richiedono l'accesso"),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("RAIPLAY_PASSWORD")
+                .hide_env_values(true)
+                .help("Password dell'account RaiPlay"),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("FILE")
+                .help("File in cui salvare il token dell'account RaiPlay (default: ~/.cache/raiplay-dl/raiplay-dl-token.json)"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .value_name("TOKEN")
+                .conflicts_with("username")
+                .help("Token dell'account RaiPlay già ottenuto, mandato come Authorization: Bearer"),
+        )
+        .arg(
+            Arg::with_name("cookies")
+                .long("cookies")
+                .value_name("FILE")
+                .help("Cookie di RaiPlay esportati dal browser in formato Netscape (cookies.txt)"),
+        )
         .arg(
             Arg::with_name("infos")
                 .short("i")
@@ -49,88 +371,377 @@ async fn main() {
         )
         .get_matches();
 
-    let url = matches.value_of("url").unwrap();
     let verbose = matches.occurrences_of("quiet") != 1;
-    let mp4 = matches.occurrences_of("mp4") == 1;
-    let infos = matches.occurrences_of("infos") == 1;
-    let m3u8 = matches.occurrences_of("m3u8") == 1;
-
-    let mut video_infos = api::extract_video_url(url, Some(verbose))
-        .await
-        .expect("Non sono riuscito a scaricare le info sul video");
-    let filename = sanitize_path::sanitize(&video_infos.infos.name, None, None);
-    video_infos
-        .fetch_all_segments(Some(verbose))
-        .await
-        .expect("Non sono riuscito a scaricare tutti i segmenti");
-
-    if infos {
-        let mut file = File::create(format!("{}.json", filename))
-            .expect("Non sono riuscito a creare data.json");
-        file.write_all(
-            serde_json::to_string_pretty(&video_infos)
-                .unwrap()
-                .as_bytes(),
-        )
-        .expect("Non sono riuscito a scrivere su data.json");
-        return;
+    let options = SaveOptions {
+        mp4: matches.occurrences_of("mp4") == 1,
+        infos: matches.occurrences_of("infos") == 1,
+        m3u8: matches.occurrences_of("m3u8") == 1,
+        subs: matches.occurrences_of("subs") == 1,
+        audio_only: matches.occurrences_of("audio-only") == 1,
+        audio_languages: matches
+            .values_of("audio-lang")
+            .map(|languages| languages.map(String::from).collect())
+            .unwrap_or_default(),
+        container: matches.value_of("container").unwrap().to_string(),
+        quality: matches.value_of("quality").map(String::from),
+        download_options: {
+            let mut options = api::DownloadOptions::default();
+            if let Some(jobs) = matches.value_of("jobs") {
+                options.jobs = jobs.parse().unwrap();
+            }
+            if let Some(retries) = matches.value_of("retries") {
+                options.retry.retries = retries.parse().unwrap();
+            }
+            if let Some(backoff) = matches.value_of("retry-backoff") {
+                options.retry.backoff = Duration::from_millis(backoff.parse().unwrap());
+            }
+            if let Some(jitter) = matches.value_of("retry-jitter") {
+                options.retry.jitter = Duration::from_millis(jitter.parse().unwrap());
+            }
+            options.skip_broken_segments = matches.occurrences_of("skip-broken-segments") == 1;
+            // Ctrl-C viene ascoltato una volta sola per tutto il programma.
+            options.cancel = cancel::ctrl_c();
+            options
+        },
+        live: live::LiveOptions {
+            duration: matches
+                .value_of("duration")
+                .map(|duration| live::parse_duration(duration).unwrap()),
+            until: matches.value_of("until").map(|until| {
+                live::parse_until(until, Local::now().naive_local())
+                    .and_then(live::local_time)
+                    .unwrap()
+            }),
+        },
+        episodes: program::EpisodeFilter {
+            season: matches
+                .value_of("season")
+                .map(|season| season.parse().unwrap()),
+            episodes: matches
+                .value_of("episodes")
+                .map(|episodes| episodes.parse().unwrap()),
+        },
+        output: matches
+            .value_of("output")
+            .map(|output| output.parse().unwrap()),
+        media_server: matches.is_present("media-server"),
+        thumbnails: matches.is_present("thumbnails"),
+        embed_thumbnail: matches
+            .value_of("embed-thumbnail")
+            .map(|kind| kind.parse().unwrap()),
+        thumbnail_size: matches.value_of("thumbnail-size").map(String::from),
+        archive: matches.value_of("download-archive").map(PathBuf::from),
+    };
+    let settings = Settings {
+        verbose,
+        prompt: if atty::is(atty::Stream::Stdin) {
+            Some(ConsolePrompt {
+                verbose,
+                cancel: options.download_options.cancel.clone(),
+            })
+        } else {
+            None
+        },
+        options,
+        progress: Arc::new(ConsoleReporter::new(verbose)),
+    };
+
+    if let Err(err) = run(&matches, settings).await {
+        eprintln!("{} {}", style(">>").red(), err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// Esegue quanto richiesto dalla riga di comando.
+async fn run(matches: &ArgMatches<'_>, settings: Settings) -> Result<(), Error> {
+    log_in(matches).await?;
+    let jobs_path = PathBuf::from(
+        matches
+            .value_of("jobs-file")
+            .unwrap_or(scheduler::DEFAULT_JOBS_FILE),
+    );
+    // `--run-scheduled` senza `--schedule` esegue solo i job già in attesa.
+    let run_scheduled_only =
+        matches.occurrences_of("run-scheduled") == 1 && matches.value_of("schedule").is_none();
+    if run_scheduled_only {
+        return save::run_scheduled(&jobs_path, &settings.options, settings.progress.as_ref())
+            .await;
+    }
+
+    let mut urls: Vec<String> = matches
+        .values_of("url")
+        .map(|urls| urls.map(String::from).collect())
+        .unwrap_or_default();
+    if let Some(path) = matches.value_of("batch-file") {
+        urls.extend(batch::read_urls(path)?);
+    }
+    if urls.is_empty() {
+        return Err(Error::NothingToDownload(String::from(
+            "the batch file has no URL",
+        )));
     }
 
-    if mp4 {
-        downloader::download(&video_infos.mp4_url, Some(verbose))
-            .await
-            .expect(&format!("Non sono riuscito a scaricare {}", mp4));
-        return;
+    if settings.options.media_server {
+        if let Some(url) = urls.iter().find(|url| sound::is_sound_url(url)) {
+            return Err(Error::Usage(format!(
+                "--media-server only works with RaiPlay videos, not with {}",
+                url
+            )));
+        }
     }
 
-    println!("{}Seleziona la qualità:", if verbose { "\n" } else { "" });
-    for (i, variant) in video_infos.m3u8_variants.iter().enumerate() {
-        println!("  [{}] {}", style(i).cyan(), variant.resolution);
+    if let Some(query) = matches.value_of("schedule") {
+        if let Some(url) = urls.iter().find(|url| !live::is_live_url(url)) {
+            return Err(Error::Usage(format!(
+                "--schedule only works with the URL of a live channel, not with {}",
+                url
+            )));
+        }
+        let date = matches
+            .value_of("date")
+            .map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap())
+            .unwrap_or_else(|| Local::now().date_naive());
+        let padding = |name: &str, default| {
+            matches
+                .value_of(name)
+                .map(|duration| live::parse_duration(duration).unwrap())
+                .unwrap_or(default)
+        };
+        for url in &urls {
+            schedule_broadcast(
+                url,
+                query,
+                date,
+                padding("pad-before", scheduler::DEFAULT_PAD_BEFORE),
+                padding("pad-after", scheduler::DEFAULT_PAD_AFTER),
+                &jobs_path,
+                &settings,
+            )
+            .await?;
+        }
+        return save::run_scheduled(&jobs_path, &settings.options, settings.progress.as_ref())
+            .await;
     }
 
-    let i: usize = loop {
-        let mut input = String::new();
+    match urls.as_slice() {
+        [url] => {
+            let mut summary = batch::Summary::default();
+            save::download_url(
+                url,
+                &settings.options,
+                settings.prompt(),
+                settings.progress.as_ref(),
+                &mut summary,
+            )
+            .await?;
+            // Il riepilogo serve solo se l'URL era di un programma.
+            if summary.entries.len() > 1 {
+                print_summary(&summary);
+            }
+            summary.result()
+        }
+        urls => download_batch(urls, settings).await,
+    }
+}
 
+/// Scarica `urls` uno dopo l'altro e alla fine stampa il riepilogo. Un URL
+/// fallito non ferma i successivi, a meno che non sia stato premuto Ctrl-C.
+async fn download_batch(urls: &[String], settings: Settings) -> Result<(), Error> {
+    // Con più URL la qualità non viene chiesta per ognuno.
+    let settings = Settings {
+        prompt: None,
+        ..settings
+    };
+    let mut summary = batch::Summary::default();
+    for (n, url) in urls.iter().enumerate() {
+        if urls[..n].contains(url) {
+            summary.add(url, Outcome::Skipped(String::from("duplicato")));
+            continue;
+        }
+        if settings.verbose {
+            println!(
+                "\n{} [{}/{}] {}",
+                style("==>").green(),
+                n + 1,
+                urls.len(),
+                style(url).cyan()
+            );
+        }
+        let downloaded = save::download_url(
+            url,
+            &settings.options,
+            None,
+            settings.progress.as_ref(),
+            &mut summary,
+        )
+        .await;
+        match downloaded {
+            Ok(()) => {}
+            Err(Error::Cancelled) => {
+                summary.add(url, Outcome::Failed(Error::Cancelled));
+                print_summary(&summary);
+                return Err(Error::Cancelled);
+            }
+            Err(err) => {
+                eprintln!("{} {}", style(">>").red(), err);
+                summary.add(url, Outcome::Failed(err));
+            }
+        }
+    }
+
+    print_summary(&summary);
+    summary.result()
+}
+
+/// Stampa la tabella degli esiti di `summary`.
+fn print_summary(summary: &batch::Summary) {
+    let downloaded = summary.count(|outcome| matches!(outcome, Outcome::Downloaded));
+    let skipped = summary.count(|outcome| matches!(outcome, Outcome::Skipped(_)));
+    let failed = summary.count(|outcome| matches!(outcome, Outcome::Failed(_)));
+
+    println!(
+        "\n{} {} scaricati, {} saltati, {} falliti",
+        style("Riepilogo:").bold(),
+        style(downloaded).green(),
+        style(skipped).yellow(),
+        style(failed).red()
+    );
+    for (url, outcome) in &summary.entries {
+        match outcome {
+            Outcome::Downloaded => println!("  {}  {}", style("OK     ").green(), url),
+            Outcome::Skipped(reason) => {
+                println!("  {}  {} ({})", style("SALTATO").yellow(), url, reason)
+            }
+            Outcome::Failed(err) => {
+                println!("  {}  {}: {}", style("ERRORE ").red(), url, err)
+            }
+        }
+    }
+}
+
+/// Prepara le credenziali di RaiPlay date con `--cookies`, `--token` o
+/// `--username` e `--password`. Senza nessuna di queste le richieste
+/// partono senza credenziali.
+async fn log_in(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let mut session = auth::Session::default();
+    if let Some(path) = matches.value_of("cookies") {
+        session.cookies = Some(auth::load_cookie_jar(Path::new(path))?);
+    }
+    if let Some(token) = matches.value_of("token") {
+        session.token = Some(token.to_string());
+    } else if let (Some(username), Some(password)) =
+        (matches.value_of("username"), matches.value_of("password"))
+    {
+        let account = auth::Account {
+            username: username.to_string(),
+            password: password.to_string(),
+            cache: matches
+                .value_of("token-file")
+                .map_or_else(auth::default_token_file, PathBuf::from),
+        };
+        return auth::log_in(account, session).await;
+    }
+    auth::set_session(session)
+}
+
+/// Chiede un numero tra 0 e `len - 1` finché non ne viene inserito uno
+/// valido. La lettura si interrompe con Ctrl-C o a fine input.
+async fn prompt_index(len: usize, cancel: &cancel::CancelToken) -> Result<usize, Error> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
         print!("{}", style("==> ").green());
-        std::io::stdout()
-            .flush()
-            .expect("Non sono riuscito a flushare stdout");
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Non hai inserito una stringa valida");
-
-        match input.trim().parse::<i32>() {
-            Ok(num) => {
-                let len = video_infos.m3u8_variants.len() as i32;
-                if num >= 0 && num < len {
-                    break num as usize;
-                } else {
-                    println!(
-                        "\n{} Devi inserire un numero tra 0 e {}\n",
-                        style(">>").red(),
-                        len - 1
-                    );
-                    continue;
-                }
+        std::io::stdout().flush()?;
+        let input = cancel
+            .run(async { Ok(lines.next_line().await?) })
+            .await?
+            .ok_or(Error::Cancelled)?;
+
+        match input.trim().parse::<usize>() {
+            Ok(num) if num < len => break Ok(num),
+            Ok(_) => {
+                println!(
+                    "\n{} Devi inserire un numero tra 0 e {}\n",
+                    style(">>").red(),
+                    len - 1
+                );
             }
             Err(_) => {
                 println!("\n{} Input non valido. Riprova\n", style(">>").red());
-                continue;
             }
         }
-    };
+    }
+}
 
-    println!();
+/// Cerca `query` nel palinsesto del giorno `date` del canale `url` e
+/// aggiunge la registrazione della trasmissione scelta ai job in attesa.
+async fn schedule_broadcast(
+    url: &str,
+    query: &str,
+    date: NaiveDate,
+    pad_before: Duration,
+    pad_after: Duration,
+    jobs_path: &Path,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let progress = settings.progress.as_ref();
+    let channel = live::fetch_channel(url, progress).await?;
+    let broadcasts = scheduler::fetch_schedule(&channel.name, date, progress).await?;
+    let found = scheduler::find_broadcasts(&broadcasts, query, Local::now().naive_local());
+
+    let broadcast = match found.len() {
+        0 => {
+            eprintln!(
+                "{} Nessuna trasmissione in programma corrisponde a {}, il palinsesto di {} è:",
+                style(">>").red(),
+                query,
+                channel.name
+            );
+            for broadcast in &broadcasts {
+                eprintln!("  {}", broadcast.label());
+            }
+            return Err(Error::NothingToDownload(format!(
+                "no scheduled broadcast matches `{}`",
+                query
+            )));
+        }
+        1 => found[0],
+        _ => match settings.prompt() {
+            Some(prompt) => {
+                let labels: Vec<String> = found.iter().map(|broadcast| broadcast.label()).collect();
+                found[prompt.choose("Seleziona la trasmissione:", &labels).await?]
+            }
+            // Senza nessuno a cui chiedere viene scelta la prima in programma.
+            None => found[0],
+        },
+    };
 
-    if m3u8 {
-        video_infos.m3u8_variants[i as usize]
-            .save_m3u8(Path::new(&format!("{}.m3u8", filename)), Some(verbose))
-            .expect("Non sono riuscito a salvare il file .m3u8");
-        return;
+    let job = scheduler::Job::new(
+        url,
+        broadcast,
+        pad_before,
+        pad_after,
+        settings.options.quality.clone(),
+        &settings.options.container,
+    )
+    .map_err(|err| Error::Usage(err.to_string()))?;
+    let mut store = scheduler::JobStore::load(jobs_path)?;
+    let (start, end) = (job.start, job.end);
+    if store.add(job) {
+        store.save(jobs_path)?;
+        println!(
+            "Registrazione di {} su {} programmata dalle {} alle {}",
+            style(&broadcast.title).cyan(),
+            channel.name,
+            style(start.format("%H:%M")).green(),
+            style(end.format("%H:%M")).green()
+        );
+    } else {
+        println!(
+            "{} La registrazione di {} è già in programma",
+            style(">>").yellow(),
+            broadcast.title
+        );
     }
 
-    video_infos.m3u8_variants[i as usize]
-        .download_ts(Path::new(&format!("{}.ts", filename)), Some(verbose))
-        .await
-        .expect("Non sono riuscito a scaricare il file .ts");
+    Ok(())
 }
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaiPlayLiveChannel {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "channel", default)]
+    pub channel: String,
+
+    #[serde(rename = "is_live", default)]
+    pub is_live: bool,
+
+    #[serde(rename = "video")]
+    pub video: LiveVideo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiveVideo {
+    #[serde(rename = "content_url")]
+    pub content_url: String,
+}
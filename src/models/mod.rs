@@ -1 +1,7 @@
+//! JSON di RaiPlay e RaiPlay Sound.
+
+pub mod live;
+pub mod program;
+pub mod schedule;
+pub mod sound;
 pub mod video;
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaiPlayProgram {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "path_id", default)]
+    pub path_id: String,
+
+    #[serde(rename = "blocks", default)]
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(rename = "id", default)]
+    pub id: String,
+
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "type", default)]
+    pub block_type: String,
+
+    #[serde(rename = "sets", default)]
+    pub sets: Vec<Set>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Set {
+    #[serde(rename = "id", default)]
+    pub id: String,
+
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "path_id", default)]
+    pub path_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetContents {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "items", default)]
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Item {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "path_id", default)]
+    pub path_id: String,
+
+    #[serde(rename = "weblink", default)]
+    pub weblink: String,
+
+    #[serde(rename = "season", default)]
+    pub season: String,
+
+    #[serde(rename = "episode", default)]
+    pub episode: String,
+
+    #[serde(rename = "episode_title", default)]
+    pub episode_title: String,
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaiPlaySchedule {
+    #[serde(rename = "channel", default)]
+    pub channel: String,
+
+    #[serde(rename = "events", default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "episode_title", default)]
+    pub episode_title: String,
+
+    /// Ora di inizio, nel formato `HH:MM`.
+    #[serde(rename = "hour", default)]
+    pub hour: String,
+
+    /// Durata, nel formato `HH:MM:SS`.
+    #[serde(rename = "duration", default)]
+    pub duration: String,
+
+    #[serde(rename = "weblink", default)]
+    pub weblink: String,
+}
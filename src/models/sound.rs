@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaiPlaySoundEpisode {
+    #[serde(rename = "uniquename", default)]
+    pub uniquename: String,
+
+    #[serde(rename = "title", default)]
+    pub title: String,
+
+    #[serde(rename = "episode_title", default)]
+    pub episode_title: String,
+
+    #[serde(rename = "description", default)]
+    pub description: String,
+
+    #[serde(rename = "create_date", default)]
+    pub create_date: String,
+
+    #[serde(rename = "season", default)]
+    pub season: String,
+
+    #[serde(rename = "episode", default)]
+    pub episode: String,
+
+    #[serde(rename = "image", default)]
+    pub image: String,
+
+    #[serde(rename = "audio")]
+    pub audio: Audio,
+
+    #[serde(rename = "podcast_info", default)]
+    pub podcast_info: PodcastInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Audio {
+    #[serde(rename = "url")]
+    pub url: String,
+
+    #[serde(rename = "duration", default)]
+    pub duration: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PodcastInfo {
+    #[serde(rename = "title", default)]
+    pub title: String,
+
+    #[serde(rename = "description", default)]
+    pub description: String,
+
+    #[serde(rename = "image", default)]
+    pub image: String,
+
+    #[serde(rename = "channel", default)]
+    pub channel: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaiPlaySoundProgram {
+    #[serde(rename = "title", default)]
+    pub title: String,
+
+    #[serde(rename = "blocks", default)]
+    pub blocks: Vec<Block>,
+}
+
+/// Blocco della pagina di un programma: contiene gli episodi direttamente
+/// in `cards` o li divide in `sets` da scaricare a parte.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "cards", default)]
+    pub cards: Vec<Card>,
+
+    #[serde(rename = "sets", default)]
+    pub sets: Vec<Set>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Set {
+    #[serde(rename = "name", default)]
+    pub name: String,
+
+    #[serde(rename = "path_id", default)]
+    pub path_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetContents {
+    #[serde(rename = "cards", default)]
+    pub cards: Vec<Card>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Card {
+    #[serde(rename = "title", default)]
+    pub title: String,
+
+    #[serde(rename = "path_id", default)]
+    pub path_id: String,
+
+    #[serde(rename = "weblink", default)]
+    pub weblink: String,
+}
@@ -102,7 +102,7 @@ pub struct RaiPlayVideo {
     pub adv: bool,
 
     #[serde(rename = "dfp")]
-    pub dfp: GeoprotectionClass,
+    pub dfp: ProgramInfoDfp,
 
     #[serde(rename = "rights_management")]
     pub rights_management: RightsManagement,
@@ -129,8 +129,21 @@ pub struct Detail {
     pub value: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GeoprotectionClass {}
+/// Un diritto sul contenuto, come `{"value": "Y"}`. Manca del tutto (`{}`)
+/// se il diritto non si applica.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Right {
+    #[serde(rename = "value", default)]
+    pub value: Option<String>,
+}
+
+impl Right {
+    pub fn is_set(&self) -> bool {
+        self.value
+            .as_deref()
+            .is_some_and(|value| value.eq_ignore_ascii_case("y"))
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Images {
@@ -279,18 +292,19 @@ pub struct ProgramInfo {
     pub adv: bool,
 }
 
+/// Dati per la pubblicità. Nei video possono mancare tutti (`{}`).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProgramInfoDfp {
-    #[serde(rename = "escaped_name")]
+    #[serde(rename = "escaped_name", default)]
     pub escaped_name: String,
 
-    #[serde(rename = "label")]
+    #[serde(rename = "label", default)]
     pub label: String,
 
-    #[serde(rename = "escaped_genres")]
+    #[serde(rename = "escaped_genres", default)]
     pub escaped_genres: Vec<ProgramCategory>,
 
-    #[serde(rename = "escaped_typology")]
+    #[serde(rename = "escaped_typology", default)]
     pub escaped_typology: Vec<ProgramCategory>,
 }
 
@@ -326,11 +340,13 @@ pub struct RightsManagement {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Rights {
-    #[serde(rename = "offline")]
-    pub offline: GeoprotectionClass,
+    /// Se il contenuto si può scaricare nell'app per vederlo offline.
+    #[serde(rename = "offline", default)]
+    pub offline: Right,
 
-    #[serde(rename = "geoprotection")]
-    pub geoprotection: GeoprotectionClass,
+    /// Se il contenuto è visibile solo dall'Italia.
+    #[serde(rename = "geoprotection", default)]
+    pub geoprotection: Right,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -440,6 +456,18 @@ pub struct Video {
     #[serde(rename = "subtitles")]
     pub subtitles: String,
 
-    #[serde(rename = "subtitlesArray")]
-    pub subtitles_array: Vec<Option<serde_json::Value>>,
+    #[serde(rename = "subtitlesArray", default)]
+    pub subtitles_array: Vec<Option<Subtitle>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subtitle {
+    #[serde(rename = "language", default)]
+    pub language: String,
+
+    #[serde(rename = "url", default)]
+    pub url: String,
+
+    #[serde(rename = "format", default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
 }
@@ -0,0 +1,260 @@
+#![warn(clippy::all)]
+
+//! Disposizione dei file per i media server come Jellyfin, Kodi e Plex:
+//! `Programma/Season NN/Programma - SNNEMM - Titolo.ext`, con accanto le
+//! schede NFO dell'episodio e del programma e le sue immagini.
+
+use crate::api;
+use crate::error::Error;
+use crate::images;
+use crate::models::video::{ProgramInfo, RaiPlayVideo};
+use crate::progress::{ProgressEvent, ProgressReporter};
+use crate::sanitize_path;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Dimensioni chieste per la locandina verticale e per lo sfondo.
+const POSTER_RESOLUTION: &str = "600x800";
+const FANART_RESOLUTION: &str = "1920x1080";
+
+lazy_static! {
+    static ref NUMBER_RE: Regex = Regex::new(r"\d+").unwrap();
+}
+
+fn first_number(s: &str) -> Option<u32> {
+    NUMBER_RE.find(s)?.as_str().parse().ok()
+}
+
+fn non_empty(s: &str) -> Option<&str> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+fn show_name(video: &RaiPlayVideo) -> &str {
+    non_empty(&video.program_info.name).unwrap_or_else(|| video.name.trim())
+}
+
+/// Percorso del video senza estensione, come
+/// `Il Collegio/Season 04/Il Collegio - S04E02 - Titolo`. Senza stagione si
+/// usa la prima; senza episodio il nome contiene la data di pubblicazione,
+/// che i media server riconoscono come episodio di un programma quotidiano.
+pub fn episode_stem(video: &RaiPlayVideo) -> String {
+    stem(
+        show_name(video),
+        &video.season,
+        &video.episode,
+        &video.date_published,
+        non_empty(&video.episode_title).unwrap_or_else(|| video.name.trim()),
+    )
+}
+
+fn stem(show: &str, season: &str, episode: &str, date: &str, title: &str) -> String {
+    let season = first_number(season).unwrap_or(1);
+    let episode = match first_number(episode) {
+        Some(episode) => format!("S{:02}E{:02}", season, episode),
+        None => api::iso_date(date).unwrap_or_else(|| format!("S{:02}", season)),
+    };
+
+    let sanitize = |s: &str| sanitize_path::sanitize(s, None, None);
+    format!(
+        "{}/{}/{}",
+        sanitize(show),
+        sanitize(&format!("Season {:02}", season)),
+        sanitize(&format!("{} - {} - {}", show, episode, title))
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Aggiunge l'elemento `<tag>` con `value`, se non è vuoto.
+fn element(xml: &mut String, tag: &str, value: &str) {
+    if let Some(value) = non_empty(value) {
+        writeln!(xml, "  <{0}>{1}</{0}>", tag, escape(value)).unwrap();
+    }
+}
+
+/// Aggiunge un elemento `<tag>` per ogni nome della lista `names`, separata
+/// da virgole come i registi e gli attori di RaiPlay.
+fn elements(xml: &mut String, tag: &str, names: &str) {
+    for name in names.split(',') {
+        element(xml, tag, name);
+    }
+}
+
+fn actors(xml: &mut String, names: &str) {
+    for name in names.split(',').filter_map(non_empty) {
+        writeln!(
+            xml,
+            "  <actor>\n    <name>{}</name>\n  </actor>",
+            escape(name)
+        )
+        .unwrap();
+    }
+}
+
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+
+/// Scheda `tvshow.nfo` del programma.
+pub fn tvshow_nfo(program: &ProgramInfo) -> String {
+    let mut xml = String::from(XML_HEADER);
+    xml.push_str("<tvshow>\n");
+    element(&mut xml, "title", &program.name);
+    element(&mut xml, "plot", &program.description);
+    element(&mut xml, "year", &program.year);
+    element(&mut xml, "studio", &program.channel);
+    elements(&mut xml, "country", &program.country);
+    elements(&mut xml, "director", &program.direction);
+    for genre in &program.genres {
+        element(&mut xml, "genre", &genre.name);
+    }
+    actors(&mut xml, &program.actors);
+    xml.push_str("</tvshow>\n");
+    xml
+}
+
+/// Scheda NFO dell'episodio, da salvare accanto al video. Regia e attori
+/// sono quelli del programma se l'episodio non li indica.
+pub fn episode_nfo(video: &RaiPlayVideo) -> String {
+    let program = &video.program_info;
+    let direction = non_empty(&video.direction).unwrap_or(&program.direction);
+    let cast = non_empty(&video.actors).unwrap_or(&program.actors);
+
+    let mut xml = String::from(XML_HEADER);
+    xml.push_str("<episodedetails>\n");
+    element(
+        &mut xml,
+        "title",
+        non_empty(&video.episode_title).unwrap_or(&video.name),
+    );
+    element(&mut xml, "showtitle", show_name(video));
+    element(
+        &mut xml,
+        "season",
+        &first_number(&video.season).unwrap_or(1).to_string(),
+    );
+    if let Some(episode) = first_number(&video.episode) {
+        element(&mut xml, "episode", &episode.to_string());
+    }
+    element(
+        &mut xml,
+        "plot",
+        non_empty(&video.description).unwrap_or(&program.description),
+    );
+    if let Some(date) = api::iso_date(&video.date_published) {
+        element(&mut xml, "aired", &date);
+    }
+    element(&mut xml, "studio", &video.channel);
+    elements(&mut xml, "director", direction);
+    actors(&mut xml, cast);
+    xml.push_str("</episodedetails>\n");
+    xml
+}
+
+/// Salva accanto al video `output` la scheda dell'episodio e, nella
+/// directory del programma, `tvshow.nfo`, `poster` e `fanart` (con
+/// l'estensione delle immagini) se non ci sono già. Le immagini che non si
+/// riescono a scaricare vengono segnalate senza interrompere il download.
+pub async fn write_sidecars(
+    video: &RaiPlayVideo,
+    output: &Path,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    fs::write(output.with_extension("nfo"), episode_nfo(video))?;
+
+    let show_dir = match output.parent().and_then(Path::parent) {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let tvshow = show_dir.join("tvshow.nfo");
+    if !tvshow.exists() {
+        fs::write(&tvshow, tvshow_nfo(&video.program_info))?;
+    }
+
+    let images = &video.program_info.images;
+    let artwork = [
+        ("poster", &images.portrait, POSTER_RESOLUTION),
+        ("fanart", &images.landscape, FANART_RESOLUTION),
+    ];
+    for (kind, image, resolution) in artwork.iter() {
+        let url = match images::resolve(images::RAI_PLAY_BASE_URL, image, resolution) {
+            Some(url) => url,
+            None => continue,
+        };
+        let name = format!("{}.{}", kind, images::extension(&url));
+        let path = show_dir.join(&name);
+        if path.exists() {
+            continue;
+        }
+        let result = match images::fetch(&url).await {
+            Ok(data) => fs::write(&path, data).map_err(Error::from),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            progress.report(ProgressEvent::Warning(&format!(
+                "Non sono riuscito a scaricare {}: {}",
+                name, err
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(
+            stem(
+                "Il Collegio",
+                "4",
+                "2",
+                "22/10/2019",
+                "Regole: niente cellulari"
+            ),
+            "Il Collegio/Season 04/Il Collegio - S04E02 - Regole! niente cellulari"
+        );
+        assert_eq!(
+            stem(
+                "Techetechetè",
+                "",
+                "",
+                "22/10/2019 20:35",
+                "Puntata del 22/10/2019"
+            ),
+            "Techetechetè/Season 01/Techetechetè - 2019-10-22 - Puntata del 22!10!2019"
+        );
+
+        let mut xml = String::new();
+        element(&mut xml, "plot", "I ragazzi & <i professori>");
+        element(&mut xml, "year", " ");
+        actors(&mut xml, "Paolo Bosisio, Maria Rosa Petolicchio");
+        assert_eq!(
+            xml,
+            "  <plot>I ragazzi &amp; &lt;i professori&gt;</plot>\n\
+             \x20 <actor>\n    <name>Paolo Bosisio</name>\n  </actor>\n\
+             \x20 <actor>\n    <name>Maria Rosa Petolicchio</name>\n  </actor>\n"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+#![warn(clippy::all)]
+
+//! Pagine dei programmi di RaiPlay (`raiplay.it/programmi/<nome>`): elenco
+//! delle stagioni e degli episodi da scaricare.
+
+use crate::error::Error;
+use crate::http;
+use crate::models::program;
+use crate::progress::{ProgressEvent, ProgressReporter, Resource};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+/// Dominio rispetto a cui risolvere i `path_id` dei JSON di RaiPlay.
+const RAI_PLAY_BASE_URL: &str = "https://www.raiplay.it/";
+
+/// Tipo dei blocchi che contengono gli episodi, e non clip o extra.
+const MULTIMEDIA_BLOCK: &str = "RaiPlay Multimedia Block";
+
+lazy_static! {
+    static ref PROGRAM_URL_RE: Regex =
+        Regex::new(r"^https?://(?:www\.)?raiplay\.it/programmi/([^/?#.]+)").unwrap();
+    static ref NUMBER_RE: Regex = Regex::new(r"\d+").unwrap();
+}
+
+#[derive(Debug)]
+pub struct InvalidEpisodesError(pub String);
+
+impl fmt::Display for InvalidEpisodesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Episode list is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidEpisodesError {}
+
+/// Insieme di episodi, come `3-7` o `1,4,9-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeRanges(Vec<(u32, Option<u32>)>);
+
+impl FromStr for EpisodeRanges {
+    type Err = InvalidEpisodesError;
+
+    fn from_str(s: &str) -> Result<EpisodeRanges, InvalidEpisodesError> {
+        let invalid = || InvalidEpisodesError(s.to_string());
+        let parse = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+
+        let mut ranges = Vec::new();
+        for part in s.split(',') {
+            let range = match part.find('-') {
+                Some(i) => {
+                    let end = part[i + 1..].trim();
+                    let end = if end.is_empty() {
+                        None
+                    } else {
+                        Some(parse(end)?)
+                    };
+                    (parse(&part[..i])?, end)
+                }
+                None => {
+                    let n = parse(part)?;
+                    (n, Some(n))
+                }
+            };
+            if range.1.is_some_and(|end| end < range.0) {
+                return Err(invalid());
+            }
+            ranges.push(range);
+        }
+        Ok(EpisodeRanges(ranges))
+    }
+}
+
+impl EpisodeRanges {
+    pub fn contains(&self, episode: u32) -> bool {
+        self.0
+            .iter()
+            .any(|&(start, end)| episode >= start && end.is_none_or(|end| episode <= end))
+    }
+}
+
+/// Filtri sugli episodi da scaricare.
+#[derive(Debug, Default, Clone)]
+pub struct EpisodeFilter {
+    pub season: Option<u32>,
+    pub episodes: Option<EpisodeRanges>,
+}
+
+impl EpisodeFilter {
+    fn matches(&self, episode: &ProgramEpisode) -> bool {
+        let season = self
+            .season
+            .is_none_or(|season| episode.season == Some(season));
+        let number = self
+            .episodes
+            .as_ref()
+            .is_none_or(|ranges| ranges.contains(episode.episode));
+        season && number
+    }
+}
+
+/// Episodio di un programma, con l'URL della sua pagina.
+#[derive(Debug, Clone)]
+pub struct ProgramEpisode {
+    pub url: String,
+    /// Percorso del JSON dell'episodio, come il `path_id` del video.
+    pub path_id: String,
+    pub name: String,
+    pub season: Option<u32>,
+    /// Numero dell'episodio, o la sua posizione nella stagione se RaiPlay
+    /// non lo indica.
+    pub episode: u32,
+}
+
+impl ProgramEpisode {
+    pub fn label(&self) -> String {
+        match self.season {
+            Some(season) => format!("S{:02}E{:02} {}", season, self.episode, self.name),
+            None => format!("E{:02} {}", self.episode, self.name),
+        }
+    }
+}
+
+/// Vero se `url` è la pagina di un programma.
+pub fn is_program_url(url: &str) -> bool {
+    PROGRAM_URL_RE.is_match(url)
+}
+
+fn first_number(s: &str) -> Option<u32> {
+    NUMBER_RE.find(s)?.as_str().parse().ok()
+}
+
+/// Scarica l'elenco degli episodi del programma e tiene quelli che
+/// soddisfano `filter`, nell'ordine di RaiPlay.
+pub async fn fetch_episodes(
+    url: &str,
+    filter: &EpisodeFilter,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<ProgramEpisode>, Error> {
+    let name = match PROGRAM_URL_RE.captures(url) {
+        Some(caps) => caps[1].to_string(),
+        None => return Err(Error::InvalidUrl(url.to_string())),
+    };
+    let base_url = reqwest::Url::parse(RAI_PLAY_BASE_URL)?;
+
+    progress.report(ProgressEvent::FetchingMetadata(Resource::Episodes));
+    let program_url = base_url.join(&format!("programmi/{}.json", name))?;
+    let program: program::RaiPlayProgram = http::client()?
+        .get(program_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // Se il programma ha blocchi di episodi, clip ed extra vengono ignorati.
+    let has_multimedia = program
+        .blocks
+        .iter()
+        .any(|block| block.block_type == MULTIMEDIA_BLOCK);
+    let sets = program
+        .blocks
+        .iter()
+        .filter(|block| !has_multimedia || block.block_type == MULTIMEDIA_BLOCK)
+        .flat_map(|block| block.sets.iter())
+        .filter(|set| !set.path_id.is_empty());
+
+    let mut episodes: Vec<ProgramEpisode> = Vec::new();
+    for set in sets {
+        let set_season = first_number(&set.name);
+        let contents: program::SetContents = http::client()?
+            .get(base_url.join(&set.path_id)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        for (i, item) in contents.items.iter().enumerate() {
+            let link = if item.weblink.is_empty() {
+                item.path_id.replace(".json", ".html")
+            } else {
+                item.weblink.clone()
+            };
+            if link.is_empty() {
+                continue;
+            }
+            let url = base_url.join(&link)?.to_string();
+            if episodes.iter().any(|episode| episode.url == url) {
+                continue;
+            }
+
+            let name = if item.episode_title.is_empty() {
+                item.name.clone()
+            } else {
+                item.episode_title.clone()
+            };
+            episodes.push(ProgramEpisode {
+                url,
+                path_id: item.path_id.clone(),
+                name,
+                season: first_number(&item.season).or(set_season),
+                episode: first_number(&item.episode).unwrap_or(i as u32 + 1),
+            });
+        }
+    }
+    progress.report(ProgressEvent::MetadataFetched(Resource::Episodes));
+    progress.report(ProgressEvent::EpisodesFound {
+        program: &program.name,
+        count: episodes.len(),
+    });
+
+    Ok(episodes
+        .into_iter()
+        .filter(|episode| filter.matches(episode))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert!(is_program_url(
+            "https://www.raiplay.it/programmi/ilcollegio"
+        ));
+        assert!(is_program_url(
+            "https://raiplay.it/programmi/ilcollegio/episodi"
+        ));
+        assert!(!is_program_url(
+            "https://www.raiplay.it/video/2019/10/Il-Collegio-4-6f9681db-62ff-4094-8272-7f5babaebc29.html"
+        ));
+
+        let ranges: EpisodeRanges = "3-7,10,12-".parse().unwrap();
+        assert!(!ranges.contains(2));
+        assert!(ranges.contains(3) && ranges.contains(7));
+        assert!(!ranges.contains(8));
+        assert!(ranges.contains(10));
+        assert!(!ranges.contains(11));
+        assert!(ranges.contains(40));
+        assert!("7-3".parse::<EpisodeRanges>().is_err());
+        assert!("a-b".parse::<EpisodeRanges>().is_err());
+
+        let filter = EpisodeFilter {
+            season: Some(2),
+            episodes: Some(ranges),
+        };
+        let episode = |season, episode| ProgramEpisode {
+            url: String::new(),
+            path_id: String::new(),
+            name: String::new(),
+            season,
+            episode,
+        };
+        assert!(filter.matches(&episode(Some(2), 4)));
+        assert!(!filter.matches(&episode(Some(1), 4)));
+        assert!(!filter.matches(&episode(None, 4)));
+        assert!(!filter.matches(&episode(Some(2), 9)));
+    }
+}
@@ -0,0 +1,508 @@
+#![warn(clippy::all)]
+
+//! Avanzamento dei download come eventi strutturati. Le funzioni della
+//! libreria ricevono un [`ProgressReporter`] a cui segnalano cosa stanno
+//! facendo; [`ConsoleReporter`] li mostra nel terminale come fa la CLI.
+
+use chrono::{DateTime, Local};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Dati scaricati prima dei file veri e propri.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    /// Il JSON con le informazioni sul video.
+    VideoInfo,
+    /// Le varianti della master playlist.
+    Variants,
+    /// L'URL del file MP4 del video.
+    Mp4Url,
+    /// I segmenti della media playlist di una variante.
+    Segments,
+    /// Il JSON con le informazioni su una diretta.
+    ChannelInfo,
+    /// Il palinsesto di un canale.
+    Schedule,
+    /// L'elenco degli episodi di un programma.
+    Episodes,
+    /// Il JSON con le informazioni su un episodio di RaiPlay Sound.
+    EpisodeInfo,
+}
+
+impl Resource {
+    fn description(self) -> &'static str {
+        match self {
+            Resource::VideoInfo => "il JSON contenente le informazioni sul video",
+            Resource::Variants => "le varianti M3U8",
+            Resource::Mp4Url => "l'URL MP4 del video",
+            Resource::Segments => "i segmenti M3U8 per la variante",
+            Resource::ChannelInfo => "il JSON contenente le informazioni sulla diretta",
+            Resource::Schedule => "il palinsesto",
+            Resource::Episodes => "la lista degli episodi",
+            Resource::EpisodeInfo => "il JSON contenente le informazioni sull'episodio",
+        }
+    }
+}
+
+/// Cosa sta succedendo durante un download.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent<'a> {
+    FetchingMetadata(Resource),
+    MetadataFetched(Resource),
+    /// Trovati `count` episodi del programma `program`.
+    EpisodesFound {
+        program: &'a str,
+        count: usize,
+    },
+    /// Inizia il download dell'episodio `label`, il numero `index` (da 0)
+    /// dei `count` di un programma.
+    EpisodeStarted {
+        index: usize,
+        count: usize,
+        label: &'a str,
+    },
+    /// `name` è già nell'archivio e viene saltato.
+    AlreadyArchived {
+        name: &'a str,
+    },
+    /// Il download di `label` è fallito, ma si prosegue con i successivi.
+    Failed {
+        label: &'a str,
+        cause: &'a str,
+    },
+    /// Scelta la variante o la traccia audio alternativa `label`.
+    VariantSelected {
+        label: &'a str,
+        audio: bool,
+    },
+    /// Inizia il download in `path` di `segments` segmenti, di cui i primi
+    /// `resumed` erano già stati scaricati, o di un file di `bytes` byte.
+    DownloadStarted {
+        path: &'a Path,
+        segments: Option<u64>,
+        bytes: Option<u64>,
+        resumed: u64,
+    },
+    /// Inizia la registrazione di una diretta in `path`.
+    RecordingStarted {
+        path: &'a Path,
+    },
+    SegmentStarted {
+        index: usize,
+    },
+    SegmentRetried {
+        index: usize,
+        attempt: u32,
+        retries: u32,
+        cause: &'a str,
+    },
+    SegmentCompleted {
+        index: usize,
+        bytes: u64,
+    },
+    SegmentSkipped {
+        index: usize,
+        cause: &'a str,
+    },
+    /// Byte scritti finora nel file, su `total` se noto.
+    BytesWritten {
+        written: u64,
+        total: Option<u64>,
+    },
+    /// Durata in secondi della diretta registrata finora.
+    Recorded {
+        duration: f32,
+        segments: u64,
+        bytes: u64,
+    },
+    /// `count` segmenti mancano dal file, elencati nel report `report`.
+    GapsSaved {
+        count: u64,
+        report: &'a Path,
+    },
+    /// La playlist della diretta è terminata.
+    LiveEnded,
+    /// Inizia la conversione nel contenitore `container`.
+    Converting {
+        container: &'a str,
+    },
+    Converted,
+    /// Il risultato finale, `path`, è pronto.
+    Saved {
+        path: &'a Path,
+    },
+    /// Non ci sono registrazioni in programma.
+    NoJobs,
+    /// Si aspetta l'ora di inizio `start` della registrazione `title`.
+    JobWaiting {
+        title: &'a str,
+        start: DateTime<Local>,
+    },
+    /// Inizia la registrazione in programma `title`.
+    JobStarted {
+        title: &'a str,
+    },
+    Warning(&'a str),
+    /// Il download si è interrotto con un errore.
+    Aborted,
+    /// Il file `path` è stato salvato.
+    Finished {
+        path: &'a Path,
+    },
+}
+
+/// Riceve gli eventi di avanzamento. È implementato anche per le closure,
+/// ad esempio `&|event| println!("{:?}", event)`.
+pub trait ProgressReporter: Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+impl<F> ProgressReporter for F
+where
+    F: Fn(ProgressEvent) + Sync,
+{
+    fn report(&self, event: ProgressEvent) {
+        self(event)
+    }
+}
+
+/// Ignora tutti gli eventi.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn report(&self, _event: ProgressEvent) {}
+}
+
+/// Stato della barra del download in corso.
+enum Bar {
+    Segments(ProgressBar, u64),
+    Bytes(ProgressBar),
+    Recording(ProgressBar, f32),
+}
+
+impl Bar {
+    fn progress_bar(&self) -> &ProgressBar {
+        match self {
+            Bar::Segments(bar, _) | Bar::Bytes(bar) | Bar::Recording(bar, _) => bar,
+        }
+    }
+}
+
+/// Mostra l'avanzamento nel terminale con le barre di indicatif. Se
+/// `verbose` è falso vengono mostrati solo le barre e gli avvisi.
+pub struct ConsoleReporter {
+    verbose: bool,
+    bar: Mutex<Option<Bar>>,
+}
+
+impl ConsoleReporter {
+    pub fn new(verbose: bool) -> ConsoleReporter {
+        ConsoleReporter {
+            verbose,
+            bar: Mutex::new(None),
+        }
+    }
+
+    /// Stampa `line` senza rovinare la barra in corso.
+    fn println(&self, bar: &Option<Bar>, line: String) {
+        match bar {
+            Some(bar) => bar.progress_bar().println(line),
+            None => eprintln!("{}", line),
+        }
+    }
+}
+
+impl ProgressReporter for ConsoleReporter {
+    fn report(&self, event: ProgressEvent) {
+        let mut bar = self.bar.lock().unwrap();
+        let verbose = self.verbose;
+
+        match event {
+            ProgressEvent::FetchingMetadata(resource) if verbose => {
+                print!("Ottenendo {}...", resource.description());
+                std::io::stdout().flush().ok();
+            }
+            ProgressEvent::MetadataFetched(_) if verbose => {
+                println!("{}", style(" fatto").green());
+            }
+            ProgressEvent::EpisodesFound { program, count } if verbose => {
+                println!(
+                    "Trovati {} episodi di {}",
+                    style(count).cyan(),
+                    style(program).green()
+                );
+            }
+            ProgressEvent::EpisodeStarted {
+                index,
+                count,
+                label,
+            } if verbose => {
+                println!(
+                    "\n{} [{}/{}] {}",
+                    style("==>").green(),
+                    index + 1,
+                    count,
+                    style(label).cyan()
+                );
+            }
+            ProgressEvent::AlreadyArchived { name } if verbose => {
+                println!(
+                    "{} {} è già nell'archivio, lo salto",
+                    style(">>").yellow(),
+                    name
+                );
+            }
+            ProgressEvent::Failed { label, cause } => {
+                self.println(&bar, format!("{} {}: {}", style(">>").red(), label, cause));
+            }
+            ProgressEvent::VariantSelected { label, audio } if verbose => {
+                let kind = if audio { "Traccia audio" } else { "Variante" };
+                println!("\n{}: {}", kind, style(label).cyan());
+            }
+            ProgressEvent::DownloadStarted {
+                segments: Some(segments),
+                resumed,
+                ..
+            } => {
+                if verbose && resumed > 0 {
+                    println!(
+                        "Riprendo il download dal segmento {} / {}",
+                        style(resumed).green(),
+                        segments
+                    );
+                }
+                let pbar = ProgressBar::new(segments).with_style(
+                    ProgressStyle::default_bar().template(
+                        "[{prefix} / {msg}] [{wide_bar:.cyan/blue}] segmento {pos} / {len} [{elapsed} .. {eta}]",
+                    ),
+                );
+                pbar.set_prefix(&style("0").green().to_string());
+                pbar.set_message("0");
+                pbar.set_position(resumed);
+                pbar.enable_steady_tick(1000);
+                *bar = Some(Bar::Segments(pbar, segments));
+            }
+            ProgressEvent::DownloadStarted { path, bytes, .. } => {
+                if verbose {
+                    println!("\nSalvo in: {}", style(path.display()).green());
+                    match bytes {
+                        Some(len) => println!(
+                            "Content-Length: {} ({})",
+                            style(len).green(),
+                            style(HumanBytes(len)).cyan()
+                        ),
+                        None => println!("Content-Length: {}", style("unknown").red()),
+                    }
+                }
+                let pbar = match bytes {
+                    Some(len) => ProgressBar::new(len).with_style(
+                        ProgressStyle::default_bar().template(
+                            "{wide_bar:.cyan/blue} {bytes:.green} / {total_bytes} [{elapsed} .. {eta:.cyan}]",
+                        ),
+                    ),
+                    None => ProgressBar::new(0).with_style(
+                        ProgressStyle::default_spinner()
+                            .template("[{elapsed_precise}] {spinner:.green} {bytes:.cyan}"),
+                    ),
+                };
+                *bar = Some(Bar::Bytes(pbar));
+            }
+            ProgressEvent::RecordingStarted { .. } => {
+                let pbar = ProgressBar::new_spinner().with_style(
+                    ProgressStyle::default_spinner().template("{spinner:.cyan} [{elapsed}] {msg}"),
+                );
+                pbar.set_message("in attesa del primo segmento");
+                pbar.enable_steady_tick(200);
+                *bar = Some(Bar::Recording(pbar, 0.0));
+            }
+            ProgressEvent::SegmentRetried {
+                index,
+                attempt,
+                retries,
+                cause,
+            } if verbose => {
+                self.println(
+                    &bar,
+                    format!(
+                        "{} Segmento {}: {}, nuovo tentativo {} / {}",
+                        style(">>").yellow(),
+                        index,
+                        cause,
+                        attempt,
+                        retries
+                    ),
+                );
+            }
+            ProgressEvent::SegmentCompleted { .. } => {
+                if let Some(Bar::Segments(pbar, _)) = bar.as_ref() {
+                    pbar.inc(1);
+                }
+            }
+            ProgressEvent::SegmentSkipped { index, cause } => {
+                self.println(
+                    &bar,
+                    format!(
+                        "{} Segmento {} saltato: {}",
+                        style(">>").red(),
+                        index,
+                        cause
+                    ),
+                );
+            }
+            ProgressEvent::BytesWritten { written, .. } => match bar.as_ref() {
+                Some(Bar::Segments(pbar, segments)) => {
+                    pbar.set_prefix(&style(HumanBytes(written)).green().to_string());
+                    if pbar.position() > 0 {
+                        let predicted = written / pbar.position() * segments;
+                        pbar.set_message(&HumanBytes(predicted).to_string());
+                    }
+                }
+                Some(Bar::Bytes(pbar)) => pbar.set_position(written),
+                _ => {}
+            },
+            ProgressEvent::Recorded {
+                duration,
+                segments,
+                bytes,
+            } => {
+                if let Some(Bar::Recording(pbar, recorded)) = bar.as_mut() {
+                    pbar.set_message(&format!(
+                        "registrati {} ({} segmenti, {})",
+                        style(format_seconds(duration)).green(),
+                        segments,
+                        HumanBytes(bytes)
+                    ));
+                    *recorded = duration;
+                }
+            }
+            ProgressEvent::GapsSaved { count, report } => {
+                let lost = match bar.as_ref() {
+                    Some(Bar::Recording(..)) => "persi",
+                    _ => "saltati",
+                };
+                self.println(
+                    &bar,
+                    format!(
+                        "{} {} segmenti {}, report salvato in {:#?}",
+                        style(">>").red(),
+                        count,
+                        lost,
+                        style(report).green()
+                    ),
+                );
+            }
+            ProgressEvent::LiveEnded if verbose => {
+                self.println(
+                    &bar,
+                    format!("{} La diretta è terminata", style(">>").green()),
+                );
+            }
+            ProgressEvent::Converting { container } if verbose => {
+                print!("Convertendo in {}...", container);
+                std::io::stdout().flush().ok();
+            }
+            ProgressEvent::Converted if verbose => {
+                println!("{}", style(" fatto").green());
+            }
+            ProgressEvent::Saved { path } if verbose => {
+                println!("Salvato in {:#?}", style(path).green());
+            }
+            ProgressEvent::NoJobs if verbose => {
+                println!("Nessuna registrazione in programma");
+            }
+            ProgressEvent::JobWaiting { title, start } if verbose => {
+                println!(
+                    "In attesa di registrare {} alle {}",
+                    style(title).cyan(),
+                    style(start.format("%Y-%m-%d %H:%M")).green()
+                );
+            }
+            ProgressEvent::JobStarted { title } if verbose => {
+                println!(
+                    "\n{} Registrazione di {}",
+                    style("==>").green(),
+                    style(title).cyan()
+                );
+            }
+            ProgressEvent::Warning(message) => {
+                self.println(&bar, format!("{} {}", style(">>").yellow(), message));
+            }
+            ProgressEvent::Aborted => {
+                if let Some(bar) = bar.take() {
+                    bar.progress_bar().abandon();
+                }
+            }
+            ProgressEvent::Finished { path } => {
+                let recorded = match bar.take() {
+                    Some(Bar::Recording(pbar, recorded)) => {
+                        pbar.finish();
+                        Some(recorded)
+                    }
+                    Some(bar) => {
+                        bar.progress_bar().finish();
+                        None
+                    }
+                    None => None,
+                };
+                if verbose {
+                    match recorded {
+                        Some(recorded) => println!(
+                            "Registrati {} in {:#?}",
+                            format_seconds(recorded),
+                            style(path).green()
+                        ),
+                        None => println!("File salvato in {:#?}", style(path).green()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Durata nel formato `HH:MM:SS`.
+pub fn format_seconds(seconds: f32) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(format_seconds(3725.4), "01:02:05");
+
+        let completed = Mutex::new(Vec::new());
+        let reporter = |event: ProgressEvent| {
+            if let ProgressEvent::SegmentCompleted { index, .. } = event {
+                completed.lock().unwrap().push(index);
+            }
+        };
+        let progress: &dyn ProgressReporter = &reporter;
+        progress.report(ProgressEvent::SegmentStarted { index: 0 });
+        progress.report(ProgressEvent::SegmentCompleted {
+            index: 0,
+            bytes: 10,
+        });
+        progress.report(ProgressEvent::SegmentCompleted {
+            index: 1,
+            bytes: 10,
+        });
+        NoProgress.report(ProgressEvent::SegmentCompleted {
+            index: 2,
+            bytes: 10,
+        });
+        assert_eq!(*completed.lock().unwrap(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,153 @@
+#![warn(clippy::all)]
+
+use super::{Codec, Sample};
+
+/// Campioni PCM contenuti in un frame AAC.
+pub const SAMPLES_PER_FRAME: u64 = 1024;
+
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Campi dell'header ADTS che servono a ricostruire l'AudioSpecificConfig.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdtsHeader {
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    pub channels: u8,
+    pub header_len: usize,
+    pub frame_len: usize,
+}
+
+impl AdtsHeader {
+    pub fn parse(data: &[u8]) -> Option<AdtsHeader> {
+        if data.len() < 7 || data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+            return None;
+        }
+        let protection_absent = data[1] & 0x01 == 1;
+        let header = AdtsHeader {
+            object_type: (data[2] >> 6) + 1,
+            sample_rate_index: (data[2] >> 2) & 0x0f,
+            channels: ((data[2] & 0x01) << 2) | (data[3] >> 6),
+            header_len: if protection_absent { 7 } else { 9 },
+            frame_len: (usize::from(data[3] & 0x03) << 11)
+                | (usize::from(data[4]) << 3)
+                | usize::from(data[5] >> 5),
+        };
+        if header.frame_len < header.header_len
+            || header.sample_rate_index as usize >= SAMPLE_RATES.len()
+        {
+            return None;
+        }
+        Some(header)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+
+    /// AudioSpecificConfig di 2 byte (ISO 14496-3) equivalente all'header.
+    pub fn audio_specific_config(&self) -> Vec<u8> {
+        let config = (u16::from(self.object_type) << 11)
+            | (u16::from(self.sample_rate_index) << 7)
+            | (u16::from(self.channels) << 3);
+        config.to_be_bytes().to_vec()
+    }
+}
+
+/// Divide i PES AAC nei singoli frame ADTS, senza header. I timestamp dei
+/// sample sono espressi in campioni, alla frequenza dello stream.
+#[derive(Default)]
+pub struct AdtsParser {
+    header: Option<AdtsHeader>,
+    next_dts: Option<u64>,
+}
+
+impl AdtsParser {
+    pub fn new() -> AdtsParser {
+        AdtsParser::default()
+    }
+
+    pub fn codec(&self) -> Option<Codec> {
+        let header = self.header?;
+        Some(Codec::Aac {
+            config: header.audio_specific_config(),
+            sample_rate: header.sample_rate(),
+            channels: u16::from(header.channels),
+        })
+    }
+
+    /// Converte un PES in sample. `pts` è a 90 kHz.
+    pub fn push(&mut self, data: &[u8], pts: Option<u64>) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut i = 0;
+
+        while let Some(header) = AdtsHeader::parse(&data[i..]) {
+            if i + header.header_len > data.len() {
+                break;
+            }
+            let end = (i + header.frame_len).min(data.len());
+            let sample_rate = u64::from(header.sample_rate());
+            self.header = Some(header);
+
+            // Il PTS del PES vale per il primo frame. Se è vicino a quello
+            // atteso si prosegue con il conteggio dei campioni, così i sample
+            // hanno tutti la stessa durata; altrimenti c'è un buco nello
+            // stream e si riparte dal PTS.
+            let dts = match (self.next_dts, pts.filter(|_| samples.is_empty())) {
+                (Some(expected), Some(pts)) => {
+                    let pts = pts * sample_rate / 90_000;
+                    if (pts as i64 - expected as i64).abs() < SAMPLES_PER_FRAME as i64 / 2 {
+                        expected
+                    } else {
+                        pts
+                    }
+                }
+                (Some(expected), None) => expected,
+                (None, Some(pts)) => pts * sample_rate / 90_000,
+                (None, None) => 0,
+            };
+
+            samples.push(Sample {
+                dts,
+                pts: dts,
+                keyframe: true,
+                data: data[i + header.header_len..end].to_vec(),
+            });
+            self.next_dts = Some(dts + SAMPLES_PER_FRAME);
+            i = end;
+        }
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        // AAC-LC, 48 kHz, stereo, frame di 10 byte.
+        let frame = [0xff, 0xf1, 0x4c, 0x80, 0x01, 0x5f, 0xfc, 1, 2, 3];
+        let header = AdtsHeader::parse(&frame).unwrap();
+        assert_eq!(header.object_type, 2);
+        assert_eq!(header.sample_rate(), 48000);
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.frame_len, 10);
+        assert_eq!(header.audio_specific_config(), vec![0x11, 0x90]);
+
+        let mut pes = frame.to_vec();
+        pes.extend_from_slice(&frame);
+        let mut parser = AdtsParser::new();
+        let samples = parser.push(&pes, Some(90_000));
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].dts, 48000);
+        assert_eq!(samples[1].dts, 48000 + SAMPLES_PER_FRAME);
+        assert_eq!(samples[1].data, vec![1, 2, 3]);
+
+        // Un PTS leggermente arrotondato non spezza la sequenza.
+        let samples = parser.push(&frame, Some(90_000 + 2 * 1920 + 1));
+        assert_eq!(samples[0].dts, 48000 + 2 * SAMPLES_PER_FRAME);
+    }
+}
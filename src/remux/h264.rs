@@ -0,0 +1,348 @@
+#![warn(clippy::all)]
+
+use super::{Codec, InvalidStreamError, Sample};
+use std::convert::TryFrom;
+
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+
+/// Profili H.264 il cui SPS contiene chroma_format_idc e le profondità di bit.
+const HIGH_PROFILES: [u8; 12] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134];
+
+/// Lettore di bit per i campi Exp-Golomb dell'SPS.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(u32::from(bit))
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0, |acc, _| Some((acc << 1) | self.bit()?))
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()? as i32;
+        Some(if v % 2 == 0 { -v / 2 } else { (v + 1) / 2 })
+    }
+}
+
+/// Informazioni dell'SPS necessarie al muxing.
+#[derive(Debug, PartialEq)]
+pub struct SpsInfo {
+    pub profile: u8,
+    pub compatibility: u8,
+    pub level: u8,
+    pub chroma_format: u32,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Rimuove gli emulation prevention byte (`00 00 03`) da una NAL.
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + reader.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// Legge profilo, livello e dimensioni del video da una NAL SPS.
+pub fn parse_sps(nal: &[u8]) -> Result<SpsInfo, InvalidStreamError> {
+    read_sps(nal).ok_or(InvalidStreamError("invalid H.264 SPS"))
+}
+
+/// Come [`parse_sps`], `None` se l'SPS è troncato o le sue dimensioni non
+/// sono valide.
+fn read_sps(nal: &[u8]) -> Option<SpsInfo> {
+    let rbsp = unescape(nal);
+    let mut r = BitReader::new(rbsp.get(1..)?);
+
+    let profile = r.bits(8)? as u8;
+    let compatibility = r.bits(8)? as u8;
+    let level = r.bits(8)? as u8;
+    r.ue()?;
+
+    let mut chroma_format = 1;
+    let mut bit_depth_luma = 8;
+    let mut bit_depth_chroma = 8;
+    if HIGH_PROFILES.contains(&profile) || profile == 135 {
+        chroma_format = r.ue()?;
+        if chroma_format == 3 {
+            r.bit()?;
+        }
+        bit_depth_luma = r.ue()?.checked_add(8)?;
+        bit_depth_chroma = r.ue()?.checked_add(8)?;
+        r.bit()?;
+        if r.bit()? == 1 {
+            let lists = if chroma_format == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?;
+    match r.ue()? {
+        0 => {
+            r.ue()?;
+        }
+        1 => {
+            r.bit()?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?;
+    r.bit()?;
+
+    let width_mbs = r.ue()? + 1;
+    let height_map_units = r.ue()? + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.bit()?;
+    }
+    r.bit()?;
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if r.bit()? == 1 {
+        crop_left = r.ue()?;
+        crop_right = r.ue()?;
+        crop_top = r.ue()?;
+        crop_bottom = r.ue()?;
+    }
+
+    let (crop_unit_x, crop_unit_y) = match chroma_format {
+        0 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+    // Valori di crop maggiori delle dimensioni non sono validi.
+    let width = width_mbs.checked_mul(16)?.checked_sub(
+        crop_left
+            .checked_add(crop_right)?
+            .checked_mul(crop_unit_x)?,
+    )?;
+    let height = ((2 - frame_mbs_only) * 16)
+        .checked_mul(height_map_units)?
+        .checked_sub(
+            crop_top
+                .checked_add(crop_bottom)?
+                .checked_mul(crop_unit_y)?,
+        )?;
+
+    Some(SpsInfo {
+        profile,
+        compatibility,
+        level,
+        chroma_format,
+        bit_depth_luma,
+        bit_depth_chroma,
+        width: u16::try_from(width).ok()?,
+        height: u16::try_from(height).ok()?,
+    })
+}
+
+/// Divide un flusso Annex B nelle sue NAL, senza start code.
+fn split_nals(data: &[u8]) -> Vec<&[u8]> {
+    let mut nals = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 2 < data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                // Lo zero iniziale di uno start code a 4 byte non fa parte
+                // della NAL precedente.
+                let mut end = i;
+                while end > start && data[end - 1] == 0 {
+                    end -= 1;
+                }
+                nals.push(&data[start..end]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        if start < data.len() {
+            nals.push(&data[start..]);
+        }
+    }
+
+    nals.into_iter().filter(|nal| !nal.is_empty()).collect()
+}
+
+/// Converte gli access unit H.264 in formato Annex B dei PES in sample con
+/// NAL prefissate dalla lunghezza (formato AVCC), estraendo SPS e PPS.
+#[derive(Default)]
+pub struct H264Parser {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    seen_keyframe: bool,
+}
+
+impl H264Parser {
+    pub fn new() -> H264Parser {
+        H264Parser::default()
+    }
+
+    /// Ritorna la configurazione della traccia, nota dopo il primo SPS e PPS.
+    pub fn codec(&self) -> Result<Option<Codec>, InvalidStreamError> {
+        let (sps, pps) = match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) => (sps, pps),
+            _ => return Ok(None),
+        };
+        let info = parse_sps(sps)?;
+
+        let mut avcc = vec![
+            1,
+            info.profile,
+            info.compatibility,
+            info.level,
+            0xff, // NAL prefissate da 4 byte di lunghezza
+            0xe1, // un solo SPS
+        ];
+        avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(sps);
+        avcc.push(1);
+        avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(pps);
+        if HIGH_PROFILES.contains(&info.profile) {
+            avcc.push(0xfc | info.chroma_format as u8);
+            avcc.push(0xf8 | (info.bit_depth_luma - 8) as u8);
+            avcc.push(0xf8 | (info.bit_depth_chroma - 8) as u8);
+            avcc.push(0);
+        }
+
+        Ok(Some(Codec::H264 {
+            avcc,
+            width: info.width,
+            height: info.height,
+        }))
+    }
+
+    /// Converte un PES in un sample. I sample precedenti al primo keyframe
+    /// vengono scartati perché non sono decodificabili.
+    pub fn push(&mut self, data: &[u8], pts: u64, dts: u64) -> Option<Sample> {
+        let mut out = Vec::with_capacity(data.len() + 16);
+        let mut keyframe = false;
+
+        for nal in split_nals(data) {
+            match nal[0] & 0x1f {
+                NAL_SPS => self.sps = Some(nal.to_vec()),
+                NAL_PPS => self.pps = Some(nal.to_vec()),
+                NAL_AUD => {}
+                nal_type => {
+                    keyframe |= nal_type == NAL_IDR;
+                    out.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    out.extend_from_slice(nal);
+                }
+            }
+        }
+
+        self.seen_keyframe |= keyframe && self.sps.is_some() && self.pps.is_some();
+        if out.is_empty() || !self.seen_keyframe {
+            return None;
+        }
+
+        Some(Sample {
+            dts,
+            pts,
+            keyframe,
+            data: out,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let baseline_720p = [0x67, 0x42, 0xc0, 0x1e, 0xed, 0x00, 0xa0, 0x0b, 0x72];
+        let info = parse_sps(&baseline_720p).unwrap();
+        assert_eq!((info.profile, info.level), (66, 30));
+        assert_eq!((info.width, info.height), (1280, 720));
+
+        // High profile 1920x1088 con 8 righe di crop in basso.
+        let high_1080p = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40,
+        ];
+        let info = parse_sps(&high_1080p).unwrap();
+        assert_eq!(info.profile, 100);
+        assert_eq!((info.width, info.height), (1920, 1080));
+
+        // Baseline 16x16 con crop_left di 100: più largo dell'immagine.
+        let overcropped = [0x67, 0x42, 0xc0, 0x1e, 0xdd, 0xf0, 0x32, 0xf8];
+        assert!(parse_sps(&overcropped).is_err());
+
+        let mut stream = vec![0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1];
+        stream.extend_from_slice(&baseline_720p);
+        stream.extend_from_slice(&[0, 0, 1, 0x68, 0xce, 0x38, 0x80]);
+        stream.extend_from_slice(&[0, 0, 1, 0x65, 0x88, 0x84]);
+        assert_eq!(split_nals(&stream).len(), 4);
+
+        let mut parser = H264Parser::new();
+        assert!(parser.push(&[0, 0, 1, 0x41, 0x9a], 0, 0).is_none());
+        let sample = parser.push(&stream, 3003, 0).unwrap();
+        assert!(sample.keyframe);
+        assert_eq!(sample.data, vec![0, 0, 0, 3, 0x65, 0x88, 0x84]);
+        assert!(parser.codec().unwrap().is_some());
+    }
+}
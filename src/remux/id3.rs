@@ -0,0 +1,144 @@
+#![warn(clippy::all)]
+
+//! Scrittura di tag ID3v2.4 per i file MP3.
+
+use super::{packed, Artwork, Metadata};
+use crate::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER_LEN: usize = 10;
+
+/// Codifica dei testi: UTF-8, ammessa da ID3v2.4.
+const ENCODING_UTF8: u8 = 3;
+
+/// Tipo di immagine `APIC` della copertina anteriore.
+const PICTURE_FRONT_COVER: u8 = 3;
+
+/// Intero in 4 byte da 7 bit, come richiesto per le dimensioni di tag e
+/// frame.
+fn to_syncsafe(n: usize) -> [u8; 4] {
+    [
+        (n >> 21) as u8 & 0x7f,
+        (n >> 14) as u8 & 0x7f,
+        (n >> 7) as u8 & 0x7f,
+        n as u8 & 0x7f,
+    ]
+}
+
+fn frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend_from_slice(&to_syncsafe(body.len()));
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+    let mut body = vec![ENCODING_UTF8];
+    body.extend_from_slice(text.as_bytes());
+    frame(id, &body)
+}
+
+/// Tag ID3v2.4 con i metadati e la copertina.
+fn tag(metadata: &Metadata, artwork: Option<&Artwork>) -> Vec<u8> {
+    let mut frames = Vec::new();
+    if let Some(title) = &metadata.title {
+        frames.extend(text_frame(b"TIT2", title));
+    }
+    if let Some(show) = &metadata.show {
+        frames.extend(text_frame(b"TALB", show));
+    }
+    if let Some(artist) = metadata.show.as_ref().or(metadata.channel.as_ref()) {
+        frames.extend(text_frame(b"TPE1", artist));
+    }
+    if let Some(channel) = &metadata.channel {
+        frames.extend(text_frame(b"TPUB", channel));
+    }
+    if let Some(date) = &metadata.date {
+        frames.extend(text_frame(b"TDRC", date));
+    }
+    if let Some(season) = metadata.season {
+        frames.extend(text_frame(b"TPOS", &season.to_string()));
+    }
+    if let Some(episode) = metadata.episode {
+        frames.extend(text_frame(b"TRCK", &episode.to_string()));
+    }
+    if let Some(description) = &metadata.description {
+        // Lingua, descrizione breve vuota e testo.
+        let mut body = vec![ENCODING_UTF8];
+        body.extend_from_slice(b"ita\0");
+        body.extend_from_slice(description.as_bytes());
+        frames.extend(frame(b"COMM", &body));
+    }
+    if let Some(artwork) = artwork {
+        let mut body = vec![ENCODING_UTF8];
+        body.extend_from_slice(artwork.mime.as_bytes());
+        body.push(0);
+        body.push(PICTURE_FRONT_COVER);
+        body.push(0);
+        body.extend_from_slice(&artwork.data);
+        frames.extend(frame(b"APIC", &body));
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&to_syncsafe(frames.len()));
+    tag.extend(frames);
+    tag
+}
+
+/// Lunghezza del tag ID3v2 che inizia con `header`, 0 se non ce n'è uno.
+pub fn tag_len(header: &[u8]) -> usize {
+    if header.len() >= HEADER_LEN && header.starts_with(b"ID3") {
+        let footer = if header[5] & 0x10 != 0 { HEADER_LEN } else { 0 };
+        HEADER_LEN + packed::syncsafe(&header[6..HEADER_LEN]) + footer
+    } else {
+        0
+    }
+}
+
+/// Sostituisce il tag ID3v2 all'inizio di un MP3 con uno nuovo.
+pub fn tag_file(path: &Path, metadata: &Metadata, artwork: Option<&Artwork>) -> Result<(), Error> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut header = [0; HEADER_LEN];
+    let read = input.read(&mut header)?;
+    let audio_start = tag_len(&header[..read]);
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(&tag(metadata, artwork))?;
+        input.seek(SeekFrom::Start(audio_start as u64))?;
+        io::copy(&mut input, &mut out)?;
+        out.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(to_syncsafe(0x3fff), [0, 0, 0x7f, 0x7f]);
+        assert_eq!(packed::syncsafe(&to_syncsafe(123_456)), 123_456);
+
+        let metadata = Metadata {
+            title: Some(String::from("Puntata del 3 marzo")),
+            show: Some(String::from("Il ruggito del coniglio")),
+            ..Metadata::default()
+        };
+        let tag = tag(&metadata, None);
+        assert!(tag.starts_with(b"ID3\x04"));
+        assert_eq!(packed::syncsafe(&tag[6..10]), tag.len() - HEADER_LEN);
+        let title = &tag[HEADER_LEN..];
+        assert_eq!(&title[..4], b"TIT2");
+        assert_eq!(packed::syncsafe(&title[4..8]), 1 + 19);
+        assert_eq!(&title[11..30], b"Puntata del 3 marzo");
+    }
+}
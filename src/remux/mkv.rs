@@ -0,0 +1,680 @@
+#![warn(clippy::all)]
+
+use super::{Artwork, Codec, Metadata, Sample, TrackInfo};
+use crate::error::Error;
+use crate::subtitles::{Cue, SubtitleTrack};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const VOID: u32 = 0xEC;
+
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TITLE: u32 = 0x7BA9;
+
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const LANGUAGE: u32 = 0x22_B59C;
+const NAME: u32 = 0x536E;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const TARGET_TYPE_VALUE: u32 = 0x68CA;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+
+const ATTACHMENTS: u32 = 0x1941_A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_UID: u32 = 0x46AE;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+const TRACK_TYPE_SUBTITLE: u64 = 17;
+
+/// Livelli dei target dei tag Matroska.
+const TARGET_COLLECTION: u64 = 70;
+const TARGET_SEASON: u64 = 60;
+const TARGET_EPISODE: u64 = 50;
+
+/// Un nuovo cluster viene iniziato al primo keyframe dopo questa durata.
+const CLUSTER_DURATION_MS: u64 = 5000;
+
+/// Spazio riservato all'inizio del segmento per il SeekHead, scritto solo
+/// alla fine quando sono note le posizioni degli elementi.
+const SEEK_HEAD_RESERVED: usize = 128;
+
+/// Dimensione "sconosciuta" a 8 byte, sostituita a fine scrittura.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+fn id_bytes(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+/// Codifica una dimensione come intero a lunghezza variabile EBML.
+fn vint(n: u64) -> Vec<u8> {
+    let len = (1..=8).find(|len| n < (1 << (7 * len)) - 1).unwrap_or(8);
+    let marked = n | (1 << (7 * len));
+    marked.to_be_bytes()[8 - len..].to_vec()
+}
+
+/// Legge un intero a lunghezza variabile EBML da `data[pos..]`, con il
+/// marcatore della lunghezza se `keep_marker`, come negli ID. Ritorna il
+/// valore e il numero di byte letti.
+fn read_vint(data: &[u8], pos: usize, keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let bytes = data.get(pos..pos + len)?;
+    let mut value = if keep_marker {
+        u64::from(first)
+    } else {
+        u64::from(first & (0xff >> len))
+    };
+    for &b in &bytes[1..] {
+        value = (value << 8) | u64::from(b);
+    }
+    Some((value, len))
+}
+
+/// Dimensione del file dichiarata dall'header EBML e dal segmento che
+/// iniziano `head`. Finché la scrittura non è finita il segmento ha
+/// dimensione sconosciuta, e il risultato non torna con quella del file.
+pub fn declared_len(head: &[u8]) -> Option<u64> {
+    if !head.starts_with(&id_bytes(EBML)) {
+        return None;
+    }
+    let (header_size, n) = read_vint(head, 4, false)?;
+    let segment = (4 + n).checked_add(usize::try_from(header_size).ok()?)?;
+    let (id, id_len) = read_vint(head, segment, true)?;
+    if id != u64::from(SEGMENT) {
+        return None;
+    }
+    let (segment_size, n) = read_vint(head, segment + id_len, false)?;
+    ((segment + id_len + n) as u64).checked_add(segment_size)
+}
+
+fn element(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut e = id_bytes(id);
+    e.extend_from_slice(&vint(data.len() as u64));
+    e.extend_from_slice(data);
+    e
+}
+
+fn master(id: u32, children: &[Vec<u8>]) -> Vec<u8> {
+    element(id, &children.concat())
+}
+
+fn uint(id: u32, v: u64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(id, &bytes[skip..])
+}
+
+fn float(id: u32, v: f64) -> Vec<u8> {
+    element(id, &v.to_be_bytes())
+}
+
+fn string(id: u32, s: &str) -> Vec<u8> {
+    element(id, s.as_bytes())
+}
+
+/// Elemento Void che occupa esattamente `len` byte (almeno 9).
+fn void(len: usize) -> Vec<u8> {
+    let mut e = id_bytes(VOID);
+    let data_len = len - 1 - 8;
+    e.push(0x01);
+    e.extend_from_slice(&(data_len as u64).to_be_bytes()[1..]);
+    e.resize(len, 0);
+    e
+}
+
+/// Header di un blocco: numero della traccia, timecode relativo al cluster
+/// e flag.
+fn block(track_number: u64, relative_time: i16, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut b = vint(track_number);
+    b.extend_from_slice(&relative_time.to_be_bytes());
+    b.push(flags);
+    b.extend_from_slice(data);
+    b
+}
+
+fn simple_tag(name: &str, value: &str) -> Vec<u8> {
+    master(
+        SIMPLE_TAG,
+        &[string(TAG_NAME, name), string(TAG_STRING, value)],
+    )
+}
+
+fn tag(target: u64, tags: Vec<Vec<u8>>) -> Option<Vec<u8>> {
+    if tags.is_empty() {
+        return None;
+    }
+    let mut children = vec![master(TARGETS, &[uint(TARGET_TYPE_VALUE, target)])];
+    children.extend(tags);
+    Some(master(TAG, &children))
+}
+
+fn tags(metadata: &Metadata) -> Vec<u8> {
+    let mut collection = Vec::new();
+    if let Some(show) = &metadata.show {
+        collection.push(simple_tag("TITLE", show));
+    }
+
+    let mut season = Vec::new();
+    if let Some(n) = metadata.season {
+        season.push(simple_tag("PART_NUMBER", &n.to_string()));
+    }
+
+    let mut episode = Vec::new();
+    if let Some(title) = &metadata.title {
+        episode.push(simple_tag("TITLE", title));
+    }
+    if let Some(n) = metadata.episode {
+        episode.push(simple_tag("PART_NUMBER", &n.to_string()));
+    }
+    if let Some(description) = &metadata.description {
+        episode.push(simple_tag("DESCRIPTION", description));
+    }
+    if let Some(channel) = &metadata.channel {
+        episode.push(simple_tag("DISTRIBUTED_BY", channel));
+    }
+    if let Some(date) = &metadata.date {
+        episode.push(simple_tag("DATE_RELEASED", date));
+    }
+
+    let children: Vec<Vec<u8>> = vec![
+        tag(TARGET_COLLECTION, collection),
+        tag(TARGET_SEASON, season),
+        tag(TARGET_EPISODE, episode),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    master(TAGS, &children)
+}
+
+/// Copertina come allegato `cover.jpg` o `cover.png`, il nome che i
+/// lettori riconoscono.
+fn attachments(artwork: &Artwork) -> Vec<u8> {
+    let name = match artwork.mime {
+        "image/png" => "cover.png",
+        _ => "cover.jpg",
+    };
+    master(
+        ATTACHMENTS,
+        &[master(
+            ATTACHED_FILE,
+            &[
+                string(FILE_NAME, name),
+                string(FILE_MIME_TYPE, artwork.mime),
+                element(FILE_DATA, &artwork.data),
+                uint(FILE_UID, 1),
+            ],
+        )],
+    )
+}
+
+fn track_entry(number: u64, track: &TrackInfo) -> Vec<u8> {
+    let mut children = vec![
+        uint(TRACK_NUMBER, number),
+        uint(TRACK_UID, number),
+        uint(FLAG_LACING, 0),
+        string(LANGUAGE, track.language.as_deref().unwrap_or("und")),
+    ];
+    match &track.codec {
+        Codec::H264 {
+            avcc,
+            width,
+            height,
+        } => {
+            children.push(uint(TRACK_TYPE, TRACK_TYPE_VIDEO));
+            children.push(string(CODEC_ID, "V_MPEG4/ISO/AVC"));
+            children.push(element(CODEC_PRIVATE, avcc));
+            children.push(master(
+                VIDEO,
+                &[
+                    uint(PIXEL_WIDTH, u64::from(*width)),
+                    uint(PIXEL_HEIGHT, u64::from(*height)),
+                ],
+            ));
+        }
+        Codec::Aac {
+            config,
+            sample_rate,
+            channels,
+        } => {
+            children.push(uint(TRACK_TYPE, TRACK_TYPE_AUDIO));
+            children.push(string(CODEC_ID, "A_AAC"));
+            children.push(element(CODEC_PRIVATE, config));
+            children.push(master(
+                AUDIO,
+                &[
+                    float(SAMPLING_FREQUENCY, f64::from(*sample_rate)),
+                    uint(CHANNELS, u64::from(*channels)),
+                ],
+            ));
+        }
+    }
+    master(TRACK_ENTRY, &children)
+}
+
+fn subtitle_entry(number: u64, track: &SubtitleTrack) -> Vec<u8> {
+    master(
+        TRACK_ENTRY,
+        &[
+            uint(TRACK_NUMBER, number),
+            uint(TRACK_UID, number),
+            uint(FLAG_LACING, 0),
+            uint(TRACK_TYPE, TRACK_TYPE_SUBTITLE),
+            string(CODEC_ID, "S_TEXT/UTF8"),
+            string(LANGUAGE, &track.language),
+            string(NAME, &track.name),
+        ],
+    )
+}
+
+struct Cluster {
+    time: u64,
+    data: Vec<u8>,
+}
+
+/// Scrive un file Matroska. I cluster vengono composti in memoria e scritti
+/// interi, mentre SeekHead, durata e dimensione del segmento sono aggiornati
+/// in `finish`.
+pub struct MkvWriter {
+    out: BufWriter<File>,
+    pos: u64,
+    segment_size_pos: u64,
+    segment_start: u64,
+    duration_pos: u64,
+    info_pos: u64,
+    tracks_pos: u64,
+    tracks: Vec<TrackInfo>,
+    has_video: bool,
+    /// Inizio della presentazione, in millisecondi.
+    start_ms: u64,
+    end_ms: u64,
+    /// Sottotitoli ancora da scrivere: numero della traccia e cue, in ordine
+    /// di inizio.
+    cues: VecDeque<(u64, Cue)>,
+    cluster: Option<Cluster>,
+    /// Tempo e posizione dei cluster che iniziano con un keyframe.
+    cue_points: Vec<(u64, u64, u64)>,
+    metadata: Metadata,
+    artwork: Option<Artwork>,
+}
+
+impl MkvWriter {
+    pub fn create(
+        path: &Path,
+        tracks: &[TrackInfo],
+        subtitles: &[SubtitleTrack],
+        metadata: &Metadata,
+        artwork: Option<&Artwork>,
+    ) -> Result<MkvWriter, Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        let header = master(
+            EBML,
+            &[
+                uint(EBML_VERSION, 1),
+                uint(EBML_READ_VERSION, 1),
+                uint(EBML_MAX_ID_LENGTH, 4),
+                uint(EBML_MAX_SIZE_LENGTH, 8),
+                string(DOC_TYPE, "matroska"),
+                uint(DOC_TYPE_VERSION, 4),
+                uint(DOC_TYPE_READ_VERSION, 2),
+            ],
+        );
+        out.write_all(&header)?;
+
+        let mut segment = id_bytes(SEGMENT);
+        segment.extend_from_slice(&UNKNOWN_SIZE);
+        out.write_all(&segment)?;
+        let segment_size_pos = (header.len() + segment.len() - UNKNOWN_SIZE.len()) as u64;
+        let segment_start = (header.len() + segment.len()) as u64;
+
+        let seek_head = void(SEEK_HEAD_RESERVED);
+        out.write_all(&seek_head)?;
+
+        let app = concat!("raiplay-dl ", env!("CARGO_PKG_VERSION"));
+        let mut info_children = vec![
+            uint(TIMECODE_SCALE, 1_000_000),
+            string(MUXING_APP, app),
+            string(WRITING_APP, app),
+        ];
+        if let Some(title) = &metadata.title {
+            info_children.push(string(TITLE, title));
+        }
+        // La durata è l'ultimo elemento di Info, quindi il suo valore occupa
+        // gli ultimi 8 byte.
+        info_children.push(float(DURATION, 0.0));
+        let info = master(INFO, &info_children);
+        let info_pos = segment_start + seek_head.len() as u64;
+        let duration_pos = info_pos + info.len() as u64 - 8;
+        out.write_all(&info)?;
+
+        let mut entries: Vec<Vec<u8>> = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| track_entry(i as u64 + 1, track))
+            .collect();
+        let mut cues = Vec::new();
+        for (i, subtitle) in subtitles.iter().enumerate() {
+            let number = (tracks.len() + i) as u64 + 1;
+            entries.push(subtitle_entry(number, subtitle));
+            cues.extend(subtitle.cues.iter().cloned().map(|cue| (number, cue)));
+        }
+        cues.sort_by_key(|(_, cue)| cue.start);
+        let tracks_element = master(TRACKS, &entries);
+        let tracks_pos = info_pos + info.len() as u64;
+        out.write_all(&tracks_element)?;
+
+        let start_ms = tracks
+            .iter()
+            .map(|track| track.start * 1000 / u64::from(track.timescale))
+            .min()
+            .unwrap_or(0);
+
+        Ok(MkvWriter {
+            out,
+            pos: tracks_pos + tracks_element.len() as u64,
+            segment_size_pos,
+            segment_start,
+            duration_pos,
+            info_pos,
+            tracks_pos,
+            tracks: tracks.to_vec(),
+            has_video: tracks.iter().any(TrackInfo::is_video),
+            start_ms,
+            end_ms: 0,
+            cues: cues.into_iter().collect(),
+            cluster: None,
+            cue_points: Vec::new(),
+            metadata: metadata.clone(),
+            artwork: artwork.cloned(),
+        })
+    }
+
+    /// Converte un timestamp della traccia `track` in millisecondi dall'inizio
+    /// del file.
+    pub fn time_ms(&self, track: usize, ts: u64) -> u64 {
+        let timescale = u64::from(self.tracks[track].timescale);
+        (ts * 1000 / timescale).saturating_sub(self.start_ms)
+    }
+
+    /// Scrive il sample della traccia `track` (indice in `tracks` di
+    /// `create`), preceduto dai sottotitoli che iniziano prima di lui.
+    pub fn write_sample(&mut self, track: usize, sample: &Sample) -> Result<(), Error> {
+        let dts_ms = self.time_ms(track, sample.dts);
+        while self
+            .cues
+            .front()
+            .is_some_and(|(_, cue)| cue.start <= dts_ms)
+        {
+            let (number, cue) = self.cues.pop_front().unwrap();
+            self.write_cue(number, &cue)?;
+        }
+
+        let time = self.time_ms(track, sample.pts);
+        let is_video = self.tracks[track].is_video();
+        let starts_cluster = sample.keyframe && (is_video || !self.has_video);
+        self.prepare_cluster(time, starts_cluster)?;
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (time as i64 - cluster.time as i64) as i16;
+        let flags = if sample.keyframe { 0x80 } else { 0 };
+        let data = block(track as u64 + 1, relative, flags, &sample.data);
+        cluster
+            .data
+            .extend_from_slice(&element(SIMPLE_BLOCK, &data));
+        self.end_ms = self.end_ms.max(time);
+        Ok(())
+    }
+
+    /// Scrive il sottotitolo `cue` nel cluster corrente. Un sottotitolo che
+    /// inizia prima del cluster viene spostato all'inizio del cluster, perché
+    /// aprirne uno nuovo farebbe tornare indietro i timecode dei cluster.
+    fn write_cue(&mut self, number: u64, cue: &Cue) -> Result<(), Error> {
+        let time = match &self.cluster {
+            Some(cluster) => cue.start.max(cluster.time),
+            None => cue.start,
+        };
+        self.prepare_cluster(time, false)?;
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (time as i64 - cluster.time as i64) as i16;
+        let group = master(
+            BLOCK_GROUP,
+            &[
+                element(BLOCK, &block(number, relative, 0, cue.text.as_bytes())),
+                uint(BLOCK_DURATION, cue.end.saturating_sub(time)),
+            ],
+        );
+        cluster.data.extend_from_slice(&group);
+        self.end_ms = self.end_ms.max(cue.end);
+        Ok(())
+    }
+
+    /// Chiude il cluster corrente e ne apre uno nuovo se `time` non è
+    /// rappresentabile nel cluster, o se è passato abbastanza tempo e il
+    /// sample è un keyframe.
+    fn prepare_cluster(&mut self, time: u64, keyframe: bool) -> Result<(), Error> {
+        let needs_new = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                let relative = time as i64 - cluster.time as i64;
+                relative > i64::from(i16::MAX)
+                    || relative < i64::from(i16::MIN)
+                    || (keyframe && relative >= CLUSTER_DURATION_MS as i64)
+            }
+        };
+        if !needs_new {
+            return Ok(());
+        }
+
+        self.flush_cluster()?;
+        let position = self.pos - self.segment_start;
+        if keyframe {
+            self.cue_points.push((time, position, self.key_track()));
+        }
+        self.cluster = Some(Cluster {
+            time,
+            data: uint(TIMECODE, time),
+        });
+        Ok(())
+    }
+
+    /// Traccia usata come riferimento negli indici: il video se presente.
+    fn key_track(&self) -> u64 {
+        self.tracks
+            .iter()
+            .position(TrackInfo::is_video)
+            .unwrap_or(0) as u64
+            + 1
+    }
+
+    fn flush_cluster(&mut self) -> Result<(), Error> {
+        if let Some(cluster) = self.cluster.take() {
+            let element = element(CLUSTER, &cluster.data);
+            self.out.write_all(&element)?;
+            self.pos += element.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Scrive i sottotitoli rimasti, l'indice, i tag e la copertina, poi
+    /// aggiorna durata, SeekHead e dimensione del segmento.
+    pub fn finish(mut self) -> Result<(), Error> {
+        while let Some((number, cue)) = self.cues.pop_front() {
+            self.write_cue(number, &cue)?;
+        }
+        self.flush_cluster()?;
+
+        let cue_points: Vec<Vec<u8>> = self
+            .cue_points
+            .iter()
+            .map(|&(time, position, track)| {
+                master(
+                    CUE_POINT,
+                    &[
+                        uint(CUE_TIME, time),
+                        master(
+                            CUE_TRACK_POSITIONS,
+                            &[uint(CUE_TRACK, track), uint(CUE_CLUSTER_POSITION, position)],
+                        ),
+                    ],
+                )
+            })
+            .collect();
+        let cues_pos = self.pos;
+        let cues = master(CUES, &cue_points);
+        self.out.write_all(&cues)?;
+        self.pos += cues.len() as u64;
+
+        let tags_pos = self.pos;
+        let tags = tags(&self.metadata);
+        self.out.write_all(&tags)?;
+        self.pos += tags.len() as u64;
+
+        let mut positions = vec![
+            (INFO, self.info_pos),
+            (TRACKS, self.tracks_pos),
+            (CUES, cues_pos),
+            (TAGS, tags_pos),
+        ];
+        if let Some(artwork) = &self.artwork {
+            positions.push((ATTACHMENTS, self.pos));
+            let attachments = attachments(artwork);
+            self.out.write_all(&attachments)?;
+            self.pos += attachments.len() as u64;
+        }
+
+        let seeks: Vec<Vec<u8>> = positions
+            .iter()
+            .map(|&(id, pos)| {
+                master(
+                    SEEK,
+                    &[
+                        element(SEEK_ID, &id_bytes(id)),
+                        uint(SEEK_POSITION, pos - self.segment_start),
+                    ],
+                )
+            })
+            .collect();
+        let mut seek_head = master(SEEK_HEAD, &seeks);
+        seek_head.extend_from_slice(&void(SEEK_HEAD_RESERVED - seek_head.len()));
+        self.out.seek(SeekFrom::Start(self.segment_start))?;
+        self.out.write_all(&seek_head)?;
+
+        self.out.seek(SeekFrom::Start(self.duration_pos))?;
+        self.out.write_all(&(self.end_ms as f64).to_be_bytes())?;
+
+        let segment_size = self.pos - self.segment_start;
+        self.out.seek(SeekFrom::Start(self.segment_size_pos))?;
+        self.out
+            .write_all(&(segment_size | (1 << 56)).to_be_bytes())?;
+
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let path = std::env::temp_dir().join(format!("raiplay-dl-mkv-{}.mkv", std::process::id()));
+        let track = TrackInfo {
+            id: 0x101,
+            codec: Codec::Aac {
+                config: vec![0x11, 0x90],
+                sample_rate: 48000,
+                channels: 2,
+            },
+            timescale: 1000,
+            language: None,
+            start: 0,
+        };
+        let mut writer =
+            MkvWriter::create(&path, &[track], &[], &Metadata::default(), None).unwrap();
+        let sample = |time| Sample {
+            dts: time,
+            pts: time,
+            keyframe: true,
+            data: vec![0; 4],
+        };
+        writer.write_sample(0, &sample(0)).unwrap();
+        writer.write_sample(0, &sample(60_000)).unwrap();
+        let pos = writer.pos;
+
+        // Un sottotitolo di 40 secondi prima del cluster resta nel cluster.
+        let cue = Cue {
+            start: 20_000,
+            end: 62_000,
+            text: String::from("Buonasera"),
+        };
+        writer.write_cue(1, &cue).unwrap();
+        assert_eq!(writer.pos, pos);
+        assert_eq!(writer.cluster.as_ref().unwrap().time, 60_000);
+        writer.finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,735 @@
+#![warn(clippy::all)]
+
+//! Remux dei file MPEG-TS scaricati (H.264 + AAC) in altri contenitori,
+//! senza dipendenze esterne.
+
+mod aac;
+mod h264;
+mod id3;
+pub mod mkv;
+mod mp3;
+pub mod mp4;
+mod packed;
+mod ts;
+
+use crate::error::Error;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::subtitles::SubtitleTrack;
+
+/// Numero massimo di sample letti per trovare la configurazione di tutte le
+/// tracce.
+const MAX_PROBE_SAMPLES: usize = 5000;
+
+/// Periodo dei timestamp MPEG-TS, che sono a 33 bit.
+const TIMESTAMP_WRAP: u64 = 1 << 33;
+
+/// Timescale dei timestamp MPEG-TS.
+pub const TS_TIMESCALE: u32 = 90_000;
+
+/// PID fittizio della traccia di un file di audio packed.
+const PACKED_AUDIO_PID: u16 = 0x100;
+
+#[derive(Debug)]
+pub struct InvalidStreamError(pub &'static str);
+
+impl fmt::Display for InvalidStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MPEG-TS stream is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidStreamError {}
+
+#[derive(Debug)]
+pub struct UnsupportedContainerError(pub String);
+
+impl fmt::Display for UnsupportedContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Container `{}` is not supported", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedContainerError {}
+
+/// Metadati del video da salvare nel contenitore.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    /// Nome del programma a cui appartiene l'episodio.
+    pub show: Option<String>,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub description: Option<String>,
+    pub channel: Option<String>,
+    /// Data di pubblicazione, nel formato `AAAA-MM-GG`.
+    pub date: Option<String>,
+}
+
+/// Immagine di copertina da incorporare nel file.
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub data: Vec<u8>,
+    /// `image/jpeg` o `image/png`.
+    pub mime: &'static str,
+}
+
+impl Artwork {
+    /// Riconosce il formato dell'immagine dai primi byte, `None` se non è
+    /// un JPEG o un PNG.
+    pub fn from_bytes(data: Vec<u8>) -> Option<Artwork> {
+        let mime = if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            "image/jpeg"
+        } else if data.starts_with(b"\x89PNG") {
+            "image/png"
+        } else {
+            return None;
+        };
+        Some(Artwork { data, mime })
+    }
+}
+
+/// Formato di un file audio scaricato così com'è.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    Mp3,
+    /// AAC in frame ADTS, da salvare in un M4A.
+    Adts,
+    Mp4,
+}
+
+impl AudioFormat {
+    /// Riconosce il formato dai primi byte del file, saltando un eventuale
+    /// tag ID3.
+    pub fn detect(data: &[u8]) -> Option<AudioFormat> {
+        if data.get(4..8) == Some(b"ftyp") {
+            return Some(AudioFormat::Mp4);
+        }
+        let start = if data.starts_with(b"ID3") && data.len() >= 10 {
+            let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+            10 + packed::syncsafe(&data[6..10]) + footer
+        } else {
+            0
+        };
+        let frame = data.get(start..start + 2)?;
+        // Entrambi iniziano con 12 bit di sync; il layer è 0 solo per ADTS.
+        match (frame[0], frame[1] & 0xf6) {
+            (0xff, 0xf0) => Some(AudioFormat::Adts),
+            (0xff, _) if frame[1] & 0xe0 == 0xe0 => Some(AudioFormat::Mp3),
+            _ => None,
+        }
+    }
+}
+
+/// Codec e configurazione di una traccia.
+#[derive(Debug, Clone)]
+pub enum Codec {
+    /// `avcc` è l'AVCDecoderConfigurationRecord con SPS e PPS.
+    H264 {
+        avcc: Vec<u8>,
+        width: u16,
+        height: u16,
+    },
+    /// `config` è l'AudioSpecificConfig.
+    Aac {
+        config: Vec<u8>,
+        sample_rate: u32,
+        channels: u16,
+    },
+}
+
+/// Traccia trovata nel file MPEG-TS.
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// PID dello stream nel file MPEG-TS.
+    pub id: u16,
+    pub codec: Codec,
+    /// Unità al secondo dei timestamp dei sample.
+    pub timescale: u32,
+    /// Codice ISO 639 a 3 lettere, se dichiarato nella PMT.
+    pub language: Option<String>,
+    /// PTS più basso tra i sample letti finora, nella timescale della
+    /// traccia.
+    pub start: u64,
+}
+
+impl TrackInfo {
+    pub fn is_video(&self) -> bool {
+        match self.codec {
+            Codec::H264 { .. } => true,
+            Codec::Aac { .. } => false,
+        }
+    }
+}
+
+/// File da convertire. `language` sostituisce la lingua delle tracce audio
+/// dichiarata nel file, ad esempio per le tracce alternative di una
+/// playlist HLS che la riportano solo nella master playlist.
+#[derive(Debug, Clone, Copy)]
+pub struct Input<'a> {
+    pub path: &'a Path,
+    pub language: Option<&'a str>,
+}
+
+impl<'a> From<&'a Path> for Input<'a> {
+    fn from(path: &'a Path) -> Input<'a> {
+        Input {
+            path,
+            language: None,
+        }
+    }
+}
+
+/// Frame di una traccia. I timestamp sono nella timescale della traccia; il
+/// video è in formato AVCC e l'audio è AAC senza header ADTS.
+#[derive(Debug)]
+pub struct Sample {
+    pub dts: u64,
+    pub pts: u64,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+enum ElementaryParser {
+    H264(h264::H264Parser),
+    Aac(aac::AdtsParser),
+}
+
+impl ElementaryParser {
+    fn codec(&self) -> Result<Option<Codec>, InvalidStreamError> {
+        match self {
+            ElementaryParser::H264(parser) => parser.codec(),
+            ElementaryParser::Aac(parser) => Ok(parser.codec()),
+        }
+    }
+}
+
+/// Riporta un timestamp a 33 bit sulla linea temporale continua più vicina
+/// a `last`, per gestire il ritorno a zero dei timestamp MPEG-TS.
+fn unwrap_timestamp(last: Option<u64>, ts: u64) -> u64 {
+    let last = match last {
+        Some(last) => last,
+        None => return ts,
+    };
+    let base = last - last % TIMESTAMP_WRAP + ts;
+    [
+        base.saturating_sub(TIMESTAMP_WRAP),
+        base,
+        base + TIMESTAMP_WRAP,
+    ]
+    .iter()
+    .copied()
+    .min_by_key(|candidate| (*candidate as i64 - last as i64).abs())
+    .unwrap()
+}
+
+/// Sorgente dei PES da cui vengono estratti i sample.
+enum Source {
+    Ts(ts::TsReader<BufReader<File>>),
+    /// Audio packed, letto per intero e diviso nei blocchi tra i tag ID3.
+    Packed {
+        chunks: VecDeque<(Option<u64>, Vec<u8>)>,
+        stream: ts::ElementaryStream,
+    },
+}
+
+impl Source {
+    fn next_pes(&mut self) -> Result<Option<ts::Pes>, Error> {
+        match self {
+            Source::Ts(reader) => reader.next_pes(),
+            Source::Packed { chunks, .. } => Ok(chunks.pop_front().map(|(pts, data)| ts::Pes {
+                pid: PACKED_AUDIO_PID,
+                pts,
+                dts: pts,
+                data,
+            })),
+        }
+    }
+
+    fn stream(&self, pid: u16) -> Option<&ts::ElementaryStream> {
+        match self {
+            Source::Ts(reader) => reader.streams().get(&pid),
+            Source::Packed { stream, .. } if pid == PACKED_AUDIO_PID => Some(stream),
+            Source::Packed { .. } => None,
+        }
+    }
+
+    fn streams(&self) -> Vec<&ts::ElementaryStream> {
+        match self {
+            Source::Ts(reader) => reader.streams().values().collect(),
+            Source::Packed { stream, .. } => vec![stream],
+        }
+    }
+}
+
+/// Estrae i sample H.264 e AAC da un file MPEG-TS o di audio packed.
+pub struct Demuxer {
+    source: Source,
+    language: Option<String>,
+    parsers: HashMap<u16, ElementaryParser>,
+    last_timestamps: HashMap<u16, u64>,
+    min_pts: HashMap<u16, u64>,
+    pending: VecDeque<(u16, Sample)>,
+}
+
+impl Demuxer {
+    pub fn open(input: Input) -> Result<Demuxer, Error> {
+        let mut file = File::open(input.path)?;
+        let mut head = [0; 4];
+        let read = file.read(&mut head)?;
+
+        let source = if packed::is_packed_audio(&head[..read]) {
+            let mut data = head[..read].to_vec();
+            file.read_to_end(&mut data)?;
+            Source::Packed {
+                chunks: packed::split(&data)
+                    .into_iter()
+                    .map(|(pts, chunk)| (pts, chunk.to_vec()))
+                    .collect(),
+                stream: ts::ElementaryStream {
+                    stream_type: ts::STREAM_TYPE_AAC_ADTS,
+                    language: None,
+                },
+            }
+        } else {
+            file.seek(SeekFrom::Start(0))?;
+            Source::Ts(ts::TsReader::new(BufReader::new(file)))
+        };
+
+        Ok(Demuxer {
+            source,
+            language: input.language.map(String::from),
+            parsers: HashMap::new(),
+            last_timestamps: HashMap::new(),
+            min_pts: HashMap::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Tracce di cui è già nota la configurazione, video prima dell'audio.
+    pub fn tracks(&self) -> Result<Vec<TrackInfo>, Error> {
+        let mut tracks = Vec::new();
+        for (&id, parser) in &self.parsers {
+            let codec = match parser.codec()? {
+                Some(codec) => codec,
+                None => continue,
+            };
+            let (timescale, language) = match codec {
+                Codec::H264 { .. } => (TS_TIMESCALE, None),
+                Codec::Aac { sample_rate, .. } => (sample_rate, self.language.clone()),
+            };
+            tracks.push(TrackInfo {
+                id,
+                codec,
+                timescale,
+                language: language.or_else(|| self.source.stream(id)?.language.clone()),
+                start: self.min_pts.get(&id).copied().unwrap_or(0),
+            });
+        }
+        tracks.sort_by_key(|track| (!track.is_video(), track.id));
+        Ok(tracks)
+    }
+
+    /// Ritorna il prossimo sample con l'id della sua traccia, `None` a fine
+    /// file.
+    pub fn next_sample(&mut self) -> Result<Option<(u16, Sample)>, Error> {
+        loop {
+            if let Some((id, sample)) = self.pending.pop_front() {
+                let min_pts = self.min_pts.entry(id).or_insert(sample.pts);
+                *min_pts = (*min_pts).min(sample.pts);
+                return Ok(Some((id, sample)));
+            }
+
+            let pes = match self.source.next_pes()? {
+                Some(pes) => pes,
+                None => return Ok(None),
+            };
+            let stream_type = match self.source.stream(pes.pid) {
+                Some(stream) => stream.stream_type,
+                None => continue,
+            };
+            let parser = match self.parsers.get_mut(&pes.pid) {
+                Some(parser) => parser,
+                None => {
+                    let parser = match stream_type {
+                        ts::STREAM_TYPE_H264 => ElementaryParser::H264(h264::H264Parser::new()),
+                        ts::STREAM_TYPE_AAC_ADTS => ElementaryParser::Aac(aac::AdtsParser::new()),
+                        // Gli altri stream (es. metadati ID3) vengono ignorati.
+                        _ => continue,
+                    };
+                    self.parsers.entry(pes.pid).or_insert(parser)
+                }
+            };
+
+            let last = self.last_timestamps.get(&pes.pid).copied();
+            let dts = pes.dts.map(|dts| unwrap_timestamp(last, dts));
+            let pts = pes.pts.map(|pts| unwrap_timestamp(dts.or(last), pts));
+            if let Some(dts) = dts {
+                self.last_timestamps.insert(pes.pid, dts);
+            }
+
+            match parser {
+                ElementaryParser::H264(parser) => {
+                    // Un frame video senza timestamp non può essere
+                    // posizionato, quindi viene scartato.
+                    if let (Some(pts), Some(dts)) = (pts, dts) {
+                        if let Some(sample) = parser.push(&pes.data, pts, dts) {
+                            self.pending.push_back((pes.pid, sample));
+                        }
+                    }
+                }
+                ElementaryParser::Aac(parser) => {
+                    for sample in parser.push(&pes.data, pts) {
+                        self.pending.push_back((pes.pid, sample));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Controlla che `path` sia un MPEG-TS, un MP4, un Matroska o un MP3 scritto
+/// per intero: pacchetti TS interi, box MP4 che arrivano fino alla fine con
+/// `moov` e `mdat`, un segmento Matroska lungo quanto il file o frame MP3
+/// interi. Gli altri formati non si possono verificare e sono un errore.
+pub fn verify(path: &Path) -> Result<(), Error> {
+    let len = std::fs::metadata(path)?.len();
+    let mut input = BufReader::new(File::open(path)?);
+    let mut head = [0; 64];
+    let read = input.read(&mut head)?;
+    let head = &head[..read];
+    input.seek(SeekFrom::Start(0))?;
+
+    if head.get(4..8) == Some(b"ftyp") {
+        let boxes = mp4::top_level_boxes(&mut input, len)?;
+        let has = |kind: &[u8; 4]| boxes.iter().any(|(k, _, _)| k == kind);
+        let end = boxes.last().map(|&(_, pos, size)| pos + size);
+        if !has(b"moov") || !has(b"mdat") {
+            return Err(InvalidStreamError("missing moov or mdat box").into());
+        }
+        if end != Some(len) {
+            return Err(InvalidStreamError("trailing data after the last box").into());
+        }
+        Ok(())
+    } else if head.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        match mkv::declared_len(head) {
+            Some(declared) if declared == len => Ok(()),
+            _ => Err(InvalidStreamError("Matroska segment does not match the file").into()),
+        }
+    } else if head.first() == Some(&ts::SYNC_BYTE) {
+        ts::check_packets(input, len)
+    } else if head.starts_with(b"ID3") || AudioFormat::detect(head) == Some(AudioFormat::Mp3) {
+        mp3::check_frames(&mut input, len)
+    } else {
+        Err(InvalidStreamError("unknown container").into())
+    }
+}
+
+/// Legge l'inizio di un file e ritorna le tracce supportate.
+pub fn probe(input: Input) -> Result<Vec<TrackInfo>, Error> {
+    let mut demuxer = Demuxer::open(input)?;
+
+    for _ in 0..MAX_PROBE_SAMPLES {
+        if demuxer.next_sample()?.is_none() {
+            break;
+        }
+        let supported = demuxer
+            .source
+            .streams()
+            .iter()
+            .filter(|stream| {
+                stream.stream_type == ts::STREAM_TYPE_H264
+                    || stream.stream_type == ts::STREAM_TYPE_AAC_ADTS
+            })
+            .count();
+        if supported > 0 && demuxer.tracks()?.len() == supported {
+            break;
+        }
+    }
+
+    let tracks = demuxer.tracks()?;
+    if tracks.is_empty() {
+        return Err(Error::from(InvalidStreamError(
+            "no H.264 or AAC streams found",
+        )));
+    }
+    Ok(tracks)
+}
+
+/// Scrive nel MP4 le tracce dei file, solo quelle audio se `audio_only`.
+fn write_mp4(mut writer: mp4::Mp4Writer, inputs: &[Input], audio_only: bool) -> Result<(), Error> {
+    for &input in inputs {
+        let track_ids: HashMap<u16, usize> = probe(input)?
+            .into_iter()
+            .filter(|track| !audio_only || !track.is_video())
+            .map(|track| (track.id, writer.add_track(track)))
+            .collect();
+
+        let mut demuxer = Demuxer::open(input)?;
+        while let Some((id, sample)) = demuxer.next_sample()? {
+            if let Some(&track) = track_ids.get(&id) {
+                writer.write_sample(track, &sample)?;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// Converte uno o più file MPEG-TS in un unico MP4 con tutte le loro tracce.
+/// I file devono condividere la stessa linea temporale, come le varianti e
+/// le tracce audio alternative di una playlist HLS.
+pub fn ts_to_mp4(inputs: &[Input], output: &Path) -> Result<(), Error> {
+    write_mp4(mp4::Mp4Writer::create(output)?, inputs, false)
+}
+
+/// Salva le tracce audio di uno o più file (MPEG-TS o audio packed) in un
+/// M4A, scartando il video, con i `metadata` e la copertina `artwork`.
+pub fn audio_to_m4a(
+    inputs: &[Input],
+    metadata: &Metadata,
+    artwork: Option<&Artwork>,
+    output: &Path,
+) -> Result<(), Error> {
+    let mut writer = mp4::Mp4Writer::create_m4a(output)?;
+    writer.set_tags(metadata, artwork);
+    write_mp4(writer, inputs, true)
+}
+
+/// Scrive i `metadata` e la copertina `artwork` in un file MP3 o MP4
+/// esistente, sostituendo i tag che aveva.
+pub fn tag_audio(
+    path: &Path,
+    format: AudioFormat,
+    metadata: &Metadata,
+    artwork: Option<&Artwork>,
+) -> Result<(), Error> {
+    match format {
+        AudioFormat::Mp3 => id3::tag_file(path, metadata, artwork),
+        AudioFormat::Mp4 => mp4::tag_file(path, metadata, artwork),
+        AudioFormat::Adts => Err(Error::from(InvalidStreamError(
+            "ADTS streams have no tags, save them as M4A",
+        ))),
+    }
+}
+
+/// Converte uno o più file MPEG-TS nel contenitore `container`, `mp4` o
+/// `mkv`, con la copertina `artwork`. I sottotitoli e i metadati finiscono
+/// solo nel Matroska, e nel MP4 solo se c'è una copertina.
+pub fn convert(
+    inputs: &[Input],
+    container: &str,
+    subtitles: &[SubtitleTrack],
+    metadata: &Metadata,
+    artwork: Option<&Artwork>,
+    output: &Path,
+) -> Result<(), Error> {
+    match container {
+        "mp4" if artwork.is_some() => {
+            let mut writer = mp4::Mp4Writer::create(output)?;
+            writer.set_tags(metadata, artwork);
+            write_mp4(writer, inputs, false)
+        }
+        "mp4" => ts_to_mp4(inputs, output),
+        "mkv" => ts_to_mkv(inputs, subtitles, metadata, artwork, output),
+        _ => Err(Error::from(UnsupportedContainerError(
+            container.to_string(),
+        ))),
+    }
+}
+
+/// Converte uno o più file MPEG-TS in un unico Matroska con tutte le loro
+/// tracce, i sottotitoli `subtitles` come tracce di testo, i `metadata`
+/// come tag e la copertina `artwork` come allegato.
+pub fn ts_to_mkv(
+    inputs: &[Input],
+    subtitles: &[SubtitleTrack],
+    metadata: &Metadata,
+    artwork: Option<&Artwork>,
+    output: &Path,
+) -> Result<(), Error> {
+    let mut tracks = Vec::new();
+    let mut demuxers = Vec::new();
+    for &input in inputs {
+        let input_tracks = probe(input)?;
+        let ids: HashMap<u16, usize> = input_tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (track.id, tracks.len() + i))
+            .collect();
+        tracks.extend(input_tracks);
+        demuxers.push((Demuxer::open(input)?, ids, None));
+    }
+
+    let mut writer = mkv::MkvWriter::create(output, &tracks, subtitles, metadata, artwork)?;
+
+    // I sample dei vari file vengono intercalati in ordine di DTS, così che
+    // ogni cluster contenga frame vicini nel tempo.
+    loop {
+        let mut next: Option<(usize, u64)> = None;
+        for (i, (demuxer, ids, pending)) in demuxers.iter_mut().enumerate() {
+            if pending.is_none() {
+                *pending = loop {
+                    match demuxer.next_sample()? {
+                        Some((id, sample)) => {
+                            if let Some(&track) = ids.get(&id) {
+                                break Some((track, sample));
+                            }
+                        }
+                        None => break None,
+                    }
+                };
+            }
+            if let Some((track, sample)) = pending {
+                let time = writer.time_ms(*track, sample.dts);
+                if next.is_none_or(|(_, best)| time < best) {
+                    next = Some((i, time));
+                }
+            }
+        }
+
+        match next {
+            Some((i, _)) => {
+                let (track, sample) = demuxers[i].2.take().unwrap();
+                writer.write_sample(track, &sample)?;
+            }
+            None => break,
+        }
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    const VIDEO_PID: u16 = 0x101;
+    const AUDIO_PID: u16 = 0x102;
+
+    /// Pacchetto TS con `payload`, completato con lo stuffing
+    /// dell'adaptation field.
+    fn packet(pid: u16, payload: &[u8]) -> Vec<u8> {
+        let stuffing = 183 - payload.len();
+        let mut packet = vec![
+            0x47,
+            0x40 | (pid >> 8) as u8,
+            pid as u8,
+            0x30,
+            stuffing as u8,
+        ];
+        if stuffing > 0 {
+            packet.push(0);
+            packet.resize(5 + stuffing, 0xff);
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// Sezione PSI con pointer field, senza CRC valido.
+    fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = body.len() + 4;
+        let mut section = vec![0, table_id, 0xb0 | (len >> 8) as u8, len as u8];
+        section.extend_from_slice(body);
+        section.extend_from_slice(&[0; 4]);
+        section
+    }
+
+    fn timestamp(prefix: u8, ts: u64) -> [u8; 5] {
+        [
+            (prefix << 4) | ((ts >> 29) as u8 & 0x0e) | 1,
+            (ts >> 22) as u8,
+            (ts >> 14) as u8 | 1,
+            (ts >> 7) as u8,
+            (ts << 1) as u8 | 1,
+        ]
+    }
+
+    fn pes(stream_id: u8, pts: u64, dts: u64, data: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, stream_id, 0, 0, 0x80, 0xc0, 10];
+        pes.extend_from_slice(&timestamp(3, pts));
+        pes.extend_from_slice(&timestamp(1, dts));
+        pes.extend_from_slice(data);
+        pes
+    }
+
+    /// Box `kind` dentro `data`, cercati anche nei box contenitore.
+    fn find_boxes<'a>(data: &'a [u8], kind: &[u8; 4], found: &mut Vec<&'a [u8]>) {
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let mut size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let mut header = 8;
+            if size == 1 {
+                size = u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()) as usize;
+                header = 16;
+            }
+            let body = &data[pos + header..pos + size];
+            match &data[pos + 4..pos + 8] {
+                name if name == kind => found.push(body),
+                b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" => find_boxes(body, kind, found),
+                _ => {}
+            }
+            pos += size;
+        }
+    }
+
+    #[test]
+    fn test() {
+        let mut pmt = vec![0, 1, 0xc1, 0, 0, 0xe1, 0x01, 0xf0, 0];
+        pmt.extend_from_slice(&[ts::STREAM_TYPE_H264, 0xe1, 0x01, 0xf0, 0]);
+        pmt.extend_from_slice(&[ts::STREAM_TYPE_AAC_ADTS, 0xe1, 0x02, 0xf0, 0]);
+
+        let idr = [
+            0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x1e, 0xed, 0x00, 0xa0, 0x0b,
+            0x72, 0, 0, 1, 0x68, 0xce, 0x38, 0x80, 0, 0, 1, 0x65, 0x88, 0x84,
+        ];
+        let adts = [0xff, 0xf1, 0x4c, 0x80, 0x01, 0x5f, 0xfc, 1, 2, 3];
+
+        let mut stream = packet(0, &section(0x00, &[0, 1, 0xc1, 0, 0, 0, 1, 0xe1, 0x00]));
+        stream.extend(packet(0x100, &section(0x02, &pmt)));
+        stream.extend(packet(VIDEO_PID, &pes(0xe0, 3003, 0, &idr)));
+        stream.extend(packet(AUDIO_PID, &pes(0xc0, 0, 0, &adts)));
+        stream.extend(packet(
+            VIDEO_PID,
+            &pes(0xe0, 6006, 3003, &[0, 0, 1, 0x41, 0x9a]),
+        ));
+        stream.extend(packet(AUDIO_PID, &pes(0xc0, 1920, 1920, &adts)));
+
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("raiplay-dl-remux-{}.ts", std::process::id()));
+        let output = input.with_extension("mp4");
+        std::fs::write(&input, &stream).unwrap();
+
+        let tracks = probe(Input::from(input.as_path())).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert!(tracks[0].is_video());
+        ts_to_mp4(&[Input::from(input.as_path())], &output).unwrap();
+        let mp4 = std::fs::read(&output).unwrap();
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        let mut moov = Vec::new();
+        find_boxes(&mp4, b"moov", &mut moov);
+        assert_eq!(moov.len(), 1);
+        let mut stco = Vec::new();
+        find_boxes(&mp4, b"stco", &mut stco);
+        assert_eq!(stco.len(), 2);
+
+        // Il primo chunk di ogni traccia punta ai suoi sample nel `mdat`.
+        let mut chunks: Vec<&[u8]> = stco
+            .iter()
+            .map(|stco| {
+                let offset = u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize;
+                &mp4[offset..offset + 3]
+            })
+            .collect();
+        chunks.sort();
+        assert_eq!(chunks, [&[0, 0, 0][..], &[1, 2, 3][..]]);
+    }
+}
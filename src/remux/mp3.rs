@@ -0,0 +1,112 @@
+#![warn(clippy::all)]
+
+//! Controllo dei frame MPEG audio Layer III di un file MP3.
+
+use super::{id3, InvalidStreamError};
+use crate::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Bitrate in kbit/s del Layer III per MPEG-1, indicizzati dall'header.
+const BITRATES_V1: [u32; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+
+/// Bitrate in kbit/s del Layer III per MPEG-2 e MPEG-2.5.
+const BITRATES_V2: [u32; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+
+/// Frequenze di campionamento di MPEG-1; dimezzate per MPEG-2 e divise per
+/// quattro per MPEG-2.5.
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Lunghezza del tag ID3v1 che può chiudere il file.
+const ID3V1_LEN: u64 = 128;
+
+/// Lunghezza del frame con l'header `h`, `None` se non è un header Layer
+/// III valido. I frame a bitrate libero non sono supportati.
+fn frame_len(h: [u8; 4]) -> Option<u64> {
+    if h[0] != 0xff || h[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    // 3 è MPEG-1, 2 MPEG-2, 0 MPEG-2.5; il Layer III ha codice 1.
+    let version = (h[1] >> 3) & 0x03;
+    let layer = (h[1] >> 1) & 0x03;
+    if version == 1 || layer != 1 {
+        return None;
+    }
+    let bitrate_index = usize::from(h[2] >> 4);
+    let rate_index = usize::from((h[2] >> 2) & 0x03);
+    let sample_rate = *SAMPLE_RATES.get(rate_index)?
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+    let (bitrate, samples) = if version == 3 {
+        (BITRATES_V1[bitrate_index], 144)
+    } else {
+        (BITRATES_V2[bitrate_index], 72)
+    };
+    if bitrate == 0 {
+        return None;
+    }
+    let padding = u64::from((h[2] >> 1) & 0x01);
+    Some(u64::from(samples * bitrate * 1000 / sample_rate) + padding)
+}
+
+/// Controlla che `input`, lungo `len` byte, sia fatto di frame interi dopo
+/// l'eventuale tag ID3v2, con al più un tag ID3v1 alla fine.
+pub fn check_frames<R: Read + Seek>(input: &mut R, len: u64) -> Result<(), Error> {
+    let mut header = [0; 10];
+    input.seek(SeekFrom::Start(0))?;
+    let read = input.read(&mut header)?;
+    let mut pos = id3::tag_len(&header[..read]) as u64;
+    let mut frames = 0;
+    while pos < len {
+        let mut frame = [0; 4];
+        let read = (len - pos).min(4) as usize;
+        input.seek(SeekFrom::Start(pos))?;
+        input.read_exact(&mut frame[..read])?;
+        if len - pos == ID3V1_LEN && frame.starts_with(b"TAG") {
+            break;
+        }
+        let size = frame_len(frame)
+            .filter(|&size| size <= len - pos)
+            .ok_or(InvalidStreamError("invalid or truncated MP3 frame"))?;
+        pos += size;
+        frames += 1;
+    }
+    if frames == 0 {
+        return Err(InvalidStreamError("no MP3 frame").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test() {
+        // MPEG-1 Layer III, 128 kbit/s a 44100 Hz: frame da 417 byte.
+        let header = [0xff, 0xfb, 0x90, 0x00];
+        assert_eq!(frame_len(header), Some(417));
+        assert_eq!(frame_len([0xff, 0xfb, 0x92, 0x00]), Some(418));
+        assert_eq!(frame_len([0xff, 0xf1, 0x50, 0x80]), None);
+
+        let mut mp3 = b"ID3\x04\x00\x00\x00\x00\x00\x02\x00\x00".to_vec();
+        for _ in 0..2 {
+            mp3.extend_from_slice(&header);
+            mp3.resize(mp3.len() + 413, 0);
+        }
+        let check = |data: &[u8]| check_frames(&mut Cursor::new(data), data.len() as u64);
+        assert!(check(&mp3).is_ok());
+        assert!(check(&mp3[..mp3.len() - 1]).is_err());
+        assert!(check(&mp3[..12]).is_err());
+        mp3.extend_from_slice(b"TAG");
+        mp3.resize(mp3.len() + 125, 0);
+        assert!(check(&mp3).is_ok());
+    }
+}
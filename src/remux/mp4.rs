@@ -0,0 +1,706 @@
+#![warn(clippy::all)]
+
+use super::{Artwork, Codec, InvalidStreamError, Metadata, Sample, TrackInfo};
+use crate::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Timescale di `mvhd`, `tkhd` ed edit list: millisecondi.
+const MOVIE_TIMESCALE: u64 = 1000;
+
+const IDENTITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Scrittura di interi big-endian nei box.
+trait PutBytes {
+    fn put_u8(&mut self, v: u8);
+    fn put_u16(&mut self, v: u16);
+    fn put_u32(&mut self, v: u32);
+    fn put_u64(&mut self, v: u64);
+}
+
+impl PutBytes for Vec<u8> {
+    fn put_u8(&mut self, v: u8) {
+        self.push(v);
+    }
+
+    fn put_u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn put_u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn put_u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(8 + content.len());
+    b.put_u32((8 + content.len()) as u32);
+    b.extend_from_slice(kind);
+    b.extend_from_slice(content);
+    b
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, content: &[u8]) -> Vec<u8> {
+    let mut c = Vec::with_capacity(4 + content.len());
+    c.put_u32((u32::from(version) << 24) | flags);
+    c.extend_from_slice(content);
+    mp4_box(kind, &c)
+}
+
+fn container(kind: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    mp4_box(kind, &children.concat())
+}
+
+/// Converte `value` da una timescale all'altra.
+fn rescale(value: u64, from: u64, to: u64) -> u64 {
+    (u128::from(value) * u128::from(to) / u128::from(from)) as u64
+}
+
+/// Codice lingua ISO 639-2 impacchettato come richiesto da `mdhd`.
+fn packed_language(language: Option<&str>) -> u16 {
+    let language = language
+        .filter(|lang| lang.len() == 3 && lang.bytes().all(|c| c.is_ascii_lowercase()))
+        .unwrap_or("und");
+    language
+        .bytes()
+        .fold(0, |acc, c| (acc << 5) | u16::from(c - 0x60))
+}
+
+struct SampleEntry {
+    dts: u64,
+    cts: u32,
+    size: u32,
+    keyframe: bool,
+}
+
+struct Mp4Track {
+    info: TrackInfo,
+    samples: Vec<SampleEntry>,
+    /// Offset nel file e numero di sample di ogni chunk.
+    chunks: Vec<(u64, u32)>,
+    first_dts: Option<u64>,
+    min_pts: Option<u64>,
+}
+
+impl Mp4Track {
+    /// Durata di ogni sample, ricavata dalla differenza tra i DTS. L'ultimo
+    /// sample dura quanto il precedente.
+    fn durations(&self) -> Vec<u32> {
+        let mut durations: Vec<u32> = self
+            .samples
+            .windows(2)
+            .map(|pair| (pair[1].dts - pair[0].dts) as u32)
+            .collect();
+        durations.push(durations.last().copied().unwrap_or(0));
+        durations
+    }
+
+    fn media_duration(&self) -> u64 {
+        self.durations().iter().map(|&d| u64::from(d)).sum()
+    }
+
+    /// Inizio della presentazione della traccia, in millisecondi.
+    fn start_ms(&self) -> u64 {
+        rescale(
+            self.min_pts.unwrap_or(0),
+            u64::from(self.info.timescale),
+            MOVIE_TIMESCALE,
+        )
+    }
+
+    /// Offset di composizione del primo frame mostrato, che l'edit list
+    /// deve saltare.
+    fn media_time(&self) -> u64 {
+        self.min_pts
+            .unwrap_or(0)
+            .saturating_sub(self.first_dts.unwrap_or(0))
+    }
+
+    /// Durata della traccia nella timeline del filmato, ritardo iniziale
+    /// compreso.
+    fn movie_duration(&self, movie_start_ms: u64) -> u64 {
+        let timescale = u64::from(self.info.timescale);
+        let media = self.media_duration().saturating_sub(self.media_time());
+        self.start_ms() - movie_start_ms + rescale(media, timescale, MOVIE_TIMESCALE)
+    }
+}
+
+/// Scrive un MP4 progressivo: i sample vanno direttamente nel `mdat` e il
+/// `moov` con le tabelle dei sample viene aggiunto alla fine.
+pub struct Mp4Writer {
+    out: BufWriter<File>,
+    pos: u64,
+    mdat_start: u64,
+    tracks: Vec<Mp4Track>,
+    last_track: Option<usize>,
+    /// `udta` con i metadati, se impostati.
+    udta: Option<Vec<u8>>,
+}
+
+impl Mp4Writer {
+    pub fn create(path: &Path) -> Result<Mp4Writer, Error> {
+        Mp4Writer::with_brands(path, b"isom", &[b"isom", b"iso2", b"avc1", b"mp41"])
+    }
+
+    /// Crea un file audio M4A.
+    pub fn create_m4a(path: &Path) -> Result<Mp4Writer, Error> {
+        Mp4Writer::with_brands(path, b"M4A ", &[b"M4A ", b"mp42", b"isom"])
+    }
+
+    fn with_brands(
+        path: &Path,
+        major_brand: &[u8; 4],
+        compatible_brands: &[&[u8; 4]],
+    ) -> Result<Mp4Writer, Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(major_brand);
+        ftyp.put_u32(0x200);
+        for brand in compatible_brands {
+            ftyp.extend_from_slice(*brand);
+        }
+        let ftyp = mp4_box(b"ftyp", &ftyp);
+        out.write_all(&ftyp)?;
+
+        // `mdat` con dimensione a 64 bit, aggiornata in `finish`.
+        let mut mdat = Vec::new();
+        mdat.put_u32(1);
+        mdat.extend_from_slice(b"mdat");
+        mdat.put_u64(0);
+        out.write_all(&mdat)?;
+
+        Ok(Mp4Writer {
+            out,
+            pos: (ftyp.len() + mdat.len()) as u64,
+            mdat_start: ftyp.len() as u64,
+            tracks: Vec::new(),
+            last_track: None,
+            udta: None,
+        })
+    }
+
+    /// Imposta i metadati e la copertina da salvare nel `moov`.
+    pub fn set_tags(&mut self, metadata: &Metadata, artwork: Option<&Artwork>) {
+        self.udta = Some(udta(metadata, artwork));
+    }
+
+    /// Aggiunge una traccia e ne ritorna l'indice da usare in
+    /// `write_sample`.
+    pub fn add_track(&mut self, info: TrackInfo) -> usize {
+        self.tracks.push(Mp4Track {
+            info,
+            samples: Vec::new(),
+            chunks: Vec::new(),
+            first_dts: None,
+            min_pts: None,
+        });
+        self.tracks.len() - 1
+    }
+
+    pub fn write_sample(&mut self, track: usize, sample: &Sample) -> Result<(), Error> {
+        let t = &mut self.tracks[track];
+
+        let first_dts = *t.first_dts.get_or_insert(sample.dts);
+        // I DTS devono essere crescenti per `stts`.
+        let last_dts = t.samples.last().map_or(0, |s| s.dts);
+        let dts = sample.dts.saturating_sub(first_dts).max(last_dts);
+        let pts = sample.pts.max(sample.dts);
+        t.min_pts = Some(t.min_pts.map_or(pts, |min| min.min(pts)));
+
+        t.samples.push(SampleEntry {
+            dts,
+            cts: (pts - sample.dts) as u32,
+            size: sample.data.len() as u32,
+            keyframe: sample.keyframe,
+        });
+        // I sample consecutivi della stessa traccia formano un chunk.
+        match t.chunks.last_mut() {
+            Some(chunk) if self.last_track == Some(track) => chunk.1 += 1,
+            _ => t.chunks.push((self.pos, 1)),
+        }
+
+        self.out.write_all(&sample.data)?;
+        self.pos += sample.data.len() as u64;
+        self.last_track = Some(track);
+        Ok(())
+    }
+
+    /// Completa il file scrivendo la dimensione del `mdat` e il `moov`.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.out.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.out
+            .write_all(&(self.pos - self.mdat_start).to_be_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+
+        let moov = self.moov();
+        self.out.write_all(&moov)?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn moov(&self) -> Vec<u8> {
+        let tracks: Vec<&Mp4Track> = self
+            .tracks
+            .iter()
+            .filter(|t| !t.samples.is_empty())
+            .collect();
+        let movie_start_ms = tracks.iter().map(|t| t.start_ms()).min().unwrap_or(0);
+        let duration = tracks
+            .iter()
+            .map(|t| t.movie_duration(movie_start_ms))
+            .max()
+            .unwrap_or(0);
+
+        let mut children = vec![mvhd(duration, tracks.len() as u32 + 1)];
+        for (i, track) in tracks.iter().enumerate() {
+            children.push(trak(track, i as u32 + 1, movie_start_ms));
+        }
+        children.extend(self.udta.clone());
+        container(b"moov", &children)
+    }
+}
+
+/// Voce di `ilst` con un valore di tipo `data_type` (1 testo UTF-8, 13
+/// JPEG, 14 PNG, 21 intero).
+fn ilst_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.put_u32(data_type);
+    data.put_u32(0); // locale
+    data.extend_from_slice(value);
+    container(kind, &[mp4_box(b"data", &data)])
+}
+
+/// Metadati in stile iTunes: `udta/meta/ilst`.
+fn udta(metadata: &Metadata, artwork: Option<&Artwork>) -> Vec<u8> {
+    let mut items = Vec::new();
+    let mut text = |kind: &[u8; 4], value: &Option<String>| {
+        if let Some(value) = value {
+            items.push(ilst_item(kind, 1, value.as_bytes()));
+        }
+    };
+    text(b"\xa9nam", &metadata.title);
+    text(b"\xa9alb", &metadata.show);
+    text(
+        b"\xa9ART",
+        &metadata.show.clone().or_else(|| metadata.channel.clone()),
+    );
+    text(b"tvsh", &metadata.show);
+    text(b"tvnn", &metadata.channel);
+    text(b"\xa9day", &metadata.date);
+    text(b"desc", &metadata.description);
+    if let Some(season) = metadata.season {
+        items.push(ilst_item(b"tvsn", 21, &season.to_be_bytes()));
+    }
+    if let Some(episode) = metadata.episode {
+        items.push(ilst_item(b"tves", 21, &episode.to_be_bytes()));
+        let mut trkn = vec![0, 0];
+        trkn.put_u16(episode.min(u32::from(u16::MAX)) as u16);
+        trkn.extend_from_slice(&[0; 4]);
+        items.push(ilst_item(b"trkn", 0, &trkn));
+    }
+    if let Some(artwork) = artwork {
+        let data_type = if artwork.mime == "image/png" { 14 } else { 13 };
+        items.push(ilst_item(b"covr", data_type, &artwork.data));
+    }
+
+    let mut hdlr = Vec::new();
+    hdlr.put_u32(0);
+    hdlr.extend_from_slice(b"mdir");
+    hdlr.extend_from_slice(b"appl");
+    hdlr.extend_from_slice(&[0; 9]);
+    let mut meta = Vec::new();
+    meta.put_u32(0); // version e flags
+    meta.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    meta.extend(container(b"ilst", &items));
+    container(b"udta", &[mp4_box(b"meta", &meta)])
+}
+
+/// Header di un box: tipo, offset del contenuto e dimensione totale.
+/// `None` alla fine dei dati.
+fn box_header(data: &[u8], pos: usize) -> Option<([u8; 4], usize, usize)> {
+    let header = data.get(pos..pos + 8)?;
+    let mut kind = [0; 4];
+    kind.copy_from_slice(&header[4..]);
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    match size {
+        0 => Some((kind, pos + 8, data.len() - pos)),
+        1 => {
+            let large = data.get(pos + 8..pos + 16)?;
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(large);
+            Some((kind, pos + 16, u64::from_be_bytes(bytes) as usize))
+        }
+        size if size >= 8 => Some((kind, pos + 8, size)),
+        _ => None,
+    }
+}
+
+/// Legge gli header dei box di primo livello di `input`, lungo `file_len`
+/// byte: tipo, posizione e dimensione di ciascuno. Un box che va oltre la
+/// fine del file è un errore.
+pub fn top_level_boxes<R: Read + Seek>(
+    input: &mut R,
+    file_len: u64,
+) -> Result<Vec<([u8; 4], u64, u64)>, Error> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        input.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 16];
+        let len = (file_len - pos).min(16) as usize;
+        input.read_exact(&mut header[..len])?;
+        let (kind, _, size) = box_header(&header[..len], 0)
+            .filter(|&(_, _, size)| size as u64 <= file_len - pos)
+            .ok_or(InvalidStreamError("invalid MP4 box"))?;
+        boxes.push((kind, pos, size as u64));
+        pos += size as u64;
+    }
+    Ok(boxes)
+}
+
+/// Sposta di `delta` byte gli offset dei chunk in `stco` e `co64` dentro
+/// `data`, che contiene i figli di un box.
+fn shift_chunk_offsets(data: &mut [u8], delta: i64) {
+    let mut pos = 0;
+    while let Some((kind, content, size)) = box_header(data, pos) {
+        let end = (pos + size).min(data.len());
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                shift_chunk_offsets(&mut data[content..end], delta)
+            }
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                for entry in data[(content + 8).min(end)..end].chunks_exact_mut(width) {
+                    let mut bytes = [0; 8];
+                    bytes[8 - width..].copy_from_slice(entry);
+                    let offset = (u64::from_be_bytes(bytes) as i64 + delta) as u64;
+                    entry.copy_from_slice(&offset.to_be_bytes()[8 - width..]);
+                }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+}
+
+/// Sostituisce i metadati di un MP4 esistente. Se il `moov` precede il
+/// `mdat`, gli offset dei chunk vengono spostati di quanto cambia la sua
+/// dimensione.
+pub fn tag_file(path: &Path, metadata: &Metadata, artwork: Option<&Artwork>) -> Result<(), Error> {
+    let file_len = fs::metadata(path)?.len();
+    let mut input = BufReader::new(File::open(path)?);
+
+    // Vengono letti solo gli header dei box di primo livello e il `moov`.
+    let boxes = top_level_boxes(&mut input, file_len)?;
+    let (moov_pos, moov_size) = boxes
+        .iter()
+        .find(|(kind, _, _)| kind == b"moov")
+        .map(|&(_, pos, size)| (pos, size))
+        .ok_or(InvalidStreamError("missing moov box"))?;
+
+    let mut moov = vec![0; moov_size as usize];
+    input.seek(SeekFrom::Start(moov_pos))?;
+    input.read_exact(&mut moov)?;
+    let (_, content, _) = box_header(&moov, 0).unwrap();
+    let mut children = Vec::new();
+    let mut child = content;
+    while let Some((kind, _, size)) = box_header(&moov, child) {
+        let end = (child + size).min(moov.len());
+        if &kind != b"udta" {
+            children.push(moov[child..end].to_vec());
+        }
+        child = end;
+    }
+    children.push(udta(metadata, artwork));
+    let mut new_moov = container(b"moov", &children);
+
+    let moov_before_mdat = boxes
+        .iter()
+        .any(|(kind, pos, _)| kind == b"mdat" && *pos > moov_pos);
+    if moov_before_mdat {
+        let delta = new_moov.len() as i64 - moov_size as i64;
+        shift_chunk_offsets(&mut new_moov[8..], delta);
+    }
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        input.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut input).take(moov_pos), &mut out)?;
+        out.write_all(&new_moov)?;
+        input.seek(SeekFrom::Start(moov_pos + moov_size))?;
+        io::copy(&mut input, &mut out)?;
+        out.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn mvhd(duration: u64, next_track_id: u32) -> Vec<u8> {
+    let mut c = Vec::new();
+    c.put_u32(0); // creation_time
+    c.put_u32(0); // modification_time
+    c.put_u32(MOVIE_TIMESCALE as u32);
+    c.put_u32(duration as u32);
+    c.put_u32(0x0001_0000); // rate 1.0
+    c.put_u16(0x0100); // volume 1.0
+    c.extend_from_slice(&[0; 10]);
+    for v in &IDENTITY_MATRIX {
+        c.put_u32(*v);
+    }
+    c.extend_from_slice(&[0; 24]);
+    c.put_u32(next_track_id);
+    full_box(b"mvhd", 0, 0, &c)
+}
+
+fn trak(track: &Mp4Track, track_id: u32, movie_start_ms: u64) -> Vec<u8> {
+    let timescale = u64::from(track.info.timescale);
+    let (width, height) = match track.info.codec {
+        Codec::H264 { width, height, .. } => (width, height),
+        Codec::Aac { .. } => (0, 0),
+    };
+
+    let mut tkhd = Vec::new();
+    tkhd.put_u32(0);
+    tkhd.put_u32(0);
+    tkhd.put_u32(track_id);
+    tkhd.put_u32(0);
+    tkhd.put_u32(track.movie_duration(movie_start_ms) as u32);
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.put_u16(0); // layer
+    tkhd.put_u16(0); // alternate_group
+    tkhd.put_u16(if track.info.is_video() { 0 } else { 0x0100 });
+    tkhd.put_u16(0);
+    for v in &IDENTITY_MATRIX {
+        tkhd.put_u32(*v);
+    }
+    tkhd.put_u32(u32::from(width) << 16);
+    tkhd.put_u32(u32::from(height) << 16);
+    // Traccia abilitata e usata nella presentazione.
+    let tkhd = full_box(b"tkhd", 0, 0x03, &tkhd);
+
+    // Edit list: un eventuale ritardo rispetto alle altre tracce, poi il
+    // media a partire dal primo frame da mostrare.
+    let mut elst = Vec::new();
+    let delay_ms = track.start_ms() - movie_start_ms;
+    elst.put_u32(if delay_ms > 0 { 2 } else { 1 });
+    if delay_ms > 0 {
+        elst.put_u32(delay_ms as u32);
+        elst.put_u32(u32::MAX); // media_time -1: edit vuoto
+        elst.put_u32(0x0001_0000);
+    }
+    let media_time = track.media_time();
+    let media_ms = rescale(
+        track.media_duration().saturating_sub(media_time),
+        timescale,
+        MOVIE_TIMESCALE,
+    );
+    elst.put_u32(media_ms as u32);
+    elst.put_u32(media_time as u32);
+    elst.put_u32(0x0001_0000);
+    let edts = container(b"edts", &[full_box(b"elst", 0, 0, &elst)]);
+
+    let mut mdhd = Vec::new();
+    mdhd.put_u32(0);
+    mdhd.put_u32(0);
+    mdhd.put_u32(track.info.timescale);
+    mdhd.put_u32(track.media_duration() as u32);
+    mdhd.put_u16(packed_language(track.info.language.as_deref()));
+    mdhd.put_u16(0);
+    let mdhd = full_box(b"mdhd", 0, 0, &mdhd);
+
+    let (handler, name, media_header) = if track.info.is_video() {
+        (b"vide", "VideoHandler", full_box(b"vmhd", 0, 1, &[0; 8]))
+    } else {
+        (b"soun", "SoundHandler", full_box(b"smhd", 0, 0, &[0; 4]))
+    };
+    let mut hdlr = Vec::new();
+    hdlr.put_u32(0);
+    hdlr.extend_from_slice(handler);
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(name.as_bytes());
+    hdlr.put_u8(0);
+    let hdlr = full_box(b"hdlr", 0, 0, &hdlr);
+
+    let mut dref = Vec::new();
+    dref.put_u32(1);
+    // I dati sono nello stesso file.
+    dref.extend_from_slice(&full_box(b"url ", 0, 1, &[]));
+    let dinf = container(b"dinf", &[full_box(b"dref", 0, 0, &dref)]);
+
+    let minf = container(b"minf", &[media_header, dinf, stbl(track)]);
+    let mdia = container(b"mdia", &[mdhd, hdlr, minf]);
+    container(b"trak", &[tkhd, edts, mdia])
+}
+
+fn sample_entry(codec: &Codec) -> Vec<u8> {
+    let mut c = Vec::new();
+    c.extend_from_slice(&[0; 6]);
+    c.put_u16(1); // data_reference_index
+
+    match codec {
+        Codec::H264 {
+            avcc,
+            width,
+            height,
+        } => {
+            c.extend_from_slice(&[0; 16]);
+            c.put_u16(*width);
+            c.put_u16(*height);
+            c.put_u32(0x0048_0000); // 72 dpi
+            c.put_u32(0x0048_0000);
+            c.put_u32(0);
+            c.put_u16(1); // frame_count
+            c.extend_from_slice(&[0; 32]); // compressorname
+            c.put_u16(0x0018); // depth
+            c.put_u16(0xffff); // pre_defined = -1
+            c.extend_from_slice(&mp4_box(b"avcC", avcc));
+            mp4_box(b"avc1", &c)
+        }
+        Codec::Aac {
+            config,
+            sample_rate,
+            channels,
+        } => {
+            c.extend_from_slice(&[0; 8]);
+            c.put_u16(*channels);
+            c.put_u16(16); // samplesize
+            c.put_u32(0);
+            c.put_u32(sample_rate << 16);
+            c.extend_from_slice(&esds(config));
+            mp4_box(b"mp4a", &c)
+        }
+    }
+}
+
+/// Descrittori MPEG-4 per l'audio AAC (ISO 14496-1).
+fn esds(config: &[u8]) -> Vec<u8> {
+    let mut decoder_specific = vec![0x05, config.len() as u8];
+    decoder_specific.extend_from_slice(config);
+
+    let mut decoder_config = vec![0x04, 13 + decoder_specific.len() as u8];
+    decoder_config.put_u8(0x40); // Audio ISO/IEC 14496-3
+    decoder_config.put_u8(0x15); // AudioStream
+    decoder_config.extend_from_slice(&[0; 3]); // bufferSizeDB
+    decoder_config.put_u32(0); // maxBitrate
+    decoder_config.put_u32(0); // avgBitrate
+    decoder_config.extend_from_slice(&decoder_specific);
+
+    let sl_config = [0x06, 0x01, 0x02];
+
+    let mut es = vec![0x03, (3 + decoder_config.len() + sl_config.len()) as u8];
+    es.put_u16(0); // ES_ID
+    es.put_u8(0);
+    es.extend_from_slice(&decoder_config);
+    es.extend_from_slice(&sl_config);
+
+    full_box(b"esds", 0, 0, &es)
+}
+
+fn stbl(track: &Mp4Track) -> Vec<u8> {
+    let mut stsd = Vec::new();
+    stsd.put_u32(1);
+    stsd.extend_from_slice(&sample_entry(&track.info.codec));
+    let mut children = vec![full_box(b"stsd", 0, 0, &stsd)];
+
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for duration in track.durations() {
+        match runs.last_mut() {
+            Some(run) if run.1 == duration => run.0 += 1,
+            _ => runs.push((1, duration)),
+        }
+    }
+    let mut stts = Vec::new();
+    stts.put_u32(runs.len() as u32);
+    for (count, delta) in runs {
+        stts.put_u32(count);
+        stts.put_u32(delta);
+    }
+    children.push(full_box(b"stts", 0, 0, &stts));
+
+    if track.samples.iter().any(|s| s.cts != 0) {
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for sample in &track.samples {
+            match runs.last_mut() {
+                Some(run) if run.1 == sample.cts => run.0 += 1,
+                _ => runs.push((1, sample.cts)),
+            }
+        }
+        let mut ctts = Vec::new();
+        ctts.put_u32(runs.len() as u32);
+        for (count, offset) in runs {
+            ctts.put_u32(count);
+            ctts.put_u32(offset);
+        }
+        children.push(full_box(b"ctts", 0, 0, &ctts));
+    }
+
+    if track.samples.iter().any(|s| !s.keyframe) {
+        let keyframes: Vec<u32> = track
+            .samples
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.keyframe)
+            .map(|(i, _)| i as u32 + 1)
+            .collect();
+        let mut stss = Vec::new();
+        stss.put_u32(keyframes.len() as u32);
+        for number in keyframes {
+            stss.put_u32(number);
+        }
+        children.push(full_box(b"stss", 0, 0, &stss));
+    }
+
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for (i, &(_, count)) in track.chunks.iter().enumerate() {
+        match runs.last() {
+            Some(run) if run.1 == count => {}
+            _ => runs.push((i as u32 + 1, count)),
+        }
+    }
+    let mut stsc = Vec::new();
+    stsc.put_u32(runs.len() as u32);
+    for (first_chunk, count) in runs {
+        stsc.put_u32(first_chunk);
+        stsc.put_u32(count);
+        stsc.put_u32(1);
+    }
+    children.push(full_box(b"stsc", 0, 0, &stsc));
+
+    let mut stsz = Vec::new();
+    stsz.put_u32(0);
+    stsz.put_u32(track.samples.len() as u32);
+    for sample in &track.samples {
+        stsz.put_u32(sample.size);
+    }
+    children.push(full_box(b"stsz", 0, 0, &stsz));
+
+    // Oltre i 4 GiB servono offset a 64 bit.
+    let mut offsets = Vec::new();
+    offsets.put_u32(track.chunks.len() as u32);
+    if track
+        .chunks
+        .iter()
+        .any(|&(offset, _)| offset > u64::from(u32::MAX))
+    {
+        for &(offset, _) in &track.chunks {
+            offsets.put_u64(offset);
+        }
+        children.push(full_box(b"co64", 0, 0, &offsets));
+    } else {
+        for &(offset, _) in &track.chunks {
+            offsets.put_u32(offset as u32);
+        }
+        children.push(full_box(b"stco", 0, 0, &offsets));
+    }
+
+    container(b"stbl", &children)
+}
@@ -0,0 +1,104 @@
+#![warn(clippy::all)]
+
+//! Audio "packed" delle playlist HLS: frame ADTS preceduti, all'inizio di
+//! ogni segmento, da un tag ID3 con il timestamp MPEG-TS del primo frame.
+
+/// Owner del frame PRIV che contiene il timestamp del segmento.
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+
+const ID3_HEADER_LEN: usize = 10;
+
+/// Vero se il file inizia come un flusso di audio packed.
+pub fn is_packed_audio(data: &[u8]) -> bool {
+    data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xff && data[1] & 0xf0 == 0xf0)
+}
+
+pub fn syncsafe(b: &[u8]) -> usize {
+    b.iter()
+        .fold(0, |acc, &b| (acc << 7) | usize::from(b & 0x7f))
+}
+
+/// Cerca il timestamp (a 90 kHz) nei frame PRIV di un tag ID3v2.4.
+fn parse_timestamp(frames: &[u8]) -> Option<u64> {
+    let mut i = 0;
+    while i + 10 <= frames.len() {
+        let id = &frames[i..i + 4];
+        let size = syncsafe(&frames[i + 4..i + 8]);
+        let body = frames.get(i + 10..i + 10 + size)?;
+        if id == b"PRIV" && body.starts_with(TIMESTAMP_OWNER) {
+            let ts = body.get(TIMESTAMP_OWNER.len()..TIMESTAMP_OWNER.len() + 8)?;
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(ts);
+            return Some(u64::from_be_bytes(bytes) & ((1 << 33) - 1));
+        }
+        if id == [0; 4] {
+            break;
+        }
+        i += 10 + size;
+    }
+    None
+}
+
+/// Divide il flusso nei blocchi di frame ADTS compresi tra i tag ID3, con il
+/// timestamp dichiarato dal tag che li precede.
+pub fn split(data: &[u8]) -> Vec<(Option<u64>, &[u8])> {
+    let mut chunks = Vec::new();
+    let mut pts = None;
+    let mut i = 0;
+
+    while i < data.len() {
+        if data[i..].starts_with(b"ID3") && data.len() >= i + ID3_HEADER_LEN {
+            let has_footer = data[i + 5] & 0x10 != 0;
+            let size = syncsafe(&data[i + 6..i + 10]);
+            let end = (i + ID3_HEADER_LEN + size).min(data.len());
+            pts = parse_timestamp(&data[i + ID3_HEADER_LEN..end]);
+            i = (end + if has_footer { ID3_HEADER_LEN } else { 0 }).min(data.len());
+            continue;
+        }
+
+        let end = data[i..]
+            .windows(3)
+            .position(|w| w == b"ID3")
+            .map_or(data.len(), |pos| i + pos.max(1));
+        chunks.push((pts.take(), &data[i..end]));
+        i = end;
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id3(timestamp: u64) -> Vec<u8> {
+        let mut priv_body = TIMESTAMP_OWNER.to_vec();
+        priv_body.extend_from_slice(&timestamp.to_be_bytes());
+        let mut frame = b"PRIV".to_vec();
+        frame.extend_from_slice(&[0, 0, 0, priv_body.len() as u8, 0, 0]);
+        frame.extend_from_slice(&priv_body);
+
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend_from_slice(&[0, 0, 0, frame.len() as u8]);
+        tag.extend_from_slice(&frame);
+        tag
+    }
+
+    #[test]
+    fn test() {
+        let frame = [0xff, 0xf1, 0x4c, 0x80, 0x01, 0x5f, 0xfc, 1, 2, 3];
+        let mut data = id3(900_000);
+        data.extend_from_slice(&frame);
+        data.extend_from_slice(&frame);
+        data.extend(id3(1_080_000));
+        data.extend_from_slice(&frame);
+
+        assert!(is_packed_audio(&data));
+        assert!(!is_packed_audio(&[0x47, 0x40, 0x00]));
+        let chunks = split(&data);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].0, Some(900_000));
+        assert_eq!(chunks[0].1.len(), 20);
+        assert_eq!(chunks[1], (Some(1_080_000), &frame[..]));
+    }
+}
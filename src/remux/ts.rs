@@ -0,0 +1,283 @@
+#![warn(clippy::all)]
+
+use super::InvalidStreamError;
+use crate::error::Error;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read};
+
+const PACKET_SIZE: usize = 188;
+pub const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+
+/// `stream_type` della PMT per H.264.
+pub const STREAM_TYPE_H264: u8 = 0x1b;
+/// `stream_type` della PMT per AAC con header ADTS.
+pub const STREAM_TYPE_AAC_ADTS: u8 = 0x0f;
+
+/// Descrittore ISO 639 della PMT, contiene la lingua dello stream.
+const ISO_639_DESCRIPTOR: u8 = 0x0a;
+
+/// Stream elementare dichiarato nella PMT.
+#[derive(Debug, Clone)]
+pub struct ElementaryStream {
+    pub stream_type: u8,
+    pub language: Option<String>,
+}
+
+/// Pacchetto PES completo di uno stream elementare. I timestamp sono quelli
+/// a 33 bit del PES, a 90 kHz.
+#[derive(Debug)]
+pub struct Pes {
+    pub pid: u16,
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/// Lettore di un flusso MPEG-TS che ricompone i pacchetti PES degli stream
+/// elementari dichiarati nella PMT.
+pub struct TsReader<R: Read> {
+    input: R,
+    pmt_pids: Vec<u16>,
+    streams: HashMap<u16, ElementaryStream>,
+    buffers: HashMap<u16, Vec<u8>>,
+    eof: bool,
+}
+
+impl<R: Read> TsReader<R> {
+    pub fn new(input: R) -> TsReader<R> {
+        TsReader {
+            input,
+            pmt_pids: Vec::new(),
+            streams: HashMap::new(),
+            buffers: HashMap::new(),
+            eof: false,
+        }
+    }
+
+    /// Stream elementari trovati finora nella PMT, indicizzati per PID.
+    pub fn streams(&self) -> &HashMap<u16, ElementaryStream> {
+        &self.streams
+    }
+
+    /// Ritorna il prossimo PES completo, `None` a fine file.
+    pub fn next_pes(&mut self) -> Result<Option<Pes>, Error> {
+        loop {
+            if self.eof {
+                // A fine file svuota i PES rimasti in sospeso.
+                let pid = match self.buffers.keys().next() {
+                    Some(&pid) => pid,
+                    None => return Ok(None),
+                };
+                let data = self.buffers.remove(&pid).unwrap();
+                match parse_pes(pid, data) {
+                    Some(pes) => return Ok(Some(pes)),
+                    None => continue,
+                }
+            }
+
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => {
+                    self.eof = true;
+                    continue;
+                }
+            };
+            if let Some(pes) = self.handle_packet(&packet)? {
+                return Ok(Some(pes));
+            }
+        }
+    }
+
+    /// Legge un pacchetto da 188 byte, risincronizzandosi sul sync byte se
+    /// necessario.
+    fn read_packet(&mut self) -> Result<Option<[u8; PACKET_SIZE]>, Error> {
+        let mut packet = [0; PACKET_SIZE];
+
+        loop {
+            match self.input.read_exact(&mut packet[..1]) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(Error::from(err)),
+            }
+            if packet[0] == SYNC_BYTE {
+                break;
+            }
+        }
+
+        match self.input.read_exact(&mut packet[1..]) {
+            Ok(()) => Ok(Some(packet)),
+            // Un pacchetto troncato a fine file viene ignorato.
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8; PACKET_SIZE]) -> Result<Option<Pes>, Error> {
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        if adaptation_field_control & 0x01 == 0 {
+            return Ok(None);
+        }
+        let payload_start = if adaptation_field_control & 0x02 != 0 {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        if payload_start >= PACKET_SIZE {
+            return Ok(None);
+        }
+        let payload = &packet[payload_start..];
+
+        if pid == PAT_PID {
+            if payload_unit_start {
+                self.parse_pat(payload)?;
+            }
+            return Ok(None);
+        }
+        if self.pmt_pids.contains(&pid) {
+            if payload_unit_start {
+                self.parse_pmt(payload)?;
+            }
+            return Ok(None);
+        }
+        if !self.streams.contains_key(&pid) {
+            return Ok(None);
+        }
+
+        let mut complete = None;
+        if payload_unit_start {
+            if let Some(data) = self.buffers.remove(&pid) {
+                complete = parse_pes(pid, data);
+            }
+            self.buffers.insert(pid, payload.to_vec());
+        } else if let Some(buffer) = self.buffers.get_mut(&pid) {
+            buffer.extend_from_slice(payload);
+        }
+
+        Ok(complete)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let section = psi_section(payload)?;
+        // I programmi iniziano dopo l'header di 8 byte e finiscono prima del
+        // CRC di 4 byte.
+        for program in section[8..section.len() - 4].chunks_exact(4) {
+            let number = u16::from_be_bytes([program[0], program[1]]);
+            let pid = (u16::from(program[2] & 0x1f) << 8) | u16::from(program[3]);
+            if number != 0 && !self.pmt_pids.contains(&pid) {
+                self.pmt_pids.push(pid);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let section = psi_section(payload)?;
+        let end = section.len() - 4;
+        let program_info_len = (usize::from(section[10] & 0x0f) << 8) | usize::from(section[11]);
+        let mut i = 12 + program_info_len;
+
+        while i + 5 <= end {
+            let stream_type = section[i];
+            let pid = (u16::from(section[i + 1] & 0x1f) << 8) | u16::from(section[i + 2]);
+            let es_info_len =
+                (usize::from(section[i + 3] & 0x0f) << 8) | usize::from(section[i + 4]);
+            let descriptors = &section[(i + 5).min(end)..(i + 5 + es_info_len).min(end)];
+
+            self.streams.insert(
+                pid,
+                ElementaryStream {
+                    stream_type,
+                    language: parse_language(descriptors),
+                },
+            );
+            i += 5 + es_info_len;
+        }
+        Ok(())
+    }
+}
+
+/// Controlla che `input`, lungo `len` byte, sia fatto solo di pacchetti
+/// interi, ognuno con il sync byte all'inizio.
+pub fn check_packets<R: Read>(mut input: R, len: u64) -> Result<(), Error> {
+    if len == 0 || !len.is_multiple_of(PACKET_SIZE as u64) {
+        return Err(InvalidStreamError("truncated packet").into());
+    }
+    let mut packet = [0; PACKET_SIZE];
+    for _ in 0..len / PACKET_SIZE as u64 {
+        input.read_exact(&mut packet)?;
+        if packet[0] != SYNC_BYTE {
+            return Err(InvalidStreamError("missing sync byte").into());
+        }
+    }
+    Ok(())
+}
+
+/// Ritorna la sezione PSI che inizia nel payload, saltando il pointer field.
+fn psi_section(payload: &[u8]) -> Result<&[u8], Error> {
+    let start = 1 + *payload.first().unwrap_or(&0) as usize;
+    if payload.len() < start + 3 {
+        return Err(Error::from(InvalidStreamError("truncated PSI section")));
+    }
+    let section_len =
+        (usize::from(payload[start + 1] & 0x0f) << 8) | usize::from(payload[start + 2]);
+    let end = start + 3 + section_len;
+    if end > payload.len() || section_len < 9 {
+        return Err(Error::from(InvalidStreamError("truncated PSI section")));
+    }
+    Ok(&payload[start..end])
+}
+
+fn parse_language(mut descriptors: &[u8]) -> Option<String> {
+    while descriptors.len() >= 2 {
+        let tag = descriptors[0];
+        let len = descriptors[1] as usize;
+        let body = descriptors.get(2..2 + len)?;
+        if tag == ISO_639_DESCRIPTOR && len >= 3 {
+            return std::str::from_utf8(&body[..3]).ok().map(String::from);
+        }
+        descriptors = &descriptors[2 + len..];
+    }
+    None
+}
+
+/// Legge un timestamp a 33 bit dai 5 byte del campo PTS/DTS.
+fn parse_timestamp(b: &[u8]) -> u64 {
+    (u64::from(b[0] >> 1 & 0x07) << 30)
+        | (u64::from(b[1]) << 22)
+        | (u64::from(b[2] >> 1) << 15)
+        | (u64::from(b[3]) << 7)
+        | u64::from(b[4] >> 1)
+}
+
+fn parse_pes(pid: u16, data: Vec<u8>) -> Option<Pes> {
+    if data.len() < 9 || data[..3] != [0, 0, 1] {
+        return None;
+    }
+    let pts_dts_flags = data[7] >> 6;
+    let payload_start = 9 + data[8] as usize;
+    if payload_start > data.len() {
+        return None;
+    }
+
+    let pts = if pts_dts_flags & 0x02 != 0 && data.len() >= 14 {
+        Some(parse_timestamp(&data[9..14]))
+    } else {
+        None
+    };
+    let dts = if pts_dts_flags == 0x03 && data.len() >= 19 {
+        Some(parse_timestamp(&data[14..19]))
+    } else {
+        pts
+    };
+
+    Some(Pes {
+        pid,
+        pts,
+        dts,
+        data: data[payload_start..].to_vec(),
+    })
+}
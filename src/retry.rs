@@ -0,0 +1,224 @@
+#![warn(clippy::all)]
+
+use crate::error::Error;
+use rand::Rng;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+/// Tentativi aggiuntivi per segmento se non specificato.
+pub const DEFAULT_RETRIES: u32 = 5;
+
+/// Attesa prima del primo nuovo tentativo, raddoppiata a ogni fallimento.
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+
+/// Massimo ritardo casuale aggiunto a ogni attesa.
+pub const DEFAULT_JITTER_MS: u64 = 250;
+
+/// Limite superiore al backoff esponenziale (jitter escluso).
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Il CDN ha risposto 403 o 404. Sul CDN Rai significa quasi sempre che il
+/// token contenuto nell'URL è scaduto, quindi ritentare è inutile.
+#[derive(Debug)]
+pub struct TokenExpiredError {
+    pub uri: String,
+    pub status: StatusCode,
+}
+
+impl fmt::Display for TokenExpiredError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CDN answered {} for `{}`, the token has probably expired",
+            self.status, self.uri
+        )
+    }
+}
+
+impl std::error::Error for TokenExpiredError {}
+
+/// Tutti i tentativi per una risorsa sono falliti.
+#[derive(Debug)]
+pub struct RetriesExhaustedError {
+    pub uri: String,
+    pub attempts: u32,
+    pub cause: String,
+}
+
+impl fmt::Display for RetriesExhaustedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` failed after {} attempts: {}",
+            self.uri, self.attempts, self.cause
+        )
+    }
+}
+
+impl std::error::Error for RetriesExhaustedError {}
+
+/// Quante volte e con che cadenza ritentare una richiesta fallita.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: DEFAULT_RETRIES,
+            backoff: Duration::from_millis(DEFAULT_BACKOFF_MS),
+            jitter: Duration::from_millis(DEFAULT_JITTER_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Attesa prima del tentativo `attempt` (il primo nuovo tentativo è 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1_u32 << attempt.saturating_sub(1).min(16);
+        let backoff = self
+            .backoff
+            .checked_mul(factor)
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF);
+
+        let jitter_ms = self.jitter.as_millis() as u64;
+        let jitter = if jitter_ms > 0 {
+            rand::thread_rng().gen_range(0, jitter_ms + 1)
+        } else {
+            0
+        };
+
+        backoff + Duration::from_millis(jitter)
+    }
+}
+
+enum Failure {
+    /// Errore transitorio (timeout, 5xx, connessione interrotta).
+    Retriable(String),
+    Fatal(Error),
+}
+
+fn classify(uri: &str, err: reqwest::Error) -> Failure {
+    match err.status() {
+        Some(status) if status == StatusCode::FORBIDDEN || status == StatusCode::NOT_FOUND => {
+            Failure::Fatal(Error::from(TokenExpiredError {
+                uri: uri.to_string(),
+                status,
+            }))
+        }
+        Some(status) if status.is_server_error() => Failure::Retriable(status.to_string()),
+        Some(_) => Failure::Fatal(Error::from(err)),
+        None if err.is_timeout() => Failure::Retriable(String::from("timeout")),
+        None => Failure::Retriable(err.to_string()),
+    }
+}
+
+/// Scarica `uri` ritentando gli errori transitori secondo `policy`.
+/// `on_retry` viene chiamata prima di ogni nuovo tentativo con il numero del
+/// tentativo e la causa del fallimento.
+pub async fn fetch_with_retry<F>(
+    client: &reqwest::Client,
+    uri: &str,
+    policy: &RetryPolicy,
+    on_retry: F,
+) -> Result<Vec<u8>, Error>
+where
+    F: Fn(u32, &str),
+{
+    let (_, data) = fetch_url_with_retry(client, uri, policy, on_retry).await?;
+    Ok(data)
+}
+
+/// Come [`fetch_with_retry`], ma ritorna anche l'URL finale dopo i
+/// redirect, rispetto a cui risolvere gli URI relativi di una playlist.
+pub async fn fetch_url_with_retry<F>(
+    client: &reqwest::Client,
+    uri: &str,
+    policy: &RetryPolicy,
+    on_retry: F,
+) -> Result<(reqwest::Url, Vec<u8>), Error>
+where
+    F: Fn(u32, &str),
+{
+    let mut attempt = 0;
+
+    loop {
+        let result = async {
+            let resp = client.get(uri).send().await?.error_for_status()?;
+            let url = resp.url().clone();
+            Ok::<_, reqwest::Error>((url, resp.bytes().await?))
+        }
+        .await;
+
+        let cause = match result {
+            Ok((url, data)) => return Ok((url, data.to_vec())),
+            Err(err) => match classify(uri, err) {
+                Failure::Fatal(err) => return Err(err),
+                Failure::Retriable(cause) => cause,
+            },
+        };
+
+        attempt += 1;
+        if attempt > policy.retries {
+            return Err(Error::from(RetriesExhaustedError {
+                uri: uri.to_string(),
+                attempts: attempt,
+                cause,
+            }));
+        }
+
+        on_retry(attempt, &cause);
+        tokio::time::delay_for(policy.delay(attempt)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let policy = RetryPolicy {
+            retries: 5,
+            backoff: Duration::from_millis(500),
+            jitter: Duration::from_millis(0),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_secs(1));
+        assert_eq!(policy.delay(4), Duration::from_secs(4));
+        assert_eq!(policy.delay(10), MAX_BACKOFF);
+        assert_eq!(policy.delay(u32::MAX), MAX_BACKOFF);
+
+        let policy = RetryPolicy {
+            jitter: Duration::from_millis(250),
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(2));
+            assert!(delay <= Duration::from_millis(2250));
+        }
+
+        let failure = |status: u16| {
+            let resp = http::Response::builder().status(status).body("").unwrap();
+            let err = reqwest::Response::from(resp)
+                .error_for_status()
+                .unwrap_err();
+            classify("https://cdn.rai.it/seg1.ts", err)
+        };
+        for &status in &[403, 404] {
+            assert!(matches!(
+                failure(status),
+                Failure::Fatal(Error::TokenExpired(TokenExpiredError { status: s, .. }))
+                    if s.as_u16() == status
+            ));
+        }
+        assert!(matches!(failure(503), Failure::Retriable(_)));
+        assert!(matches!(failure(400), Failure::Fatal(Error::Http { .. })));
+    }
+}
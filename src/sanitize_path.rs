@@ -1,5 +1,7 @@
 #![warn(clippy::all)]
 
+//! Pulizia dei nomi dei file dai caratteri non validi su Windows e Unix.
+
 use lazy_static::lazy_static;
 use std::path::Path;
 
@@ -24,7 +26,7 @@ lazy_static! {
     ];
 }
 
-#[allow(dead_code)]
+/// Sistema operativo di cui rispettare le regole sui nomi dei file.
 pub enum OsTarget {
     Windows,
     Unix,
@@ -36,10 +38,10 @@ fn windows(path: &str, replacement: Option<char>) -> String {
     let replacement = replacement.unwrap_or(DEFAULT_REPLACEMENT);
 
     let filename = {
-        let path_str = if path.ends_with("..") {
-            &path[..path.len() - 2]
-        } else if path.ends_with("/") {
-            &path[..path.len() - 1]
+        let path_str = if let Some(stripped) = path.strip_suffix("..") {
+            stripped
+        } else if let Some(stripped) = path.strip_suffix('/') {
+            stripped
         } else {
             path
         };
@@ -104,9 +106,18 @@ fn unix(path: &str, replacement: Option<char>) -> String {
     new_path
 }
 
+/// Rende `path` un nome di file valido per `target` (default: tutti i
+/// sistemi), sostituendo i caratteri vietati con `replacement` (default:
+/// `!`) e accorciandolo se troppo lungo.
 pub fn sanitize(path: &str, replacement: Option<char>, target: Option<OsTarget>) -> String {
+    // Il taglio va fatto al confine di un carattere, non a metà di una
+    // lettera accentata.
     let path = if path.len() > MAX_FILENAME_LENGTH {
-        &path[..=MAX_FILENAME_LENGTH]
+        let end = (0..=MAX_FILENAME_LENGTH + 1)
+            .rev()
+            .find(|&i| path.is_char_boundary(i))
+            .unwrap_or(0);
+        &path[..end]
     } else {
         path
     };
@@ -151,5 +162,10 @@ mod tests {
         for path in paths {
             let _ = sanitize(&path, None, None);
         }
+
+        let accented = "è".repeat(MAX_FILENAME_LENGTH);
+        let sanitized = sanitize(&accented, None, None);
+        assert!(sanitized.len() <= MAX_FILENAME_LENGTH + 1);
+        assert!(sanitized.chars().all(|ch| ch == 'è'));
     }
 }
@@ -0,0 +1,806 @@
+#![warn(clippy::all)]
+
+//! Download completi: da un URL di RaiPlay o di RaiPlay Sound ai file
+//! salvati, con le scelte fatte una volta sola in [`SaveOptions`]. Qui si
+//! decide cosa scaricare e come chiamare i file; i singoli passi sono negli
+//! altri moduli. L'avanzamento viene segnalato al [`ProgressReporter`], e le
+//! scelte che servono un utente sono chieste a un [`Prompt`], se c'è.
+
+use crate::api::{self, DownloadOptions, M3u8VideoVariant, RaiPlayVideoInfos};
+use crate::batch::{Outcome, Summary};
+use crate::error::Error;
+use crate::format_selector::FormatSelector;
+use crate::images::{self, ImageKind};
+use crate::live::{self, LiveOptions};
+use crate::models::video::RaiPlayVideo;
+use crate::program::{self, EpisodeFilter};
+use crate::progress::{ProgressEvent, ProgressReporter};
+use crate::template::{self, OutputTemplate};
+use crate::{archive, auth, downloader, nfo, remux, sanitize_path, scheduler, sound, subtitles};
+use chrono::Local;
+use futures::future::BoxFuture;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Cosa scaricare di ogni URL e come salvarlo.
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    /// Scarica il file MP4 invece di una variante HLS.
+    pub mp4: bool,
+    /// Salva solo il JSON con le informazioni.
+    pub infos: bool,
+    /// Salva solo la media playlist della variante.
+    pub m3u8: bool,
+    /// Salva i sottotitoli accanto al video.
+    pub subs: bool,
+    pub audio_only: bool,
+    /// Lingue delle tracce audio alternative da includere.
+    pub audio_languages: Vec<String>,
+    /// Contenitore della variante: `ts`, `mp4` o `mkv`.
+    pub container: String,
+    /// Selettore della variante, come `best[height<=720]`.
+    pub quality: Option<String>,
+    pub download_options: DownloadOptions,
+    pub live: LiveOptions,
+    /// Filtri sugli episodi dei programmi.
+    pub episodes: EpisodeFilter,
+    /// Modello per il nome dei file.
+    pub output: Option<OutputTemplate>,
+    /// Se disporre i file e salvare le schede NFO per i media server.
+    pub media_server: bool,
+    /// Se salvare le immagini del video accanto al file.
+    pub thumbnails: bool,
+    /// Immagine da incorporare come copertina.
+    pub embed_thumbnail: Option<ImageKind>,
+    /// Dimensione delle immagini, come `1920x1080`.
+    pub thumbnail_size: Option<String>,
+    /// Archivio con i video già scaricati.
+    pub archive: Option<PathBuf>,
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions {
+            mp4: false,
+            infos: false,
+            m3u8: false,
+            subs: false,
+            audio_only: false,
+            audio_languages: Vec::new(),
+            container: String::from("ts"),
+            quality: None,
+            download_options: DownloadOptions::default(),
+            live: LiveOptions::default(),
+            episodes: EpisodeFilter::default(),
+            output: None,
+            media_server: false,
+            thumbnails: false,
+            embed_thumbnail: None,
+            thumbnail_size: None,
+            archive: None,
+        }
+    }
+}
+
+/// Chiede all'utente di scegliere, quando non c'è un selettore a farlo.
+pub trait Prompt: Sync {
+    /// Ritorna l'indice della scelta tra `choices`, descritte da `title`.
+    fn choose<'a>(
+        &'a self,
+        title: &'a str,
+        choices: &'a [String],
+    ) -> BoxFuture<'a, Result<usize, Error>>;
+}
+
+/// Scarica il video, il programma, la diretta o l'audio di `url` e ne
+/// aggiunge l'esito a `summary`. Degli episodi di un programma viene
+/// aggiunto l'esito di ognuno, e uno fallito non ferma i successivi.
+/// Ritorna un errore se non è stato possibile scaricare `url`, o se è
+/// stato premuto Ctrl-C.
+pub async fn download_url(
+    url: &str,
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    if live::is_live_url(url) {
+        record_channel(url, None, options, prompt, progress).await?;
+        summary.add(url, Outcome::Downloaded);
+        return Ok(());
+    }
+
+    if sound::is_sound_program_url(url) {
+        return download_sound_program(url, options, progress, summary).await;
+    }
+
+    if sound::is_sound_url(url) {
+        let outcome = download_sound(url, options, progress).await?;
+        summary.add(url, outcome);
+        return Ok(());
+    }
+
+    if !program::is_program_url(url) {
+        let outcome = download_video(url, options, prompt, progress).await?;
+        summary.add(url, outcome);
+        return Ok(());
+    }
+
+    let episodes = program::fetch_episodes(url, &options.episodes, progress).await?;
+    if episodes.is_empty() {
+        return Err(Error::NothingToDownload(String::from(
+            "no episode matches the filters",
+        )));
+    }
+
+    // Gli episodi già scaricati si riconoscono dal `path_id`, senza bisogno
+    // di scaricarne il JSON.
+    let archive = match &options.archive {
+        Some(path) => archive::DownloadArchive::load(path)?,
+        None => archive::DownloadArchive::default(),
+    };
+    for (n, episode) in episodes.iter().enumerate() {
+        let label = episode.label();
+        if archive.contains(&episode.path_id) {
+            progress.report(ProgressEvent::AlreadyArchived { name: &label });
+            summary.add(
+                &episode.url,
+                Outcome::Skipped(String::from("già nell'archivio")),
+            );
+            continue;
+        }
+        progress.report(ProgressEvent::EpisodeStarted {
+            index: n,
+            count: episodes.len(),
+            label: &label,
+        });
+        // Con più episodi la qualità non viene chiesta per ognuno.
+        let outcome = download_video(&episode.url, options, None, progress).await;
+        add_outcome(summary, &episode.url, outcome, progress)?;
+    }
+
+    Ok(())
+}
+
+/// Aggiunge a `summary` l'esito del download di un episodio. Solo Ctrl-C
+/// ferma gli episodi successivi.
+fn add_outcome(
+    summary: &mut Summary,
+    url: &str,
+    outcome: Result<Outcome, Error>,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    match outcome {
+        Ok(outcome) => summary.add(url, outcome),
+        Err(Error::Cancelled) => return Err(Error::Cancelled),
+        Err(err) => {
+            progress.report(ProgressEvent::Failed {
+                label: url,
+                cause: &err.to_string(),
+            });
+            summary.add(url, Outcome::Failed(err));
+        }
+    }
+    Ok(())
+}
+
+/// Scarica gli episodi di un programma di RaiPlay Sound. Gli episodi non
+/// hanno un numero affidabile, quindi il filtro sugli episodi indica le
+/// posizioni nell'elenco.
+async fn download_sound_program(
+    url: &str,
+    options: &SaveOptions,
+    progress: &dyn ProgressReporter,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let ranges = options.episodes.episodes.as_ref();
+    let episodes: Vec<String> = sound::fetch_program_episodes(url, progress)
+        .await?
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| ranges.is_none_or(|ranges| ranges.contains(*i as u32 + 1)))
+        .map(|(_, url)| url)
+        .collect();
+    if episodes.is_empty() {
+        return Err(Error::NothingToDownload(String::from(
+            "no episode matches the filters",
+        )));
+    }
+
+    for (n, episode) in episodes.iter().enumerate() {
+        progress.report(ProgressEvent::EpisodeStarted {
+            index: n,
+            count: episodes.len(),
+            label: episode,
+        });
+        let outcome = download_sound(episode, options, progress).await;
+        add_outcome(summary, episode, outcome, progress)?;
+    }
+
+    Ok(())
+}
+
+/// Scarica un episodio di RaiPlay Sound come MP3 o M4A con i suoi tag,
+/// saltandolo se è già nell'archivio. I file per i media server si possono
+/// creare solo per i video.
+pub async fn download_sound(
+    url: &str,
+    options: &SaveOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<Outcome, Error> {
+    if options.media_server {
+        return Err(Error::Usage(String::from(
+            "the media server layout is only available for RaiPlay videos",
+        )));
+    }
+    options.download_options.cancel.check()?;
+    auth::refresh().await?;
+    let episode = sound::fetch_episode(url, progress).await?;
+    if let Some(path) = &options.archive {
+        let archive = archive::DownloadArchive::load(path)?;
+        if archive.contains(&episode.id) || archive.contains(&episode.path_id) {
+            progress.report(ProgressEvent::AlreadyArchived {
+                name: &episode.name,
+            });
+            return Ok(Outcome::Skipped(String::from("già nell'archivio")));
+        }
+    }
+    // Il formato si conosce solo a download finito, quindi con un modello il
+    // file viene rinominato dopo.
+    let stem = |ext: &str| -> Result<String, Error> {
+        match &options.output {
+            Some(output) => {
+                let fields = template::sound_fields(&episode, ext)?;
+                let filename = output.render_stem(&fields);
+                template::create_parent_dirs(Path::new(&filename))?;
+                Ok(filename)
+            }
+            None => Ok(sanitize_path::sanitize(&episode.name, None, None)),
+        }
+    };
+
+    if options.infos {
+        let mut file = File::create(format!("{}.json", stem("json")?))?;
+        file.write_all(serde_json::to_string_pretty(&episode)?.as_bytes())?;
+        return Ok(Outcome::Downloaded);
+    }
+
+    let filename = sanitize_path::sanitize(&episode.name, None, None);
+    let mut path =
+        sound::download_episode(&episode, &filename, &options.download_options, progress).await?;
+    if options.output.is_some() {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let target = PathBuf::from(format!("{}.{}", stem(&ext)?, ext));
+        std::fs::rename(&path, &target)?;
+        path = target;
+    }
+    progress.report(ProgressEvent::Saved { path: &path });
+
+    if let Some(archive) = &options.archive {
+        archive::record(archive, &episode.id, &episode.path_id, &path)?;
+    }
+    Ok(Outcome::Downloaded)
+}
+
+/// Sceglie la variante con il selettore di `options`, o chiedendola a
+/// `prompt` se non c'è un selettore. Senza nessuno dei due viene scelta la
+/// migliore.
+async fn choose_variant(
+    variants: &[M3u8VideoVariant],
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+) -> Result<usize, Error> {
+    let selector = match (&options.quality, prompt) {
+        (Some(quality), _) => quality
+            .parse::<FormatSelector>()
+            .map_err(|err| Error::Usage(err.to_string()))?,
+        (None, Some(prompt)) => {
+            let labels: Vec<String> = variants.iter().map(|variant| variant.label()).collect();
+            return prompt.choose("Seleziona la qualità:", &labels).await;
+        }
+        (None, None) => FormatSelector::default(),
+    };
+    match selector.select(variants) {
+        Some(i) => {
+            progress.report(ProgressEvent::VariantSelected {
+                label: &variants[i].label(),
+                audio: false,
+            });
+            Ok(i)
+        }
+        None => Err(Error::NothingToDownload(format!(
+            "no variant matches the selector `{}`",
+            options.quality.as_deref().unwrap_or("best")
+        ))),
+    }
+}
+
+/// Avvisa se nessuna traccia audio alternativa corrisponde alle lingue
+/// richieste.
+fn warn_missing_audio(languages: &[String], renditions: &[usize], progress: &dyn ProgressReporter) {
+    if !languages.is_empty() && renditions.is_empty() {
+        progress.report(ProgressEvent::Warning(&format!(
+            "Nessuna traccia audio alternativa corrisponde a {}, uso l'audio della variante",
+            languages.join(",")
+        )));
+    }
+}
+
+/// Scarica un singolo video con le opzioni `options`, saltandolo se è già
+/// nell'archivio.
+pub async fn download_video(
+    url: &str,
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+) -> Result<Outcome, Error> {
+    options.download_options.cancel.check()?;
+    auth::refresh().await?;
+    let video = api::fetch_video(url, progress).await?;
+    if let Some(path) = &options.archive {
+        let archive = archive::DownloadArchive::load(path)?;
+        if archive.contains(&video.id) || archive.contains(&video.path_id) {
+            progress.report(ProgressEvent::AlreadyArchived { name: &video.name });
+            return Ok(Outcome::Skipped(String::from("già nell'archivio")));
+        }
+    }
+
+    let (id, path_id) = (video.id.clone(), video.path_id.clone());
+    let output = save_video(url, video, options, prompt, progress).await?;
+    // Nell'archivio finiscono solo i video salvati davvero, non le dirette,
+    // le info o le sole playlist.
+    if let (Some(path), Some(output)) = (&options.archive, &output) {
+        archive::record(path, &id, &path_id, output)?;
+    }
+    Ok(Outcome::Downloaded)
+}
+
+/// Salva il video `video` della pagina `url` come richiesto da `options`.
+/// Ritorna il percorso del file con il video o l'audio, se ne è stato
+/// salvato uno.
+async fn save_video(
+    url: &str,
+    video: RaiPlayVideo,
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+) -> Result<Option<PathBuf>, Error> {
+    let cancel = &options.download_options.cancel;
+    let mp4 = options.mp4;
+    let infos = options.infos;
+    let m3u8 = options.m3u8;
+    let subs = options.subs;
+    let audio_only = options.audio_only;
+    let audio_languages = &options.audio_languages;
+    let container = options.container.as_str();
+    let download_options = &options.download_options;
+
+    let mut video_infos = api::resolve_video(url, video, progress).await?;
+    if video_infos.infos.is_live {
+        let metadata = video_infos.metadata();
+        record_live(
+            &video_infos.infos.name,
+            &video_infos.m3u8_variants,
+            metadata,
+            options,
+            prompt,
+            progress,
+        )
+        .await?;
+        return Ok(None);
+    }
+    video_infos
+        .fetch_all_segments(&download_options.retry, progress)
+        .await?;
+
+    // La variante va scelta prima di tutto il resto, perché il nome del file
+    // può contenerne i campi.
+    let (variant, ext) = if infos {
+        (None, "json")
+    } else if mp4 {
+        (None, "mp4")
+    } else if audio_only {
+        let i = video_infos
+            .audio_only_variant()
+            .ok_or_else(|| Error::Playlist(String::from("master playlist has no variants")))?;
+        (Some(i), "m4a")
+    } else {
+        let i = choose_variant(&video_infos.m3u8_variants, options, prompt, progress).await?;
+        (Some(i), if m3u8 { "m3u8" } else { container })
+    };
+    let filename = match &options.output {
+        _ if options.media_server => {
+            let filename = nfo::episode_stem(&video_infos.infos);
+            template::create_parent_dirs(Path::new(&filename))?;
+            filename
+        }
+        Some(output) => {
+            let variant = variant.map(|i| &video_infos.m3u8_variants[i]);
+            let fields = template::video_fields(&video_infos.infos, variant, ext)?;
+            let filename = output.render_stem(&fields);
+            template::create_parent_dirs(Path::new(&filename))?;
+            filename
+        }
+        None => sanitize_path::sanitize(&video_infos.infos.name, None, None),
+    };
+
+    if infos {
+        let mut file = File::create(format!("{}.json", filename))?;
+        file.write_all(serde_json::to_string_pretty(&video_infos)?.as_bytes())?;
+        return Ok(None);
+    }
+
+    // I sottotitoli servono per i file accanto al video e per le tracce del
+    // MKV.
+    let subtitle_tracks = if subs || (container == "mkv" && !mp4 && !m3u8) {
+        match subtitles::fetch_subtitle_tracks(&video_infos.infos, progress).await {
+            Ok(tracks) => tracks,
+            Err(err) => {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "Non sono riuscito a scaricare i sottotitoli: {}",
+                    err
+                )));
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    if subs {
+        if subtitle_tracks.is_empty() {
+            progress.report(ProgressEvent::Warning("Nessun sottotitolo disponibile"));
+        }
+        subtitles::save_sidecars(&subtitle_tracks, &filename, progress)?;
+    }
+
+    let thumbnail_size = options.thumbnail_size.as_deref();
+    if options.thumbnails {
+        let saved =
+            images::save_thumbnails(&video_infos.infos, &filename, thumbnail_size, progress)
+                .await?;
+        for path in &saved {
+            progress.report(ProgressEvent::Saved { path });
+        }
+    }
+
+    // Con le info si è già usciti, e tranne che per l'MP4 la variante è
+    // stata scelta.
+    let i = variant.unwrap_or_default();
+    if m3u8 && !mp4 && !audio_only {
+        video_infos.m3u8_variants[i]
+            .save_m3u8(Path::new(&format!("{}.m3u8", filename)), progress)?;
+        return Ok(None);
+    }
+
+    let artwork = match options.embed_thumbnail {
+        Some(kind) => match images::video_image_url(&video_infos.infos, kind, thumbnail_size) {
+            Some(url) => images::fetch_artwork(&url, progress).await,
+            None => {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "Il video non ha l'immagine {}",
+                    kind.name()
+                )));
+                None
+            }
+        },
+        None => None,
+    };
+    let artwork = artwork.as_ref();
+
+    let output = if mp4 {
+        // Senza un modello il file prende il nome che ha nell'URL.
+        let path = if options.output.is_some() || options.media_server {
+            let path = PathBuf::from(format!("{}.mp4", filename));
+            downloader::download_to(&video_infos.mp4_url, &path, cancel, progress).await?;
+            path
+        } else {
+            downloader::download(&video_infos.mp4_url, cancel, progress).await?
+        };
+        if artwork.is_some() {
+            remux::tag_audio(
+                &path,
+                remux::AudioFormat::Mp4,
+                &video_infos.metadata(),
+                artwork,
+            )?;
+        }
+        path
+    } else if audio_only {
+        let renditions = video_infos.select_audio(i, audio_languages);
+        warn_missing_audio(audio_languages, &renditions, progress);
+        let mut files = video_infos
+            .download_renditions(&renditions, &filename, download_options, progress)
+            .await?;
+        if files.is_empty() {
+            // L'audio è nella variante stessa, da cui verrà scartato il video.
+            let path = PathBuf::from(format!("{}.audio.ts", filename));
+            progress.report(ProgressEvent::VariantSelected {
+                label: &video_infos.m3u8_variants[i].label(),
+                audio: false,
+            });
+            video_infos.m3u8_variants[i]
+                .download_ts(&path, download_options, progress)
+                .await?;
+            files.push((path, None));
+        }
+
+        let m4a_path = PathBuf::from(format!("{}.m4a", filename));
+        progress.report(ProgressEvent::Converting { container: "M4A" });
+        let inputs: Vec<remux::Input> = files
+            .iter()
+            .map(|(path, language)| remux::Input {
+                path,
+                language: language.as_deref(),
+            })
+            .collect();
+        remux::audio_to_m4a(&inputs, &video_infos.metadata(), artwork, &m4a_path)?;
+        for (path, _) in &files {
+            std::fs::remove_file(path)?;
+        }
+        progress.report(ProgressEvent::Converted);
+        progress.report(ProgressEvent::Saved { path: &m4a_path });
+        m4a_path
+    } else {
+        save_variant(
+            &mut video_infos,
+            i,
+            &filename,
+            &subtitle_tracks,
+            artwork,
+            options,
+            progress,
+        )
+        .await?
+    };
+
+    if options.media_server {
+        nfo::write_sidecars(&video_infos.infos, &output, progress).await?;
+    }
+    Ok(Some(output))
+}
+
+/// Scarica la variante `i` con le tracce audio scelte e la converte nel
+/// contenitore richiesto, con la copertina `artwork`.
+async fn save_variant(
+    video_infos: &mut RaiPlayVideoInfos,
+    i: usize,
+    filename: &str,
+    subtitle_tracks: &[subtitles::SubtitleTrack],
+    artwork: Option<&remux::Artwork>,
+    options: &SaveOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<PathBuf, Error> {
+    let audio_languages = &options.audio_languages;
+    let download_options = &options.download_options;
+
+    let ts_path = PathBuf::from(format!("{}.ts", filename));
+    video_infos.m3u8_variants[i]
+        .download_ts(&ts_path, download_options, progress)
+        .await?;
+
+    let renditions = video_infos.select_audio(i, audio_languages);
+    warn_missing_audio(audio_languages, &renditions, progress);
+    let audio_files = video_infos
+        .download_renditions(&renditions, filename, download_options, progress)
+        .await?;
+    // Il video e le tracce audio alternative finiscono nello stesso file.
+    let inputs: Vec<remux::Input> = std::iter::once(remux::Input::from(ts_path.as_path()))
+        .chain(audio_files.iter().map(|(path, language)| remux::Input {
+            path,
+            language: language.as_deref(),
+        }))
+        .collect();
+    let converted = save_container(
+        &inputs,
+        filename,
+        &options.container,
+        subtitle_tracks,
+        &video_infos.metadata(),
+        artwork,
+        progress,
+    )?;
+
+    Ok(converted.unwrap_or(ts_path))
+}
+
+/// Converte i file `.ts` di `inputs` nel contenitore `container` e li
+/// rimuove. Ritorna il percorso del file convertito, o niente se il
+/// contenitore è `ts` e non c'era niente da fare.
+fn save_container(
+    inputs: &[remux::Input],
+    filename: &str,
+    container: &str,
+    subtitle_tracks: &[subtitles::SubtitleTrack],
+    metadata: &remux::Metadata,
+    artwork: Option<&remux::Artwork>,
+    progress: &dyn ProgressReporter,
+) -> Result<Option<PathBuf>, Error> {
+    let path = PathBuf::from(format!("{}.{}", filename, container));
+    if container != "mp4" && container != "mkv" {
+        return Ok(None);
+    }
+
+    progress.report(ProgressEvent::Converting {
+        container: &container.to_uppercase(),
+    });
+    remux::convert(inputs, container, subtitle_tracks, metadata, artwork, &path)?;
+    for input in inputs {
+        std::fs::remove_file(input.path)?;
+    }
+    progress.report(ProgressEvent::Converted);
+    progress.report(ProgressEvent::Saved { path: &path });
+    Ok(Some(path))
+}
+
+/// Registra la diretta `name` con una delle sue `variants`, fino ai limiti
+/// di `options.live`.
+async fn record_live(
+    name: &str,
+    variants: &[M3u8VideoVariant],
+    metadata: remux::Metadata,
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    if variants.is_empty() {
+        return Err(Error::Playlist(String::from("live stream has no variants")));
+    }
+    let i = choose_variant(variants, options, prompt, progress).await?;
+
+    // Il nome contiene l'ora di inizio, per non sovrascrivere le
+    // registrazioni precedenti dello stesso canale.
+    let started = Local::now();
+    let filename = sanitize_path::sanitize(
+        &format!("{} {}", name, started.format("%Y-%m-%d %H.%M")),
+        None,
+        None,
+    );
+    let ts_path = PathBuf::from(format!("{}.ts", filename));
+    let recording = live::record(
+        &variants[i].uri,
+        &ts_path,
+        &options.live,
+        &options.download_options,
+        progress,
+    )
+    .await?;
+    if recording.segments == 0 {
+        std::fs::remove_file(&ts_path)?;
+        return Err(Error::NothingToDownload(String::from(
+            "no segment was recorded",
+        )));
+    }
+
+    let metadata = remux::Metadata {
+        date: Some(started.format("%Y-%m-%d").to_string()),
+        ..metadata
+    };
+    save_container(
+        &[remux::Input::from(ts_path.as_path())],
+        &filename,
+        &options.container,
+        &[],
+        &metadata,
+        None,
+        progress,
+    )?;
+
+    Ok(())
+}
+
+/// Registra la diretta della pagina `url`, salvandola con il nome `title` o,
+/// se manca, con quello del canale.
+pub async fn record_channel(
+    url: &str,
+    title: Option<&str>,
+    options: &SaveOptions,
+    prompt: Option<&dyn Prompt>,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    options.download_options.cancel.check()?;
+    auth::refresh().await?;
+    let channel = live::fetch_channel(url, progress).await?;
+    let (variants, _) = api::fetch_master_playlist(&channel.content_url, progress).await?;
+    let name = title.unwrap_or(&channel.name);
+    let metadata = remux::Metadata {
+        title: Some(name.to_string()),
+        channel: Some(channel.name.clone()),
+        ..remux::Metadata::default()
+    };
+    record_live(name, &variants, metadata, options, prompt, progress).await
+}
+
+/// Esegue in ordine i job salvati in `jobs_path`, aspettando l'inizio di
+/// ognuno, finché non ne restano. Ogni job usa la qualità e il contenitore
+/// con cui è stato programmato.
+pub async fn run_scheduled(
+    jobs_path: &Path,
+    options: &SaveOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<(), Error> {
+    let mut announced: Option<String> = None;
+
+    loop {
+        // Il file viene riletto a ogni giro, così i job aggiunti da un altro
+        // comando mentre si aspetta vengono visti.
+        let mut store = scheduler::JobStore::load(jobs_path)?;
+        let expired = store.remove_expired(Local::now());
+        if !expired.is_empty() {
+            for job in &expired {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "La registrazione di {} è finita alle {}, la salto",
+                    job.title,
+                    job.end.format("%Y-%m-%d %H:%M")
+                )));
+            }
+            store.save(jobs_path)?;
+        }
+
+        let job = match store.next() {
+            Some(job) => job.clone(),
+            None => {
+                progress.report(ProgressEvent::NoJobs);
+                return Ok(());
+            }
+        };
+        let wait = (job.start - Local::now()).to_std().unwrap_or_default();
+        if !wait.is_zero() {
+            if announced.as_ref() != Some(&job.id) {
+                progress.report(ProgressEvent::JobWaiting {
+                    title: &job.title,
+                    start: job.start,
+                });
+                announced = Some(job.id.clone());
+            }
+            let delay = tokio::time::delay_for(wait.min(scheduler::RELOAD_INTERVAL));
+            options
+                .download_options
+                .cancel
+                .run(async {
+                    delay.await;
+                    Ok(())
+                })
+                .await?;
+            continue;
+        }
+
+        progress.report(ProgressEvent::JobStarted { title: &job.title });
+        let job_options = SaveOptions {
+            quality: job.quality.clone(),
+            container: job.container.clone(),
+            live: LiveOptions {
+                duration: None,
+                until: Some(job.end),
+            },
+            ..options.clone()
+        };
+        // Una registrazione fallita non deve far saltare le successive: il
+        // job viene tolto come quelli riusciti e l'errore solo segnalato.
+        match record_channel(
+            &job.channel_url,
+            Some(&job.title),
+            &job_options,
+            None,
+            progress,
+        )
+        .await
+        {
+            Ok(()) => {}
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(err) => progress.report(ProgressEvent::Failed {
+                label: &job.title,
+                cause: &err.to_string(),
+            }),
+        }
+
+        let mut store = scheduler::JobStore::load(jobs_path)?;
+        store.remove(&job.id);
+        store.save(jobs_path)?;
+    }
+}
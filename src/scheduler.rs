@@ -0,0 +1,342 @@
+#![warn(clippy::all)]
+
+//! Registrazioni programmate dal palinsesto dei canali: le trasmissioni
+//! scelte diventano job salvati in un file, così che sopravvivano a un
+//! riavvio, e vengono registrate con la pipeline delle dirette.
+
+use crate::error::Error;
+use crate::http;
+use crate::live::{self, InvalidTimeError};
+use crate::models::schedule;
+use crate::progress::{ProgressEvent, ProgressReporter, Resource};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Dominio rispetto a cui risolvere gli URL del palinsesto.
+const RAI_PLAY_BASE_URL: &str = "https://www.raiplay.it/";
+
+/// File dei job se non specificato.
+pub const DEFAULT_JOBS_FILE: &str = "raiplay-dl-jobs.json";
+
+/// Anticipo con cui parte la registrazione se non specificato: i programmi
+/// iniziano spesso prima dell'orario del palinsesto.
+pub const DEFAULT_PAD_BEFORE: Duration = Duration::from_secs(2 * 60);
+
+/// Margine dopo la fine prevista se non specificato.
+pub const DEFAULT_PAD_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Ogni quanto rileggere il file dei job mentre si aspetta, per accorgersi
+/// di quelli aggiunti nel frattempo.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Trasmissione del palinsesto, con gli orari locali.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Broadcast {
+    pub title: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl Broadcast {
+    pub fn label(&self) -> String {
+        format!(
+            "{}-{} {}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            self.title
+        )
+    }
+}
+
+/// Nome del canale nell'URL del palinsesto, ad esempio `rai-1` per `Rai 1`.
+fn schedule_slug(channel: &str) -> String {
+    channel
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// `None` se la durata non è rappresentabile con [`chrono::Duration`].
+fn to_chrono(duration: Duration) -> Option<chrono::Duration> {
+    chrono::Duration::try_seconds(i64::try_from(duration.as_secs()).ok()?)
+}
+
+/// Trasmissioni del palinsesto del giorno `date`. Il palinsesto prosegue
+/// dopo la mezzanotte, quindi un orario precedente a quello della
+/// trasmissione prima appartiene al giorno dopo.
+fn broadcasts(schedule: &schedule::RaiPlaySchedule, date: NaiveDate) -> Vec<Broadcast> {
+    let mut day = date;
+    let mut previous: Option<NaiveTime> = None;
+    let mut broadcasts: Vec<Broadcast> = Vec::new();
+
+    for event in &schedule.events {
+        let time = match NaiveTime::parse_from_str(event.hour.trim(), "%H:%M") {
+            Ok(time) => time,
+            Err(_) => continue,
+        };
+        if previous.is_some_and(|previous| time < previous) {
+            day = day.succ_opt().unwrap_or(day);
+        }
+        previous = Some(time);
+
+        let start = day.and_time(time);
+        let duration = live::parse_duration(&event.duration).unwrap_or_default();
+        let title = if event.episode_title.trim().is_empty() || event.episode_title == event.name {
+            event.name.trim().to_string()
+        } else {
+            format!("{} - {}", event.name.trim(), event.episode_title.trim())
+        };
+        broadcasts.push(Broadcast {
+            title,
+            start,
+            end: to_chrono(duration)
+                .and_then(|duration| start.checked_add_signed(duration))
+                .unwrap_or(start),
+        });
+    }
+
+    // Senza durata una trasmissione finisce quando inizia la successiva.
+    for i in 1..broadcasts.len() {
+        if broadcasts[i - 1].end == broadcasts[i - 1].start {
+            broadcasts[i - 1].end = broadcasts[i].start;
+        }
+    }
+    broadcasts.retain(|broadcast| broadcast.end > broadcast.start);
+    broadcasts
+}
+
+/// Scarica il palinsesto del giorno `date` del canale `channel`, come
+/// `Rai 1`.
+pub async fn fetch_schedule(
+    channel: &str,
+    date: NaiveDate,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<Broadcast>, Error> {
+    progress.report(ProgressEvent::FetchingMetadata(Resource::Schedule));
+    let url = reqwest::Url::parse(RAI_PLAY_BASE_URL)?.join(&format!(
+        "palinsesto/app/{}/{}.json",
+        schedule_slug(channel),
+        date.format("%d-%m-%Y")
+    ))?;
+    let schedule: schedule::RaiPlaySchedule = http::client()?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    progress.report(ProgressEvent::MetadataFetched(Resource::Schedule));
+    Ok(broadcasts(&schedule, date))
+}
+
+/// Trasmissioni non ancora finite a `now` che corrispondono a `query`: un
+/// orario come `21:25`, che sceglie la trasmissione in onda a quell'ora, o
+/// una parte del titolo.
+pub fn find_broadcasts<'a>(
+    broadcasts: &'a [Broadcast],
+    query: &str,
+    now: NaiveDateTime,
+) -> Vec<&'a Broadcast> {
+    let query = query.trim();
+    let time = NaiveTime::parse_from_str(query, "%H:%M").ok();
+    let query = query.to_lowercase();
+
+    broadcasts
+        .iter()
+        .filter(|broadcast| broadcast.end > now)
+        .filter(|broadcast| match time {
+            // Una trasmissione a cavallo della mezzanotte va in onda
+            // all'orario `time` del giorno in cui inizia o di quello in cui
+            // finisce.
+            Some(time) => [broadcast.start.date(), broadcast.end.date()]
+                .iter()
+                .map(|date| date.and_time(time))
+                .any(|at| broadcast.start <= at && at < broadcast.end),
+            None => broadcast.title.to_lowercase().contains(&query),
+        })
+        .collect()
+}
+
+/// Registrazione programmata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// Pagina della diretta da registrare.
+    pub channel_url: String,
+    pub title: String,
+    /// Inizio e fine della registrazione, margini compresi.
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub quality: Option<String>,
+    pub container: String,
+}
+
+impl Job {
+    pub fn new(
+        channel_url: &str,
+        broadcast: &Broadcast,
+        pad_before: Duration,
+        pad_after: Duration,
+        quality: Option<String>,
+        container: &str,
+    ) -> Result<Job, InvalidTimeError> {
+        let invalid = |pad: Duration| InvalidTimeError(format!("{}s", pad.as_secs()));
+        let start = to_chrono(pad_before)
+            .and_then(|pad| broadcast.start.checked_sub_signed(pad))
+            .ok_or_else(|| invalid(pad_before))?;
+        let end = to_chrono(pad_after)
+            .and_then(|pad| broadcast.end.checked_add_signed(pad))
+            .ok_or_else(|| invalid(pad_after))?;
+        Ok(Job {
+            id: Uuid::new_v4().to_string(),
+            channel_url: channel_url.to_string(),
+            title: broadcast.title.clone(),
+            start: live::local_time(start)?,
+            end: live::local_time(end)?,
+            quality,
+            container: container.to_string(),
+        })
+    }
+}
+
+/// Job in attesa, salvati in un file JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JobStore {
+    pub jobs: Vec<Job>,
+}
+
+impl JobStore {
+    /// Legge i job da `path`; se il file non esiste non ci sono job.
+    pub fn load(path: &Path) -> Result<JobStore, Error> {
+        if !path.exists() {
+            return Ok(JobStore::default());
+        }
+        let content = fs::read(path)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Salva i job in `path`, passando da un file temporaneo come il journal
+    /// dei download.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Aggiunge `job`, a meno che la stessa registrazione non sia già in
+    /// programma. Ritorna vero se è stato aggiunto.
+    pub fn add(&mut self, job: Job) -> bool {
+        let duplicate = self.jobs.iter().any(|other| {
+            other.channel_url == job.channel_url && other.start == job.start && other.end == job.end
+        });
+        if !duplicate {
+            self.jobs.push(job);
+        }
+        !duplicate
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.jobs.retain(|job| job.id != id);
+    }
+
+    /// Toglie e ritorna i job che sono già finiti a `now`.
+    pub fn remove_expired(&mut self, now: DateTime<Local>) -> Vec<Job> {
+        let (expired, pending) = self.jobs.drain(..).partition(|job| job.end <= now);
+        self.jobs = pending;
+        expired
+    }
+
+    /// Il prossimo job da registrare.
+    pub fn next(&self) -> Option<&Job> {
+        self.jobs.iter().min_by_key(|job| job.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(schedule_slug("Rai News 24"), "rai-news-24");
+
+        let event = |name: &str, hour: &str, duration: &str| schedule::Event {
+            name: name.to_string(),
+            episode_title: String::new(),
+            hour: hour.to_string(),
+            duration: duration.to_string(),
+            weblink: String::new(),
+        };
+        let schedule = schedule::RaiPlaySchedule {
+            channel: String::from("Rai 1"),
+            events: vec![
+                event("Tg1", "20:00", "00:30:00"),
+                event("Techetechete'", "20:30", ""),
+                event("Il Collegio", "21:25", "02:50:00"),
+                event("Tg1 Notte", "00:20", "00:20:00"),
+            ],
+        };
+        let date = NaiveDate::from_ymd_opt(2020, 3, 1).unwrap();
+        let at = |day, h, m| {
+            NaiveDate::from_ymd_opt(2020, 3, day)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let broadcasts = broadcasts(&schedule, date);
+        assert_eq!(broadcasts.len(), 4);
+        assert_eq!(broadcasts[1].end, at(1, 21, 25));
+        assert_eq!(broadcasts[2].end, at(2, 0, 15));
+        assert_eq!(broadcasts[3].start, at(2, 0, 20));
+        assert_eq!(broadcasts[2].label(), "21:25-00:15 Il Collegio");
+
+        let find = |query: &str, now| {
+            find_broadcasts(&broadcasts, query, now)
+                .iter()
+                .map(|broadcast| broadcast.title.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("tg1", at(1, 19, 0)), ["Tg1", "Tg1 Notte"]);
+        assert_eq!(find("tg1", at(1, 21, 0)), ["Tg1 Notte"]);
+        assert_eq!(find("22:00", at(1, 19, 0)), ["Il Collegio"]);
+        assert_eq!(find("21:25", at(1, 19, 0)), ["Il Collegio"]);
+        assert_eq!(find("00:05", at(1, 19, 0)), ["Il Collegio"]);
+        assert_eq!(find("00:25", at(1, 19, 0)), ["Tg1 Notte"]);
+
+        let job = Job::new(
+            "https://www.raiplay.it/dirette/rai1",
+            &broadcasts[2],
+            DEFAULT_PAD_BEFORE,
+            DEFAULT_PAD_AFTER,
+            None,
+            "mp4",
+        )
+        .unwrap();
+        assert_eq!(job.start.naive_local(), at(1, 21, 23));
+        assert_eq!(job.end.naive_local(), at(2, 0, 20));
+
+        let mut store = JobStore::default();
+        assert!(store.add(job.clone()));
+        assert!(!store.add(Job {
+            id: String::from("other"),
+            ..job.clone()
+        }));
+        assert_eq!(store.next(), Some(&job));
+        assert!(store.remove_expired(job.start).is_empty());
+        assert_eq!(store.remove_expired(job.end), vec![job]);
+        assert!(store.next().is_none());
+    }
+}
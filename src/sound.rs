@@ -0,0 +1,336 @@
+#![warn(clippy::all)]
+
+//! Podcast e programmi radio di RaiPlay Sound (`raiplaysound.it`), salvati
+//! come MP3 o M4A con i tag dell'episodio e la copertina del programma.
+
+use crate::api::{self, DownloadOptions};
+use crate::downloader;
+use crate::error::Error;
+use crate::http;
+use crate::images;
+use crate::models::sound;
+use crate::progress::{ProgressEvent, ProgressReporter, Resource};
+use crate::remux::{self, Artwork, AudioFormat, Metadata};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Dominio rispetto a cui risolvere i `path_id` e le immagini.
+const RAI_PLAY_SOUND_BASE_URL: &str = "https://www.raiplaysound.it/";
+
+/// Dimensione richiesta per le copertine, che su RaiPlay Sound sono
+/// quadrate.
+const ARTWORK_RESOLUTION: &str = "1200x1200";
+
+/// Byte letti per riconoscere il formato del file scaricato.
+const SNIFF_LEN: u64 = 64 * 1024;
+
+lazy_static! {
+    static ref EPISODE_URL_RE: Regex =
+        Regex::new(r"^https?://(?:www\.)?raiplaysound\.it/audio/\d{4}/\d{2}/[^.]+\.html$").unwrap();
+    static ref PROGRAM_URL_RE: Regex =
+        Regex::new(r"^https?://(?:www\.)?raiplaysound\.it/programmi/([^/?#.]+)").unwrap();
+}
+
+#[derive(Debug)]
+pub struct UnsupportedAudioError(pub String);
+
+impl fmt::Display for UnsupportedAudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Audio format of `{}` is not supported", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedAudioError {}
+
+/// Episodio di un podcast, con i metadati da scrivere nei tag.
+#[derive(Debug, Serialize)]
+pub struct SoundEpisode {
+    /// Identificativo dell'episodio, come `ContentItem-...`.
+    pub id: String,
+    /// Percorso del JSON dell'episodio.
+    pub path_id: String,
+    pub name: String,
+    /// Relinker del file audio.
+    pub audio_url: String,
+    pub artwork_url: Option<String>,
+    pub metadata: Metadata,
+}
+
+/// Vero se `url` è un episodio o un programma di RaiPlay Sound.
+pub fn is_sound_url(url: &str) -> bool {
+    EPISODE_URL_RE.is_match(url) || is_sound_program_url(url)
+}
+
+pub fn is_sound_program_url(url: &str) -> bool {
+    PROGRAM_URL_RE.is_match(url)
+}
+
+fn base_url() -> reqwest::Url {
+    reqwest::Url::parse(RAI_PLAY_SOUND_BASE_URL).unwrap()
+}
+
+/// URL assoluto di un'immagine, con la dimensione al posto del segnaposto
+/// `[RESOLUTION]`.
+fn image_url(path: &str) -> Option<String> {
+    images::resolve(RAI_PLAY_SOUND_BASE_URL, path, ARTWORK_RESOLUTION).map(String::from)
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+impl SoundEpisode {
+    fn from_json(episode: sound::RaiPlaySoundEpisode, path_id: String) -> SoundEpisode {
+        let podcast = &episode.podcast_info;
+        let title = non_empty(&episode.title).or_else(|| non_empty(&episode.episode_title));
+        SoundEpisode {
+            id: episode.uniquename.clone(),
+            path_id,
+            name: title.clone().unwrap_or_else(|| episode.uniquename.clone()),
+            artwork_url: image_url(&podcast.image).or_else(|| image_url(&episode.image)),
+            metadata: Metadata {
+                title,
+                show: non_empty(&podcast.title),
+                season: episode.season.trim().parse().ok(),
+                episode: episode.episode.trim().parse().ok(),
+                description: non_empty(&episode.description)
+                    .or_else(|| non_empty(&podcast.description)),
+                channel: non_empty(&podcast.channel),
+                date: api::iso_date(&episode.create_date),
+            },
+            audio_url: episode.audio.url,
+        }
+    }
+}
+
+/// Scarica le informazioni sull'episodio della pagina `url`.
+pub async fn fetch_episode(
+    url: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<SoundEpisode, Error> {
+    if !EPISODE_URL_RE.is_match(url) {
+        return Err(Error::InvalidUrl(url.to_string()));
+    }
+    let json_url = format!("{}.json", url.trim_end_matches(".html"));
+
+    progress.report(ProgressEvent::FetchingMetadata(Resource::EpisodeInfo));
+    let episode: sound::RaiPlaySoundEpisode = http::client()?
+        .get(&json_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    progress.report(ProgressEvent::MetadataFetched(Resource::EpisodeInfo));
+    let path_id = reqwest::Url::parse(&json_url)?.path().to_string();
+    Ok(SoundEpisode::from_json(episode, path_id))
+}
+
+/// URL della pagina di un episodio a partire da una card.
+fn card_url(card: &sound::Card) -> Option<String> {
+    let link = if card.weblink.is_empty() {
+        card.path_id.replace(".json", ".html")
+    } else {
+        card.weblink.clone()
+    };
+    if link.is_empty() {
+        return None;
+    }
+    base_url().join(&link).ok().map(String::from)
+}
+
+/// Scarica l'elenco degli episodi del programma `url`, nell'ordine di
+/// RaiPlay Sound.
+pub async fn fetch_program_episodes(
+    url: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<String>, Error> {
+    let name = match PROGRAM_URL_RE.captures(url) {
+        Some(caps) => caps[1].to_string(),
+        None => return Err(Error::InvalidUrl(url.to_string())),
+    };
+
+    progress.report(ProgressEvent::FetchingMetadata(Resource::Episodes));
+    let program: sound::RaiPlaySoundProgram = http::client()?
+        .get(base_url().join(&format!("programmi/{}.json", name))?)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let mut episodes: Vec<String> = Vec::new();
+    let mut add = |cards: &[sound::Card]| {
+        for url in cards.iter().filter_map(card_url) {
+            if EPISODE_URL_RE.is_match(&url) && !episodes.contains(&url) {
+                episodes.push(url);
+            }
+        }
+    };
+    for block in &program.blocks {
+        add(&block.cards);
+        for set in block.sets.iter().filter(|set| !set.path_id.is_empty()) {
+            let contents: sound::SetContents = http::client()?
+                .get(base_url().join(&set.path_id)?)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            add(&contents.cards);
+        }
+    }
+    progress.report(ProgressEvent::MetadataFetched(Resource::Episodes));
+    progress.report(ProgressEvent::EpisodesFound {
+        program: &program.title,
+        count: episodes.len(),
+    });
+    Ok(episodes)
+}
+
+/// Scarica la copertina dell'episodio. Un errore non interrompe il
+/// download dell'audio.
+async fn fetch_artwork(episode: &SoundEpisode, progress: &dyn ProgressReporter) -> Option<Artwork> {
+    let url = reqwest::Url::parse(episode.artwork_url.as_ref()?).ok()?;
+    images::fetch_artwork(&url, progress).await
+}
+
+/// Scarica l'audio dell'episodio in `<filename>.mp3` o `<filename>.m4a`,
+/// a seconda del formato servito dal relinker, e ci scrive i tag. Ritorna
+/// il percorso del file salvato.
+pub async fn download_episode(
+    episode: &SoundEpisode,
+    filename: &str,
+    options: &DownloadOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<PathBuf, Error> {
+    let artwork = fetch_artwork(episode, progress).await;
+    let artwork = artwork.as_ref();
+    let m4a_path = PathBuf::from(format!("{}.m4a", filename));
+
+    // Il relinker risponde con un redirect al file o alla playlist HLS.
+    let client = http::client()?;
+    let resp = client.head(&episode.audio_url).send().await?;
+    let media_url = resp.url().clone();
+    if api::is_geo_blocked_placeholder(&media_url) {
+        return Err(Error::GeoBlocked(episode.audio_url.clone()));
+    }
+    resp.error_for_status()?;
+    if media_url.path().ends_with(".m3u8") {
+        let (mut variants, _) = api::fetch_master_playlist(media_url.as_str(), progress).await?;
+        // Le varianti sono in ordine di qualità crescente.
+        let variant = variants
+            .last_mut()
+            .ok_or_else(|| UnsupportedAudioError(media_url.to_string()))?;
+        let ts_path = PathBuf::from(format!("{}.audio.ts", filename));
+        variant.download_ts(&ts_path, options, progress).await?;
+        remux::audio_to_m4a(
+            &[remux::Input::from(ts_path.as_path())],
+            &episode.metadata,
+            artwork,
+            &m4a_path,
+        )?;
+        fs::remove_file(&ts_path)?;
+        return Ok(m4a_path);
+    }
+
+    let part_path = PathBuf::from(format!("{}.part", filename));
+    downloader::download_to(media_url.as_str(), &part_path, &options.cancel, progress).await?;
+    let mut head = Vec::new();
+    File::open(&part_path)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)?;
+
+    let path = match AudioFormat::detect(&head) {
+        Some(AudioFormat::Adts) => {
+            remux::audio_to_m4a(
+                &[remux::Input::from(part_path.as_path())],
+                &episode.metadata,
+                artwork,
+                &m4a_path,
+            )?;
+            fs::remove_file(&part_path)?;
+            m4a_path
+        }
+        Some(format) => {
+            let path = if format == AudioFormat::Mp3 {
+                PathBuf::from(format!("{}.mp3", filename))
+            } else {
+                m4a_path
+            };
+            remux::tag_audio(&part_path, format, &episode.metadata, artwork)?;
+            fs::rename(&part_path, &path)?;
+            path
+        }
+        None => {
+            fs::remove_file(&part_path)?;
+            return Err(Error::from(UnsupportedAudioError(media_url.to_string())));
+        }
+    };
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert!(is_sound_url(
+            "https://www.raiplaysound.it/audio/2021/03/Il-ruggito-del-coniglio-del-03032021-0b4f3a2c.html"
+        ));
+        assert!(is_sound_url(
+            "https://www.raiplaysound.it/programmi/ilruggitodelconiglio"
+        ));
+        assert!(!is_sound_url("https://www.raiplay.it/programmi/ilcollegio"));
+        assert!(is_sound_program_url(
+            "https://raiplaysound.it/programmi/ilruggitodelconiglio/puntate"
+        ));
+
+        assert_eq!(
+            image_url("/dl/img/2020/09/coniglio-[RESOLUTION].jpg").as_deref(),
+            Some("https://www.raiplaysound.it/dl/img/2020/09/coniglio-1200x1200.jpg")
+        );
+        assert_eq!(image_url(" "), None);
+
+        let episode: sound::RaiPlaySoundEpisode = serde_json::from_str(
+            r#"{
+                "uniquename": "ContentItem-0b4f3a2c",
+                "title": "Il ruggito del coniglio del 03/03/2021",
+                "create_date": "03-03-2021",
+                "audio": {"url": "https://mediapolisvod.rai.it/relinker/relinkerServlet.htm?cont=1"},
+                "podcast_info": {"title": "Il ruggito del coniglio", "image": "/dl/coniglio.jpg"}
+            }"#,
+        )
+        .unwrap();
+        let path_id = "/audio/2021/03/Il-ruggito-del-coniglio-del-03032021-0b4f3a2c.json";
+        let episode = SoundEpisode::from_json(episode, path_id.to_string());
+        assert_eq!(episode.id, "ContentItem-0b4f3a2c");
+        let output: crate::template::OutputTemplate = "{metadata.show}/{id}.{ext}".parse().unwrap();
+        let fields = crate::template::sound_fields(&episode, "mp3").unwrap();
+        assert_eq!(
+            output.render_stem(&fields),
+            "Il ruggito del coniglio/ContentItem-0b4f3a2c"
+        );
+        assert_eq!(episode.name, "Il ruggito del coniglio del 03/03/2021");
+        assert_eq!(
+            episode.metadata.show.as_deref(),
+            Some("Il ruggito del coniglio")
+        );
+        assert_eq!(episode.metadata.date.as_deref(), Some("2021-03-03"));
+        assert_eq!(
+            episode.artwork_url.as_deref(),
+            Some("https://www.raiplaysound.it/dl/coniglio.jpg")
+        );
+    }
+}
@@ -0,0 +1,381 @@
+#![warn(clippy::all)]
+
+//! Download dei sottotitoli di RaiPlay e conversione tra i formati usati da
+//! Rai (EBU STL, TTML, WebVTT, SRT).
+
+mod stl;
+mod ttml;
+
+use crate::error::Error;
+use crate::http;
+use crate::models::video;
+use crate::progress::{ProgressEvent, ProgressReporter};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Dominio rispetto a cui risolvere gli URL relativi dei sottotitoli.
+const RAI_PLAY_BASE_URL: &str = "https://www.raiplay.it/";
+
+#[derive(Debug)]
+pub struct InvalidSubtitlesError(pub String);
+
+impl fmt::Display for InvalidSubtitlesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Subtitles are not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSubtitlesError {}
+
+/// Formati di sottotitoli supportati.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+    Ttml,
+    /// EBU STL (Tech 3264), il formato binario usato dalla Rai.
+    Stl,
+}
+
+impl SubtitleFormat {
+    /// Riconosce il formato da un nome (`"STL"`, `"webvtt"`, ...) o da
+    /// un'estensione.
+    pub fn from_name(name: &str) -> Option<SubtitleFormat> {
+        match name.trim().to_lowercase().as_str() {
+            "srt" | "subrip" => Some(SubtitleFormat::Srt),
+            "vtt" | "webvtt" => Some(SubtitleFormat::WebVtt),
+            "ttml" | "dfxp" | "xml" => Some(SubtitleFormat::Ttml),
+            "stl" | "ebu-stl" => Some(SubtitleFormat::Stl),
+            _ => None,
+        }
+    }
+}
+
+impl video::Subtitle {
+    /// Formato dei sottotitoli, dal campo `format` se presente, altrimenti
+    /// dall'estensione dell'URL.
+    pub fn subtitle_format(&self) -> Option<SubtitleFormat> {
+        self.format
+            .as_deref()
+            .and_then(SubtitleFormat::from_name)
+            .or_else(|| {
+                let path = self.url.split(['?', '#']).next()?;
+                SubtitleFormat::from_name(path.rsplit('.').next()?)
+            })
+    }
+}
+
+/// Singolo sottotitolo, con inizio e fine in millisecondi.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+/// Traccia di sottotitoli già scaricata e convertita.
+#[derive(Debug, Clone)]
+pub struct SubtitleTrack {
+    /// Codice ISO 639-2 della lingua.
+    pub language: String,
+    pub name: String,
+    pub cues: Vec<Cue>,
+}
+
+/// Converte il nome della lingua usato da Rai in un codice ISO 639-2.
+pub fn language_code(language: &str) -> String {
+    match language.trim().to_lowercase().as_str() {
+        "italiano" | "italian" | "it" | "ita" => String::from("ita"),
+        "inglese" | "english" | "en" | "eng" => String::from("eng"),
+        "francese" | "french" | "fr" | "fra" => String::from("fra"),
+        "tedesco" | "german" | "de" | "deu" | "ger" => String::from("deu"),
+        "spagnolo" | "spanish" | "es" | "spa" => String::from("spa"),
+        _ => String::from("und"),
+    }
+}
+
+/// Legge un timestamp SRT (`00:01:02,345`) o WebVTT (`00:01:02.345` o
+/// `01:02.345`) e lo ritorna in millisecondi.
+fn parse_timestamp(ts: &str) -> Option<u64> {
+    let ts = ts.trim().replace(',', ".");
+    let (hms, millis) = match ts.find('.') {
+        Some(i) => (&ts[..i], &ts[i + 1..]),
+        None => (&ts[..], "0"),
+    };
+
+    let mut seconds: u64 = 0;
+    for part in hms.split(':') {
+        seconds = seconds
+            .checked_mul(60)?
+            .checked_add(part.parse::<u64>().ok()?)?;
+    }
+    let millis = format!("{:0<3}", millis);
+    seconds
+        .checked_mul(1000)?
+        .checked_add(millis.get(..3)?.parse::<u64>().ok()?)
+}
+
+/// Legge i cue di un file SRT o WebVTT. I blocchi senza `-->` (header,
+/// note, stili) vengono ignorati.
+pub fn parse_cues(text: &str) -> Vec<Cue> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let timing = match lines.next() {
+            Some(timing) => timing,
+            None => continue,
+        };
+        let mut times = timing.splitn(2, "-->");
+        let start = times.next().and_then(parse_timestamp);
+        // In WebVTT le impostazioni del cue seguono il timestamp di fine.
+        let end = times
+            .next()
+            .and_then(|end| end.split_whitespace().next())
+            .and_then(parse_timestamp);
+
+        if let (Some(start), Some(end)) = (start, end) {
+            let text = lines.collect::<Vec<&str>>().join("\n");
+            if !text.trim().is_empty() {
+                cues.push(Cue { start, end, text });
+            }
+        }
+    }
+
+    cues
+}
+
+/// Legge i cue di un file di sottotitoli nel formato `format`.
+pub fn parse(format: SubtitleFormat, data: &[u8]) -> Result<Vec<Cue>, Error> {
+    match format {
+        SubtitleFormat::Srt | SubtitleFormat::WebVtt => {
+            Ok(parse_cues(&String::from_utf8_lossy(data)))
+        }
+        SubtitleFormat::Ttml => ttml::parse(&String::from_utf8_lossy(data)),
+        SubtitleFormat::Stl => stl::parse(data),
+    }
+}
+
+/// Formatta un timestamp in millisecondi come `hh:mm:ss<sep>mmm`.
+fn format_timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// Scrive i cue in formato SRT.
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_timestamp(cue.start, ','),
+            format_timestamp(cue.end, ','),
+            cue.text
+        ));
+    }
+    out
+}
+
+/// Scrive i cue in formato WebVTT.
+pub fn to_webvtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(cue.start, '.'),
+            format_timestamp(cue.end, '.'),
+            cue.text
+        ));
+    }
+    out
+}
+
+/// Sottotitoli elencati nelle info del video. Se `subtitlesArray` è vuoto
+/// viene usato il campo `subtitles`, che contiene al più un URL.
+fn subtitle_entries(video: &video::RaiPlayVideo) -> Vec<video::Subtitle> {
+    let entries: Vec<video::Subtitle> = video
+        .video
+        .subtitles_array
+        .iter()
+        .flatten()
+        .filter(|sub| !sub.url.trim().is_empty())
+        .cloned()
+        .collect();
+    if !entries.is_empty() || video.video.subtitles.trim().is_empty() {
+        return entries;
+    }
+
+    vec![video::Subtitle {
+        language: String::from("Italiano"),
+        url: video.video.subtitles.trim().to_string(),
+        format: None,
+    }]
+}
+
+/// Scarica e converte tutti i sottotitoli del video. Le tracce in un formato
+/// sconosciuto vengono saltate.
+pub async fn fetch_subtitle_tracks(
+    video: &video::RaiPlayVideo,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<SubtitleTrack>, Error> {
+    let base_url = reqwest::Url::parse(RAI_PLAY_BASE_URL)?;
+    let mut tracks = Vec::new();
+
+    for entry in subtitle_entries(video) {
+        let name = if entry.language.is_empty() {
+            "Italiano"
+        } else {
+            &entry.language
+        };
+        let format = match entry.subtitle_format() {
+            Some(format) => format,
+            None => {
+                progress.report(ProgressEvent::Warning(&format!(
+                    "Sottotitoli {} in un formato non supportato: {}",
+                    name, entry.url
+                )));
+                continue;
+            }
+        };
+
+        let url = base_url.join(&entry.url)?;
+        let data = http::client()?
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        tracks.push(SubtitleTrack {
+            language: language_code(name),
+            name: name.to_string(),
+            cues: parse(format, &data)?,
+        });
+    }
+
+    Ok(tracks)
+}
+
+/// Salva ogni traccia come SRT e WebVTT accanto al video, con nomi del tipo
+/// `<filename>.<lingua>.srt`. Ritorna i percorsi dei file creati.
+pub fn save_sidecars(
+    tracks: &[SubtitleTrack],
+    filename: &str,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut paths = Vec::new();
+
+    for track in tracks {
+        let count = seen.entry(&track.language).or_insert(0);
+        *count += 1;
+        // Più tracce nella stessa lingua vengono numerate.
+        let stem = if *count == 1 {
+            format!("{}.{}", filename, track.language)
+        } else {
+            format!("{}.{}.{}", filename, track.language, count)
+        };
+
+        for (ext, contents) in &[
+            ("srt", to_srt(&track.cues)),
+            ("vtt", to_webvtt(&track.cues)),
+        ] {
+            let path = PathBuf::from(format!("{}.{}", stem, ext));
+            fs::write(&path, contents)?;
+            progress.report(ProgressEvent::Finished { path: &path });
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let srt = "1\r\n00:00:01,500 --> 00:00:03,000\r\nCiao\r\na tutti\r\n\r\n2\r\n00:01:00,000 --> 00:01:02,250\r\nSecondo\r\n";
+        let cues = vec![
+            Cue {
+                start: 1500,
+                end: 3000,
+                text: String::from("Ciao\na tutti"),
+            },
+            Cue {
+                start: 60_000,
+                end: 62_250,
+                text: String::from("Secondo"),
+            },
+        ];
+        assert_eq!(parse_cues(srt), cues);
+        assert_eq!(parse_cues(&to_srt(&cues)), cues);
+        assert_eq!(parse_cues(&to_webvtt(&cues)), cues);
+        assert!(to_webvtt(&cues).contains("00:01:00.000 --> 00:01:02.250"));
+
+        let vtt = "WEBVTT\n\nNOTE commento\n\n01:02.5 --> 01:04.000 align:start\nTesto\n";
+        assert_eq!(
+            parse_cues(vtt),
+            vec![Cue {
+                start: 62_500,
+                end: 64_000,
+                text: String::from("Testo"),
+            }]
+        );
+
+        assert_eq!(parse_timestamp("01:00:00,250"), Some(3_600_250));
+        assert_eq!(parse_timestamp("99999999999999999:00:00"), None);
+
+        let ttml = r#"<?xml version="1.0"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttp="http://www.w3.org/ns/ttml#parameter" ttp:tickRate="10000000">
+  <body><div>
+    <p begin="00:00:01.5" end="00:00:03:15">Primo
+      <br/><span tts:fontStyle="italic">rigo &amp; co</span></p>
+    <p begin="40000000t" dur="1s">Secondo</p>
+    <p begin="99999999999999999999s" dur="1s">Fuori scala</p>
+  </div></body>
+</tt>"#;
+        assert_eq!(
+            parse(SubtitleFormat::Ttml, ttml.as_bytes()).unwrap(),
+            vec![
+                Cue {
+                    start: 1500,
+                    end: 3500,
+                    text: String::from("Primo\nrigo & co"),
+                },
+                Cue {
+                    start: 4000,
+                    end: 5000,
+                    text: String::from("Secondo"),
+                },
+            ]
+        );
+
+        let sub = video::Subtitle {
+            language: String::from("Italiano"),
+            url: String::from("/dl/video/sub_ita.STL?v=1"),
+            format: None,
+        };
+        assert_eq!(sub.subtitle_format(), Some(SubtitleFormat::Stl));
+        assert_eq!(language_code("Italiano"), "ita");
+        assert_eq!(language_code("Klingon"), "und");
+
+        let video: video::Video = serde_json::from_str(
+            r#"{"content_url": "", "duration": "", "highlights": "", "subtitles": "",
+                "subtitlesArray": [null, {"language": "Inglese"}, {"url": "/sub_ita.srt"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(video.subtitles_array.len(), 3);
+    }
+}
@@ -0,0 +1,230 @@
+#![warn(clippy::all)]
+
+//! Lettura dei sottotitoli EBU STL (EBU Tech 3264): un blocco GSI da 1024
+//! byte seguito da blocchi TTI da 128 byte.
+
+use super::{Cue, InvalidSubtitlesError};
+use crate::error::Error;
+
+const GSI_SIZE: usize = 1024;
+const TTI_SIZE: usize = 128;
+
+/// Extension Block Number dell'ultimo blocco di un sottotitolo.
+const EBN_LAST: u8 = 0xff;
+/// Extension Block Number dei blocchi con dati utente.
+const EBN_USER_DATA: u8 = 0xfe;
+
+const ITALIC_ON: u8 = 0x80;
+const ITALIC_OFF: u8 = 0x81;
+const NEWLINE: u8 = 0x8a;
+const UNUSED_SPACE: u8 = 0x8f;
+
+/// Legge un timecode `HHMMSSFF` scritto in ASCII nel blocco GSI.
+fn parse_gsi_timecode(field: &[u8], fps: u64) -> Option<u64> {
+    let field = std::str::from_utf8(field).ok()?;
+    let part = |i: usize| field.get(i..i + 2)?.parse::<u64>().ok();
+    Some(timecode_ms(part(0)?, part(2)?, part(4)?, part(6)?, fps))
+}
+
+fn timecode_ms(hours: u64, minutes: u64, seconds: u64, frames: u64, fps: u64) -> u64 {
+    ((hours * 60 + minutes) * 60 + seconds) * 1000 + frames * 1000 / fps
+}
+
+/// Compone una lettera con il diacritico ISO 6937 che la precede.
+fn compose(diacritic: u8, base: char) -> Option<char> {
+    let (plain, composed) = match diacritic {
+        0xc1 => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+        0xc2 => ("aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+        0xc3 => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        0xc4 => ("anoANO", "ãñõÃÑÕ"),
+        0xc8 => ("aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+        0xcb => ("cC", "çÇ"),
+        _ => return None,
+    };
+    let i = plain.chars().position(|c| c == base)?;
+    composed.chars().nth(i)
+}
+
+/// Caratteri della metà alta di ISO 6937 diversi dai diacritici.
+fn iso6937_char(b: u8) -> Option<char> {
+    let c = match b {
+        0xa1 => '¡',
+        0xa2 => '¢',
+        0xa3 => '£',
+        0xa4 => '$',
+        0xa5 => '¥',
+        0xa7 => '§',
+        0xa9 => '‘',
+        0xaa => '“',
+        0xab => '«',
+        0xb0 => '°',
+        0xb9 => '’',
+        0xba => '”',
+        0xbb => '»',
+        0xbc => '¼',
+        0xbd => '½',
+        0xbe => '¾',
+        0xbf => '¿',
+        0xd0 => '―',
+        0xe1 => 'Æ',
+        0xe9 => 'Ø',
+        0xea => 'Œ',
+        0xf1 => 'æ',
+        0xf9 => 'ø',
+        0xfa => 'œ',
+        0xfb => 'ß',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Decodifica il Text Field di un blocco TTI. I codici di controllo del
+/// teletext (colori, box) vengono ignorati, il corsivo diventa `<i>`.
+fn decode_text(field: &[u8]) -> String {
+    let mut text = String::new();
+    let mut italic = false;
+    let mut bytes = field.iter().copied();
+
+    while let Some(b) = bytes.next() {
+        match b {
+            UNUSED_SPACE => break,
+            NEWLINE => text.push('\n'),
+            ITALIC_ON if !italic => {
+                text.push_str("<i>");
+                italic = true;
+            }
+            ITALIC_OFF if italic => {
+                text.push_str("</i>");
+                italic = false;
+            }
+            0x20..=0x7e => text.push(b as char),
+            0xc1..=0xcf => {
+                if let Some(base) = bytes.next() {
+                    let base = base as char;
+                    text.push(compose(b, base).unwrap_or(base));
+                }
+            }
+            _ => text.extend(iso6937_char(b)),
+        }
+    }
+    if italic {
+        text.push_str("</i>");
+    }
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Legge i cue di un file EBU STL. I timecode sono resi relativi all'inizio
+/// del programma dichiarato nel blocco GSI.
+pub fn parse(data: &[u8]) -> Result<Vec<Cue>, Error> {
+    if data.len() < GSI_SIZE || &data[3..6] != b"STL" {
+        return Err(Error::from(InvalidSubtitlesError(String::from(
+            "missing EBU STL GSI block",
+        ))));
+    }
+
+    // Il Disk Format Code è `STL25.01` o `STL30.01`.
+    let fps = match &data[6..8] {
+        b"30" => 30,
+        _ => 25,
+    };
+    let programme_start = parse_gsi_timecode(&data[256..264], fps).unwrap_or(0);
+
+    let mut cues = Vec::new();
+    let mut pending: Option<(u64, u64, String)> = None;
+
+    for block in data[GSI_SIZE..].chunks_exact(TTI_SIZE) {
+        let ebn = block[3];
+        let comment = block[15] == 1;
+        if ebn == EBN_USER_DATA || comment {
+            continue;
+        }
+
+        let tc = |i: usize| {
+            let tc = &block[i..i + 4];
+            timecode_ms(
+                u64::from(tc[0]),
+                u64::from(tc[1]),
+                u64::from(tc[2]),
+                u64::from(tc[3]),
+                fps,
+            )
+        };
+        let text = decode_text(&block[16..]);
+        // I blocchi di estensione continuano il testo del blocco precedente.
+        match pending.as_mut() {
+            Some((_, _, pending_text)) => {
+                if !text.is_empty() {
+                    pending_text.push('\n');
+                    pending_text.push_str(&text);
+                }
+            }
+            None => pending = Some((tc(5), tc(9), text)),
+        }
+
+        if ebn == EBN_LAST {
+            let (start, end, text) = pending.take().unwrap();
+            if !text.is_empty() && end > start {
+                cues.push(Cue {
+                    start: start.saturating_sub(programme_start),
+                    end: end.saturating_sub(programme_start),
+                    text,
+                });
+            }
+        }
+    }
+
+    Ok(cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tti(ebn: u8, tci: [u8; 4], tco: [u8; 4], text: &[u8]) -> Vec<u8> {
+        let mut block = vec![0, 1, 0, ebn, 0];
+        block.extend_from_slice(&tci);
+        block.extend_from_slice(&tco);
+        block.extend_from_slice(&[20, 2, 0]);
+        block.extend_from_slice(text);
+        block.resize(TTI_SIZE, UNUSED_SPACE);
+        block
+    }
+
+    #[test]
+    fn test() {
+        let mut data = vec![b' '; GSI_SIZE];
+        data[..11].copy_from_slice(b"850STL25.01");
+        data[256..264].copy_from_slice(b"10000000");
+
+        data.extend(tti(
+            EBN_LAST,
+            [10, 0, 1, 0],
+            [10, 0, 3, 12],
+            b"  Perch\xc2e  \x8a\x80ciao\x81",
+        ));
+        data.extend(tti(0, [10, 0, 4, 0], [10, 0, 5, 0], b"prima"));
+        data.extend(tti(EBN_LAST, [0; 4], [0; 4], b"seconda"));
+
+        assert_eq!(
+            parse(&data).unwrap(),
+            vec![
+                Cue {
+                    start: 1000,
+                    end: 3480,
+                    text: String::from("Perché\n<i>ciao</i>"),
+                },
+                Cue {
+                    start: 4000,
+                    end: 5000,
+                    text: String::from("prima\nseconda"),
+                },
+            ]
+        );
+        assert!(parse(b"WEBVTT").is_err());
+    }
+}
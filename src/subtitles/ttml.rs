@@ -0,0 +1,178 @@
+#![warn(clippy::all)]
+
+//! Lettura dei sottotitoli TTML (e DFXP). Vengono considerati solo gli
+//! elementi `<p>` con i loro tempi; gli stili sono ignorati.
+
+use super::{Cue, InvalidSubtitlesError};
+use crate::error::Error;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+const DEFAULT_FRAME_RATE: f64 = 30.0;
+const DEFAULT_TICK_RATE: f64 = 1.0;
+
+lazy_static! {
+    static ref PARAGRAPH_RE: Regex =
+        Regex::new(r"(?s)<(?:\w+:)?p\b([^>]*)>(.*?)</(?:\w+:)?p>").unwrap();
+    static ref ATTRIBUTE_RE: Regex = Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).unwrap();
+    static ref BREAK_RE: Regex = Regex::new(r"<(?:\w+:)?br\b[^>]*>").unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"<[^>]*>").unwrap();
+    static ref WHITESPACE_RE: Regex = Regex::new(r"\s+").unwrap();
+    static ref CLOCK_TIME_RE: Regex =
+        Regex::new(r"^(\d+):(\d{2}):(\d{2})(?:(\.\d+)|:(\d+(?:\.\d+)?))?$").unwrap();
+    static ref OFFSET_TIME_RE: Regex = Regex::new(r"^(\d+(?:\.\d+)?)(h|ms|m|s|f|t)$").unwrap();
+}
+
+/// Parametri del documento usati per interpretare i tempi.
+struct TimeBase {
+    frame_rate: f64,
+    tick_rate: f64,
+}
+
+impl TimeBase {
+    fn from_root(document: &str) -> TimeBase {
+        let root = document
+            .find("<tt")
+            .and_then(|start| Some(&document[start..start + document[start..].find('>')?]))
+            .unwrap_or("");
+        let attribute = |name: &str| {
+            ATTRIBUTE_RE
+                .captures_iter(root)
+                .find(|cap| cap[1].ends_with(name))
+                .and_then(|cap| cap[2].trim().parse::<f64>().ok())
+                .filter(|rate| *rate > 0.0)
+        };
+
+        TimeBase {
+            frame_rate: attribute("frameRate").unwrap_or(DEFAULT_FRAME_RATE),
+            tick_rate: attribute("tickRate").unwrap_or(DEFAULT_TICK_RATE),
+        }
+    }
+
+    /// Legge un'espressione di tempo TTML e la ritorna in millisecondi.
+    fn parse(&self, time: &str) -> Option<u64> {
+        let time = time.trim();
+        let seconds = if let Some(cap) = CLOCK_TIME_RE.captures(time) {
+            let part = |i: usize| cap[i].parse::<f64>().unwrap_or(0.0);
+            let fraction = cap
+                .get(4)
+                .map(|m| format!("0{}", m.as_str()).parse::<f64>().unwrap_or(0.0))
+                .or_else(|| {
+                    let frames = cap.get(5)?.as_str().parse::<f64>().ok()?;
+                    Some(frames / self.frame_rate)
+                })
+                .unwrap_or(0.0);
+            part(1) * 3600.0 + part(2) * 60.0 + part(3) + fraction
+        } else {
+            let cap = OFFSET_TIME_RE.captures(time)?;
+            let value = cap[1].parse::<f64>().ok()?;
+            match &cap[2] {
+                "h" => value * 3600.0,
+                "m" => value * 60.0,
+                "s" => value,
+                "ms" => value / 1000.0,
+                "f" => value / self.frame_rate,
+                _ => value / self.tick_rate,
+            }
+        };
+        Some((seconds * 1000.0).round() as u64)
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Testo di un paragrafo: gli spazi XML vengono compressi, `<br/>` diventa
+/// un a capo e gli altri tag vengono rimossi.
+fn paragraph_text(content: &str) -> String {
+    let content = WHITESPACE_RE.replace_all(content, " ");
+    let content = BREAK_RE.replace_all(&content, "\n");
+    let content = TAG_RE.replace_all(&content, "");
+    decode_entities(&content)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Legge i cue di un documento TTML.
+pub fn parse(document: &str) -> Result<Vec<Cue>, Error> {
+    if !document.contains("<tt") {
+        return Err(Error::from(InvalidSubtitlesError(String::from(
+            "missing TTML root element",
+        ))));
+    }
+    let time_base = TimeBase::from_root(document);
+    let mut cues = Vec::new();
+
+    for paragraph in PARAGRAPH_RE.captures_iter(document) {
+        let mut begin = None;
+        let mut end = None;
+        let mut dur = None;
+        for attribute in ATTRIBUTE_RE.captures_iter(&paragraph[1]) {
+            match &attribute[1] {
+                "begin" => begin = time_base.parse(&attribute[2]),
+                "end" => end = time_base.parse(&attribute[2]),
+                "dur" => dur = time_base.parse(&attribute[2]),
+                _ => {}
+            }
+        }
+
+        let start = match begin {
+            Some(begin) => begin,
+            None => continue,
+        };
+        let end = match end.or_else(|| dur.and_then(|dur| start.checked_add(dur))) {
+            Some(end) => end,
+            None => continue,
+        };
+        let text = paragraph_text(&paragraph[2]);
+        if !text.is_empty() && end > start {
+            cues.push(Cue { start, end, text });
+        }
+    }
+
+    Ok(cues)
+}
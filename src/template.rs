@@ -0,0 +1,312 @@
+#![warn(clippy::all)]
+
+//! Modelli per il nome dei file scaricati, ad esempio
+//! `{program_info.name}/S{season:02}E{episode:02} - {episode_title}.{ext}`.
+//!
+//! Fra graffe si può indicare qualsiasi campo di `RaiPlayVideo`, anche
+//! annidato come `program_info.name`, della variante scelta come
+//! `variant.resolution.height` o l'estensione `ext`. Dopo i due punti si può
+//! indicare la larghezza minima dei numeri, con `0` davanti per riempirla di
+//! zeri. I campi che mancano diventano `NA`, `{{` e `}}` sono graffe
+//! letterali. Ogni `/` del modello separa una directory, e ogni componente
+//! del percorso passa da [`sanitize_path::sanitize`].
+//!
+//! Per gli episodi di RaiPlay Sound i campi sono invece quelli di
+//! [`SoundEpisode`], come `name` o `metadata.show`.
+
+use crate::api::M3u8VideoVariant;
+use crate::error::Error;
+use crate::models::video::RaiPlayVideo;
+use crate::sanitize_path;
+use crate::sound::SoundEpisode;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Valore dei campi che mancano.
+const MISSING: &str = "NA";
+
+#[derive(Debug)]
+pub struct InvalidTemplateError(pub String);
+
+impl fmt::Display for InvalidTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output template is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTemplateError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field {
+        path: Vec<String>,
+        width: usize,
+        zero: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate {
+    absolute: bool,
+    /// Componenti del percorso, separati dalle `/` del modello.
+    components: Vec<Vec<Part>>,
+}
+
+fn parse_field(spec: &str) -> Option<Part> {
+    let (name, format) = match spec.find(':') {
+        Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+        None => (spec, None),
+    };
+    let path: Vec<String> = name.split('.').map(String::from).collect();
+    let valid_name = |part: &String| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+    };
+    if !path.iter().all(valid_name) {
+        return None;
+    }
+
+    let (width, zero) = match format {
+        Some(format) => (format.parse().ok()?, format.starts_with('0')),
+        None => (0, false),
+    };
+    Some(Part::Field { path, width, zero })
+}
+
+impl FromStr for OutputTemplate {
+    type Err = InvalidTemplateError;
+
+    fn from_str(s: &str) -> Result<OutputTemplate, InvalidTemplateError> {
+        let invalid = |cause: &str| InvalidTemplateError(format!("{} in `{}`", cause, s));
+        let mut components = vec![Vec::new()];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    let mut closed = false;
+                    for ch in chars.by_ref() {
+                        if ch == '}' {
+                            closed = true;
+                            break;
+                        }
+                        spec.push(ch);
+                    }
+                    if !closed {
+                        return Err(invalid("unmatched `{`"));
+                    }
+                    let field = parse_field(&spec)
+                        .ok_or_else(|| invalid(&format!("field `{{{}}}` is not valid", spec)))?;
+                    let component = components.last_mut().unwrap();
+                    if !literal.is_empty() {
+                        component.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    component.push(field);
+                }
+                '}' => return Err(invalid("unmatched `}`")),
+                '/' => {
+                    let component = components.last_mut().unwrap();
+                    if !literal.is_empty() {
+                        component.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    components.push(Vec::new());
+                }
+                ch => literal.push(ch),
+            }
+        }
+        if !literal.is_empty() {
+            components.last_mut().unwrap().push(Part::Literal(literal));
+        }
+
+        let absolute = s.starts_with('/');
+        components.retain(|component| !component.is_empty());
+        if components.is_empty() {
+            return Err(invalid("no file name"));
+        }
+        Ok(OutputTemplate {
+            absolute,
+            components,
+        })
+    }
+}
+
+fn lookup<'a>(fields: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(fields, |value, key| value.get(key))
+}
+
+fn format_value(value: Option<&Value>, width: usize, zero: bool) -> String {
+    let text = match value {
+        Some(Value::String(s)) if !s.is_empty() => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::Bool(b)) => b.to_string(),
+        _ => return MISSING.to_string(),
+    };
+    if width == 0 {
+        return text;
+    }
+    // RaiPlay mette anche i numeri come stagione ed episodio in stringhe.
+    match text.trim().parse::<i64>() {
+        Ok(n) if zero => format!("{:0width$}", n, width = width),
+        Ok(n) => format!("{:width$}", n, width = width),
+        Err(_) => text,
+    }
+}
+
+impl OutputTemplate {
+    /// Compone il percorso con i campi `fields`, come quelli di
+    /// [`video_fields`].
+    pub fn render(&self, fields: &Value) -> String {
+        let components: Vec<String> = self
+            .components
+            .iter()
+            .map(|parts| {
+                let only_literals = parts.iter().all(|part| matches!(part, Part::Literal(_)));
+                let component: String = parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Literal(literal) => literal.clone(),
+                        Part::Field { path, width, zero } => {
+                            format_value(lookup(fields, path), *width, *zero)
+                        }
+                    })
+                    .collect();
+                // `.` e `..` scritti nel modello si riferiscono alle
+                // directory, mentre da un campo non devono uscire.
+                if !component.chars().all(|ch| ch == '.') {
+                    sanitize_path::sanitize(&component, None, None)
+                } else if only_literals {
+                    component
+                } else {
+                    component.replace('.', "!")
+                }
+            })
+            .collect();
+
+        let path = components.join("/");
+        if self.absolute {
+            format!("/{}", path)
+        } else {
+            path
+        }
+    }
+
+    /// Come [`OutputTemplate::render`], ma senza l'estensione `ext` finale:
+    /// il risultato è la base a cui aggiungere le estensioni del video e dei
+    /// file accanto, come i sottotitoli.
+    pub fn render_stem(&self, fields: &Value) -> String {
+        let path = self.render(fields);
+        let ext = fields
+            .get("ext")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        match path.strip_suffix(&format!(".{}", ext)) {
+            Some(stem) if !ext.is_empty() => stem.to_string(),
+            _ => path,
+        }
+    }
+}
+
+/// Campi per i modelli: quelli di `video`, la variante scelta sotto
+/// `variant`, con anche la sua etichetta in `variant.label`, e l'estensione
+/// del file in `ext`.
+pub fn video_fields(
+    video: &RaiPlayVideo,
+    variant: Option<&M3u8VideoVariant>,
+    ext: &str,
+) -> Result<Value, Error> {
+    let mut fields = serde_json::to_value(video)?;
+    if let Some(variant) = variant {
+        let mut variant_fields = serde_json::to_value(variant)?;
+        if let Some(object) = variant_fields.as_object_mut() {
+            object.remove("segments");
+            object.insert(String::from("label"), Value::from(variant.label()));
+        }
+        fields["variant"] = variant_fields;
+    }
+    fields["ext"] = Value::from(ext);
+    Ok(fields)
+}
+
+/// Campi per i modelli di un episodio di RaiPlay Sound: quelli di
+/// `episode`, con i tag sotto `metadata`, e l'estensione del file in `ext`.
+pub fn sound_fields(episode: &SoundEpisode, ext: &str) -> Result<Value, Error> {
+    let mut fields = serde_json::to_value(episode)?;
+    fields["ext"] = Value::from(ext);
+    Ok(fields)
+}
+
+/// Crea le directory che mancano per salvare il file `path`.
+pub fn create_parent_dirs(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Ok(fs::create_dir_all(parent)?),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test() {
+        let fields = json!({
+            "name": "Il Collegio 4 - Episodio 2",
+            "season": "4",
+            "episode": "2",
+            "episode_title": "Regole: niente cellulari?",
+            "program_info": {"name": "Il Collegio"},
+            "variant": {"resolution": {"width": 1280, "height": 720}},
+            "ext": "mp4",
+        });
+
+        let template: OutputTemplate =
+            "{program_info.name}/S{season:02}E{episode:02} - {episode_title}.{ext}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.render(&fields),
+            "Il Collegio/S04E02 - Regole! niente cellulari!.mp4"
+        );
+        assert_eq!(
+            template.render_stem(&fields),
+            "Il Collegio/S04E02 - Regole! niente cellulari!"
+        );
+
+        let template: OutputTemplate =
+            "../{{raiplay}}/{name} {variant.resolution.height}p {director}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.render(&fields),
+            "../{raiplay}/Il Collegio 4 - Episodio 2 720p NA"
+        );
+
+        let fields = json!({"episode_title": "007", "season": "+5", "ext": "mp4"});
+        let template: OutputTemplate = "{episode_title} {season} {season:02}.{ext}"
+            .parse()
+            .unwrap();
+        assert_eq!(template.render(&fields), "007 +5 05.mp4");
+
+        assert!("{name".parse::<OutputTemplate>().is_err());
+        assert!("{name}}".parse::<OutputTemplate>().is_err());
+        assert!("{season:xx}".parse::<OutputTemplate>().is_err());
+        assert!("{}".parse::<OutputTemplate>().is_err());
+    }
+}